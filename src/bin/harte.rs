@@ -0,0 +1,57 @@
+use rsto6502::conformance::{HarteCase, HarteMatrix};
+use rsto6502::Variant;
+use std::path::PathBuf;
+use std::{env, fs, process};
+
+// harte FILE_OR_DIR... runs SingleStepTests JSON files against the NMOS 6502
+// and prints which opcodes pass
+fn main() {
+    let mut paths = Vec::new();
+    for arg in env::args().skip(1) {
+        let path = PathBuf::from(arg);
+        if path.is_dir() {
+            let mut files: Vec<_> = fs::read_dir(&path)
+                .unwrap()
+                .map(|entry| entry.unwrap().path())
+                .filter(|file| file.extension().is_some_and(|ext| ext == "json"))
+                .collect();
+            files.sort();
+            paths.extend(files);
+        } else {
+            paths.push(path);
+        }
+    }
+    if paths.is_empty() {
+        eprintln!("usage: harte FILE_OR_DIR...");
+        process::exit(2);
+    }
+
+    let mut matrix = HarteMatrix::default();
+    for path in paths {
+        let text = fs::read_to_string(&path).unwrap();
+        match HarteCase::parse_file(&text) {
+            Ok(cases) => matrix.run(&cases, Variant::Nmos6502),
+            Err(err) => {
+                eprintln!("{}: {}", path.display(), err);
+                process::exit(1);
+            }
+        }
+    }
+
+    print!("{}", matrix);
+    for (opcode, result) in matrix.failures() {
+        if let Some((name, reason)) = &result.first_failure {
+            println!(
+                "{:02x}: {} of {} failed, first \"{}\": {}",
+                opcode,
+                result.failed,
+                result.failed + result.passed,
+                name,
+                reason
+            );
+        }
+    }
+    if !matrix.all_passed() {
+        process::exit(1);
+    }
+}
@@ -1,5 +1,6 @@
-use rsto6502::{nintendo, Memory};
-use std::{env, fs};
+use rsto6502::{nintendo, GdbServer, Memory, TraceFormat, TraceLogger};
+use std::io;
+use std::{env, fs, process};
 
 fn main() {
     let args: Vec<_> = env::args().collect();
@@ -8,17 +9,92 @@ fn main() {
 
     let rom_bytes = fs::read(filename).unwrap();
 
-    let mut nes = nintendo::Nes::new(&rom_bytes);
+    let mut nes = match nintendo::Nes::new(&rom_bytes) {
+        Ok(nes) => nes,
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+    };
 
-    nes.cpu.core.pc = 0xc000;
-    nes.cpu.core.f.i = true;
-    nes.cpu.cycles = 7;
+    nintendo::start_nestest(&mut nes);
+
+    // nintendo ROM --gdb PORT waits for GDB instead of tracing
+    if args.get(2).map(String::as_str) == Some("--gdb") {
+        let port = args
+            .get(3)
+            .and_then(|port| port.parse().ok())
+            .unwrap_or(1234);
+        eprintln!("waiting for GDB on port {}", port);
+        let result = GdbServer::accept(port).and_then(|mut server| server.serve(&mut nes.cpu));
+        if let Err(err) = result {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+        return;
+    }
+
+    // nintendo ROM --compare LOG checks the trace against a reference log
+    // such as nestest.log
+    if args.get(2).map(String::as_str) == Some("--compare") {
+        let Some(log) = args.get(3).map(|path| fs::read_to_string(path).unwrap()) else {
+            eprintln!("usage: nintendo ROM --compare LOG");
+            process::exit(2);
+        };
+        match nintendo::compare_nestest(&mut nes, &log) {
+            Ok(None) => println!("{} lines match", log.lines().count()),
+            Ok(Some(mismatch)) => {
+                print!("{}", mismatch);
+                process::exit(1);
+            }
+            Err(err) => {
+                eprintln!("{}", err);
+                process::exit(1);
+            }
+        }
+        return;
+    }
+
+    // --format mesen or fceux picks another trace layout
+    let format = match args.iter().position(|arg| arg == "--format") {
+        None => TraceFormat::Nestest,
+        Some(i) => match args.get(i + 1).map(String::as_str) {
+            Some("nestest") => TraceFormat::Nestest,
+            Some("mesen") => TraceFormat::Mesen,
+            Some("fceux") => TraceFormat::Fceux,
+            _ => {
+                eprintln!("usage: nintendo ROM --format nestest|mesen|fceux");
+                process::exit(2);
+            }
+        },
+    };
+    let mut logger = TraceLogger::new(io::stdout().lock(), format);
+
+    // --cdl FILE saves an FCEUX code/data log of the run
+    let cdl_path = args
+        .iter()
+        .position(|arg| arg == "--cdl")
+        .and_then(|i| args.get(i + 1));
+    if cdl_path.is_some() {
+        nes.enable_cdl();
+    }
 
     while nes.cpu.memory.read(0x02) == 0 && nes.cpu.memory.read(0x03) == 0 {
-        let old_pc = nes.cpu.core.pc;
-        let old_core_spec = format!("{}", nes.cpu);
-        nes.cpu.emulate_instruction();
-        println!("{:04X}  {}", old_pc, old_core_spec);
+        if let Err(err) = nes.trace(&mut logger) {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+        if let Err(err) = nes.step() {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+    }
+
+    if let (Some(path), Some(cdl)) = (cdl_path, nes.cdl()) {
+        if let Err(err) = fs::write(path, cdl.to_fceux()) {
+            eprintln!("{}: {}", path, err);
+            process::exit(1);
+        }
     }
 
     eprintln!(
@@ -0,0 +1,54 @@
+use rsto6502::TraceDiff;
+use std::{env, fs, process};
+
+const USAGE: &str = "usage: tracediff [--ignore FIELD,...] [--context N] LEFT RIGHT
+fields: pc bytes a x y p sp cycles scanline dot frame ppu p0-p7";
+
+// tracediff LEFT RIGHT compares two trace logs in any of the nestest, Mesen
+// or FCEUX layouts and shows where they first disagree
+fn main() {
+    let mut diff = TraceDiff::default();
+    let mut paths = Vec::new();
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--ignore" => {
+                let fields = args.next().unwrap_or_default();
+                for field in fields.split(',') {
+                    if !diff.ignore(field) {
+                        eprintln!("unknown field \"{}\"\n{}", field, USAGE);
+                        process::exit(2);
+                    }
+                }
+            }
+            "--context" => match args.next().and_then(|lines| lines.parse().ok()) {
+                Some(lines) => diff.context = lines,
+                None => {
+                    eprintln!("{}", USAGE);
+                    process::exit(2);
+                }
+            },
+            _ => paths.push(arg),
+        }
+    }
+    let [left, right] = paths.as_slice() else {
+        eprintln!("{}", USAGE);
+        process::exit(2);
+    };
+
+    let read = |path: &String| match fs::read_to_string(path) {
+        Ok(log) => log,
+        Err(err) => {
+            eprintln!("{}: {}", path, err);
+            process::exit(2);
+        }
+    };
+    let comparison = diff.compare(&read(left), &read(right));
+    match comparison.divergence {
+        None => println!("{} instructions match", comparison.compared),
+        Some(divergence) => {
+            print!("{}", divergence);
+            process::exit(1);
+        }
+    }
+}
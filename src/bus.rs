@@ -0,0 +1,27 @@
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AccessKind {
+    OpcodeFetch,
+    OperandFetch,
+    Read,
+    Write,
+    // Accesses the hardware makes while it is busy with something else; the
+    // value read is thrown away
+    DummyRead,
+    // The unmodified value written back by NMOS read-modify-write
+    // instructions
+    DummyWrite,
+}
+
+impl AccessKind {
+    pub fn is_write(&self) -> bool {
+        matches!(self, AccessKind::Write | AccessKind::DummyWrite)
+    }
+}
+
+// One bus cycle: every cycle of a 6502 instruction either reads or writes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BusAccess {
+    pub addr: u16,
+    pub value: u8,
+    pub kind: AccessKind,
+}
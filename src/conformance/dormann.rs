@@ -0,0 +1,356 @@
+use std::collections::VecDeque;
+use std::fmt::Display;
+
+use crate::error::Error;
+use crate::memory::{Memory, RandomAccessMemory};
+use crate::processor::Processor;
+use crate::step::StepKind;
+use crate::variant::Variant;
+
+// The interrupt test drives IRQ and NMI by writing to a feedback port
+const IRQ_BIT: u8 = 0x01;
+const NMI_BIT: u8 = 0x02;
+
+// One of Klaus Dormann's test programs, with where it lives in memory and how
+// to tell whether it passed. Every failed check traps by jumping or branching
+// to itself, and so does the end of a passing run.
+#[derive(Clone, Debug)]
+pub struct DormannTest {
+    pub image: Vec<u8>,
+    pub load_addr: u16,
+    pub start: u16,
+    // Where a passing run traps. The decimal test ends in the same place
+    // either way, so it has none and uses error_addr instead.
+    pub success: Option<u16>,
+    // Where the test keeps the number of the check that is running
+    pub test_number_addr: Option<u16>,
+    // Non-zero when the decimal test has found a wrong result
+    pub error_addr: Option<u16>,
+    // Where the decimal test finishes. The run stops before the instruction
+    // there, as it's the 65C02's STP, which NMOS parts run as DCP.
+    pub end: Option<u16>,
+    // The interrupt test's feedback port: bit 0 drives IRQ, bit 1 NMI
+    pub interrupt_port: Option<u16>,
+    pub variant: Variant,
+    // How many of the last instructions to keep for the report
+    pub trace_length: usize,
+    pub max_cycles: usize,
+}
+
+impl DormannTest {
+    // An image that fills the address space is loaded at 0, anything
+    // smaller at the start address. The success address depends on the
+    // options the test was assembled with and comes from its listing; it is
+    // 0x3469 for the prebuilt 6502_functional_test.bin.
+    pub fn functional(image: Vec<u8>, success: u16) -> Self {
+        Self::new(image, 0x0400, Some(success), Some(0x0200))
+    }
+
+    // The prebuilt 6502_interrupt_test.bin passes at 0x06f5
+    pub fn interrupt(image: Vec<u8>, success: u16) -> Self {
+        Self {
+            interrupt_port: Some(0xbffc),
+            ..Self::new(image, 0x0400, Some(success), Some(0x0200))
+        }
+    }
+
+    // `end` is the address of DONE, which comes from the listing like the
+    // functional test's success address
+    pub fn decimal(image: Vec<u8>, variant: Variant, end: u16) -> Self {
+        Self {
+            error_addr: Some(0x000b),
+            end: Some(end),
+            variant,
+            ..Self::new(image, 0x0200, None, None)
+        }
+    }
+
+    fn new(image: Vec<u8>, start: u16, success: Option<u16>, test_number: Option<u16>) -> Self {
+        let load_addr = if image.len() >= 0x10000 { 0 } else { start };
+        Self {
+            image,
+            load_addr,
+            start,
+            success,
+            test_number_addr: test_number,
+            error_addr: None,
+            end: None,
+            interrupt_port: None,
+            variant: Variant::Nmos6502,
+            trace_length: 20,
+            max_cycles: 200_000_000,
+        }
+    }
+
+    pub fn run(&self) -> DormannReport {
+        let mut memory = RandomAccessMemory::new(0x10000);
+        for (i, byte) in self.image.iter().take(0x10000).enumerate() {
+            memory.write(self.load_addr.wrapping_add(i as u16), *byte);
+        }
+        let mut cpu = Processor::with_variant(memory, self.variant);
+        cpu.core.pc = self.start;
+
+        let mut trace = VecDeque::with_capacity(self.trace_length);
+        let mut instructions = 0;
+        let outcome = loop {
+            if cpu.cycles >= self.max_cycles {
+                break DormannOutcome::TimedOut;
+            }
+            if self.end == Some(cpu.core.pc) {
+                break self.trapped(&cpu, cpu.core.pc, false);
+            }
+            if let Some(port) = self.interrupt_port {
+                let value = cpu.memory.read(port);
+                cpu.set_irq(value & IRQ_BIT != 0);
+                cpu.set_nmi(value & NMI_BIT != 0);
+            }
+
+            let before = cpu.core;
+            let step = match cpu.step() {
+                Ok(step) => step,
+                Err(Error::InvalidOpcode { pc, opcode }) => {
+                    break DormannOutcome::InvalidOpcode { pc, opcode }
+                }
+                Err(err) => unreachable!("instructions only fail to decode: {}", err),
+            };
+
+            instructions += 1;
+            if self.trace_length > 0 {
+                if trace.len() == self.trace_length {
+                    trace.pop_front();
+                }
+                let bytes: Vec<_> = step
+                    .opcode_bytes()
+                    .iter()
+                    .map(|byte| format!("{:02X}", byte))
+                    .collect();
+                trace.push_back(format!(
+                    "{:04X}  {:8}  {:14}  {}",
+                    step.addr,
+                    bytes.join(" "),
+                    step.to_string(),
+                    before
+                ));
+            }
+
+            if cpu.is_halted() {
+                break self.trapped(&cpu, step.addr, true);
+            }
+            if step.kind == StepKind::Instruction && cpu.core.pc == step.addr {
+                break self.trapped(&cpu, step.addr, false);
+            }
+        };
+
+        DormannReport {
+            outcome,
+            instructions,
+            cycles: cpu.cycles,
+            trace: trace.into(),
+        }
+    }
+
+    fn trapped(
+        &self,
+        cpu: &Processor<RandomAccessMemory>,
+        pc: u16,
+        halted: bool,
+    ) -> DormannOutcome {
+        let passed = match (self.success, self.error_addr) {
+            (Some(success), _) => pc == success,
+            (None, Some(error_addr)) => cpu.memory.read(error_addr) == 0,
+            (None, None) => false,
+        };
+        if passed {
+            DormannOutcome::Passed
+        } else if halted && self.error_addr.is_none() {
+            DormannOutcome::Halted { pc }
+        } else {
+            DormannOutcome::Failed {
+                pc,
+                test_number: self.test_number_addr.map(|addr| cpu.memory.read(addr)),
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DormannOutcome {
+    Passed,
+    // Trapped anywhere but the success address
+    Failed { pc: u16, test_number: Option<u8> },
+    // A JAM instruction ran
+    Halted { pc: u16 },
+    InvalidOpcode { pc: u16, opcode: u8 },
+    TimedOut,
+}
+
+#[derive(Clone, Debug)]
+pub struct DormannReport {
+    pub outcome: DormannOutcome,
+    pub instructions: usize,
+    pub cycles: usize,
+    // The last few instructions, oldest first, each with the registers from
+    // before it ran
+    pub trace: Vec<String>,
+}
+
+impl DormannReport {
+    pub fn passed(&self) -> bool {
+        self.outcome == DormannOutcome::Passed
+    }
+}
+
+impl Display for DormannReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.outcome {
+            DormannOutcome::Passed => write!(f, "passed")?,
+            DormannOutcome::Failed {
+                pc,
+                test_number: Some(test_number),
+            } => write!(f, "test 0x{:02x} failed at 0x{:04x}", test_number, pc)?,
+            DormannOutcome::Failed {
+                pc,
+                test_number: None,
+            } => write!(f, "failed at 0x{:04x}", pc)?,
+            DormannOutcome::Halted { pc } => write!(f, "halted at 0x{:04x}", pc)?,
+            DormannOutcome::InvalidOpcode { pc, opcode } => {
+                write!(f, "invalid opcode 0x{:02x} at 0x{:04x}", opcode, pc)?
+            }
+            DormannOutcome::TimedOut => write!(f, "timed out")?,
+        }
+        writeln!(
+            f,
+            " after {} instructions, {} cycles",
+            self.instructions, self.cycles
+        )?;
+        for line in &self.trace {
+            writeln!(f, "{}", line)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Builds a small image that loads at 0x0400, with the IRQ vector
+    // pointing at irq if given
+    fn image(program: &[u8], irq: Option<u16>) -> Vec<u8> {
+        let mut image = vec![0; 0x10000];
+        image[0x0400..0x0400 + program.len()].copy_from_slice(program);
+        if let Some(irq) = irq {
+            image[0xfffe..].copy_from_slice(&irq.to_le_bytes());
+        }
+        image
+    }
+
+    #[test]
+    fn test_traps() {
+        // LDA #$07; STA $0200; CMP #$07; BNE *; JMP *
+        let program = [
+            0xa9, 0x07, 0x8d, 0x00, 0x02, 0xc9, 0x07, 0xd0, 0xfe, 0x4c, 0x09, 0x04,
+        ];
+        let report = DormannTest::functional(image(&program, None), 0x0409).run();
+        assert!(report.passed(), "{}", report);
+        assert_eq!(report.instructions, 5);
+
+        // CMP #$08 fails, so it traps at the BNE
+        let mut failing = program;
+        failing[6] = 0x08;
+        let mut test = DormannTest::functional(image(&failing, None), 0x0409);
+        test.trace_length = 2;
+        let report = test.run();
+        assert_eq!(
+            report.outcome,
+            DormannOutcome::Failed {
+                pc: 0x0407,
+                test_number: Some(0x07)
+            }
+        );
+        assert_eq!(report.trace.len(), 2);
+        assert!(report.trace[1].starts_with("0407  D0 FE     BNE $0407"));
+        assert!(report.to_string().starts_with("test 0x07 failed at 0x0407"));
+
+        // JMP $0500, where there is a JAM
+        let mut test = DormannTest::functional(image(&[0x4c, 0x00, 0x05], None), 0x0409);
+        test.image[0x0500] = 0x02;
+        assert_eq!(test.run().outcome, DormannOutcome::Halted { pc: 0x0500 });
+
+        // JMP $0400 isn't a trap, as it doesn't jump to itself
+        test.image[0x0500..0x0503].copy_from_slice(&[0x4c, 0x00, 0x04]);
+        test.max_cycles = 100;
+        assert_eq!(test.run().outcome, DormannOutcome::TimedOut);
+    }
+
+    #[test]
+    fn test_interrupt_port() {
+        // CLI; LDA #$01; STA $BFFC; loop: JMP loop
+        // irq: LDA #$00; STA $BFFC; JMP *
+        let mut program = vec![0x58, 0xa9, 0x01, 0x8d, 0xfc, 0xbf, 0x4c, 0x06, 0x04];
+        program.extend([0xa9, 0x00, 0x8d, 0xfc, 0xbf, 0x4c, 0x0e, 0x04]);
+        let test = DormannTest::interrupt(image(&program, Some(0x0409)), 0x040e);
+        let report = test.run();
+        assert!(report.passed(), "{}", report);
+    }
+
+    #[test]
+    fn test_decimal_error_flag() {
+        // LDA #$00; STA $0B; STP, which is never reached as it's the end
+        let program = [0xa9, 0x00, 0x85, 0x0b, 0xdb, 0x00, 0x00];
+        for variant in [Variant::Nmos6502, Variant::Cmos65C02] {
+            let report = DormannTest::decimal(program.to_vec(), variant, 0x0204).run();
+            assert!(report.passed(), "{}", report);
+            assert_eq!(report.instructions, 2);
+        }
+
+        let mut failing = program;
+        failing[1] = 0x01;
+        assert_eq!(
+            DormannTest::decimal(failing.to_vec(), Variant::Nmos6502, 0x0204)
+                .run()
+                .outcome,
+            DormannOutcome::Failed {
+                pc: 0x0204,
+                test_number: None
+            }
+        );
+    }
+
+    // The test programs aren't distributed with this crate. Put the
+    // prebuilt binaries in tests/roms and run these with --ignored.
+    fn load_rom(name: &str) -> Vec<u8> {
+        let path = format!("{}/tests/roms/{}", env!("CARGO_MANIFEST_DIR"), name);
+        std::fs::read(&path).unwrap_or_else(|err| panic!("can't read {}: {}", path, err))
+    }
+
+    #[test]
+    #[ignore = "needs tests/roms/6502_functional_test.bin"]
+    fn test_functional_rom() {
+        let rom = load_rom("6502_functional_test.bin");
+        let report = DormannTest::functional(rom, 0x3469).run();
+        assert!(report.passed(), "{}", report);
+    }
+
+    #[test]
+    #[ignore = "needs tests/roms/6502_interrupt_test.bin"]
+    fn test_interrupt_rom() {
+        let rom = load_rom("6502_interrupt_test.bin");
+        let report = DormannTest::interrupt(rom, 0x06f5).run();
+        assert!(report.passed(), "{}", report);
+    }
+
+    // Assemble the test for the NMOS 6502 (cputype = 0) with chk_n, chk_v
+    // and chk_z turned on, and give the address of DONE from the listing in
+    // hex in DECIMAL_TEST_END
+    #[test]
+    #[ignore = "needs tests/roms/6502_decimal_test.bin and DECIMAL_TEST_END"]
+    fn test_decimal_rom() {
+        let rom = load_rom("6502_decimal_test.bin");
+        let end = std::env::var("DECIMAL_TEST_END").expect("DECIMAL_TEST_END isn't set");
+        let end = u16::from_str_radix(&end, 16)
+            .unwrap_or_else(|err| panic!("bad DECIMAL_TEST_END {}: {}", end, err));
+        let report = DormannTest::decimal(rom, Variant::Nmos6502, end).run();
+        assert!(report.passed(), "{}", report);
+    }
+}
@@ -0,0 +1,405 @@
+use std::fmt::Display;
+
+use crate::error::Error;
+use crate::json::Json;
+use crate::memory::{Memory, RandomAccessMemory};
+use crate::processor::Processor;
+use crate::variant::Variant;
+
+// Bits 4 and 5 of P aren't stored anywhere in a real 6502, so they aren't
+// compared
+const FLAG_MASK: u8 = 0xcf;
+
+// The registers and memory before or after one instruction
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct HarteState {
+    pub pc: u16,
+    pub s: u8,
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub p: u8,
+    pub ram: Vec<(u16, u8)>,
+}
+
+// One bus cycle
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HarteCycle {
+    pub addr: u16,
+    pub value: u8,
+    pub write: bool,
+}
+
+// One case from Tom Harte's SingleStepTests: an instruction run from the
+// initial state should reach the final state through exactly these cycles
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HarteCase {
+    pub name: String,
+    pub initial: HarteState,
+    pub expected: HarteState,
+    pub cycles: Vec<HarteCycle>,
+}
+
+fn field(json: &Json, key: &str, max: u64, index: usize) -> Result<u64, Error> {
+    json.get(key)
+        .and_then(Json::as_u64)
+        .filter(|value| *value <= max)
+        .ok_or(Error::InvalidTestCase {
+            index,
+            reason: "missing or bad number",
+        })
+}
+
+fn parse_state(json: Option<&Json>, index: usize) -> Result<HarteState, Error> {
+    let bad = |reason| Error::InvalidTestCase { index, reason };
+    let json = json.ok_or(bad("missing state"))?;
+    let ram = json
+        .get("ram")
+        .and_then(Json::as_array)
+        .ok_or(bad("missing ram"))?
+        .iter()
+        .map(|entry| match entry.as_array() {
+            Some([addr, value]) => match (addr.as_u64(), value.as_u64()) {
+                (Some(addr @ 0..=0xffff), Some(value @ 0..=0xff)) => Ok((addr as u16, value as u8)),
+                _ => Err(bad("bad ram entry")),
+            },
+            _ => Err(bad("bad ram entry")),
+        })
+        .collect::<Result<_, _>>()?;
+
+    Ok(HarteState {
+        pc: field(json, "pc", 0xffff, index)? as u16,
+        s: field(json, "s", 0xff, index)? as u8,
+        a: field(json, "a", 0xff, index)? as u8,
+        x: field(json, "x", 0xff, index)? as u8,
+        y: field(json, "y", 0xff, index)? as u8,
+        p: field(json, "p", 0xff, index)? as u8,
+        ram,
+    })
+}
+
+fn parse_cycle(json: &Json, index: usize) -> Result<HarteCycle, Error> {
+    let bad = Error::InvalidTestCase {
+        index,
+        reason: "bad cycle",
+    };
+    let Some([addr, value, kind]) = json.as_array() else {
+        return Err(bad);
+    };
+    match (addr.as_u64(), value.as_u64(), kind.as_str()) {
+        (Some(addr @ 0..=0xffff), Some(value @ 0..=0xff), Some(kind @ ("read" | "write"))) => {
+            Ok(HarteCycle {
+                addr: addr as u16,
+                value: value as u8,
+                write: kind == "write",
+            })
+        }
+        _ => Err(bad),
+    }
+}
+
+impl HarteCase {
+    // Reads one of the per-opcode files, which hold an array of cases
+    pub fn parse_file(text: &str) -> Result<Vec<HarteCase>, Error> {
+        let json = Json::parse(text)?;
+        let cases = json.as_array().ok_or(Error::InvalidTestCase {
+            index: 0,
+            reason: "expected an array of cases",
+        })?;
+
+        cases
+            .iter()
+            .enumerate()
+            .map(|(index, case)| {
+                let cycles =
+                    case.get("cycles")
+                        .and_then(Json::as_array)
+                        .ok_or(Error::InvalidTestCase {
+                            index,
+                            reason: "missing cycles",
+                        })?;
+                Ok(HarteCase {
+                    name: case
+                        .get("name")
+                        .and_then(Json::as_str)
+                        .unwrap_or("")
+                        .to_string(),
+                    initial: parse_state(case.get("initial"), index)?,
+                    expected: parse_state(case.get("final"), index)?,
+                    cycles: cycles
+                        .iter()
+                        .map(|cycle| parse_cycle(cycle, index))
+                        .collect::<Result<_, _>>()?,
+                })
+            })
+            .collect()
+    }
+
+    // The opcode the case tests
+    pub fn opcode(&self) -> u8 {
+        self.initial
+            .ram
+            .iter()
+            .find(|(addr, _)| *addr == self.initial.pc)
+            .map_or(0, |(_, value)| *value)
+    }
+
+    // Runs the instruction and describes the first thing that doesn't match
+    pub fn run(&self, variant: Variant) -> Result<(), String> {
+        let mut cpu = Processor::with_variant(RandomAccessMemory::new(0x10000), variant);
+        let initial = &self.initial;
+        cpu.core.pc = initial.pc;
+        cpu.core.sp = initial.s;
+        cpu.core.a = initial.a;
+        cpu.core.x = initial.x;
+        cpu.core.y = initial.y;
+        cpu.core.f.set_byte(initial.p);
+        for (addr, value) in &initial.ram {
+            cpu.memory.write(*addr, *value);
+        }
+
+        cpu.step().map_err(|err| err.to_string())?;
+
+        let expected = &self.expected;
+        let registers = [
+            ("PC", cpu.core.pc, expected.pc),
+            ("S", cpu.core.sp as u16, expected.s as u16),
+            ("A", cpu.core.a as u16, expected.a as u16),
+            ("X", cpu.core.x as u16, expected.x as u16),
+            ("Y", cpu.core.y as u16, expected.y as u16),
+            (
+                "P",
+                (cpu.core.f.get_byte() & FLAG_MASK) as u16,
+                (expected.p & FLAG_MASK) as u16,
+            ),
+        ];
+        for (name, actual, expected) in registers {
+            if actual != expected {
+                return Err(format!(
+                    "{} is 0x{:02x}, expected 0x{:02x}",
+                    name, actual, expected
+                ));
+            }
+        }
+        for (addr, value) in &expected.ram {
+            let actual = cpu.memory.read(*addr);
+            if actual != *value {
+                return Err(format!(
+                    "0x{:04x} is 0x{:02x}, expected 0x{:02x}",
+                    addr, actual, value
+                ));
+            }
+        }
+
+        let accesses = cpu.bus_accesses();
+        if accesses.len() != self.cycles.len() {
+            return Err(format!(
+                "took {} cycles, expected {}",
+                accesses.len(),
+                self.cycles.len()
+            ));
+        }
+        for (i, (access, cycle)) in accesses.iter().zip(&self.cycles).enumerate() {
+            let actual = HarteCycle {
+                addr: access.addr,
+                value: access.value,
+                write: access.kind.is_write(),
+            };
+            if actual != *cycle {
+                let kind = |write| if write { "write" } else { "read" };
+                return Err(format!(
+                    "cycle {} was {} 0x{:02x} at 0x{:04x}, expected {} 0x{:02x} at 0x{:04x}",
+                    i + 1,
+                    kind(actual.write),
+                    actual.value,
+                    actual.addr,
+                    kind(cycle.write),
+                    cycle.value,
+                    cycle.addr
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct OpcodeResult {
+    pub passed: usize,
+    pub failed: usize,
+    // The name of the first failing case and what went wrong
+    pub first_failure: Option<(String, String)>,
+}
+
+// Pass and fail counts for every opcode, shown as a 16x16 grid with the
+// high nibble down the side
+#[derive(Clone, Debug)]
+pub struct HarteMatrix {
+    pub results: Vec<OpcodeResult>,
+}
+
+impl Default for HarteMatrix {
+    fn default() -> Self {
+        Self {
+            results: vec![OpcodeResult::default(); 256],
+        }
+    }
+}
+
+impl HarteMatrix {
+    pub fn run(&mut self, cases: &[HarteCase], variant: Variant) {
+        for case in cases {
+            let result = &mut self.results[case.opcode() as usize];
+            match case.run(variant) {
+                Ok(()) => result.passed += 1,
+                Err(reason) => {
+                    result.failed += 1;
+                    result
+                        .first_failure
+                        .get_or_insert_with(|| (case.name.clone(), reason));
+                }
+            }
+        }
+    }
+
+    pub fn all_passed(&self) -> bool {
+        self.results.iter().all(|result| result.failed == 0)
+    }
+
+    // Opcodes with at least one failure, with their first failure
+    pub fn failures(&self) -> impl Iterator<Item = (u8, &OpcodeResult)> {
+        self.results
+            .iter()
+            .enumerate()
+            .filter(|(_, result)| result.failed > 0)
+            .map(|(opcode, result)| (opcode as u8, result))
+    }
+}
+
+// Each cell is "ok" when every case passed, the number of failures
+// otherwise, or blank when there were no cases for the opcode
+impl Display for HarteMatrix {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "  ")?;
+        for low in 0..16 {
+            write!(f, " {:>5X}", low)?;
+        }
+        writeln!(f)?;
+        for high in 0..16 {
+            write!(f, "{:X}x", high)?;
+            for low in 0..16 {
+                let result = &self.results[high * 16 + low];
+                if result.failed > 0 {
+                    write!(f, " {:>5}", result.failed)?;
+                } else if result.passed > 0 {
+                    write!(f, " {:>5}", "ok")?;
+                } else {
+                    write!(f, " {:>5}", "")?;
+                }
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // LDA #$42 at 0x1000, and a second case that expects the wrong value
+    const CASES: &str = r#"[
+        {
+            "name": "a9 42 00",
+            "initial": {"pc": 4096, "s": 253, "a": 0, "x": 0, "y": 0, "p": 38,
+                        "ram": [[4096, 169], [4097, 66]]},
+            "final": {"pc": 4098, "s": 253, "a": 66, "x": 0, "y": 0, "p": 36,
+                      "ram": [[4096, 169], [4097, 66]]},
+            "cycles": [[4096, 169, "read"], [4097, 66, "read"]]
+        },
+        {
+            "name": "a9 42 01",
+            "initial": {"pc": 4096, "s": 253, "a": 0, "x": 0, "y": 0, "p": 36,
+                        "ram": [[4096, 169], [4097, 66]]},
+            "final": {"pc": 4098, "s": 253, "a": 67, "x": 0, "y": 0, "p": 36,
+                      "ram": [[4096, 169], [4097, 66]]},
+            "cycles": [[4096, 169, "read"], [4097, 66, "read"]]
+        }
+    ]"#;
+
+    #[test]
+    fn test_parse_file() {
+        let cases = HarteCase::parse_file(CASES).unwrap();
+        assert_eq!(cases.len(), 2);
+        assert_eq!(cases[0].name, "a9 42 00");
+        assert_eq!(cases[0].opcode(), 0xa9);
+        assert_eq!(cases[0].expected.a, 0x42);
+        assert_eq!(
+            cases[0].cycles[1],
+            HarteCycle {
+                addr: 0x1001,
+                value: 0x42,
+                write: false
+            }
+        );
+
+        assert_eq!(
+            HarteCase::parse_file(r#"[{"name": "x"}]"#),
+            Err(Error::InvalidTestCase {
+                index: 0,
+                reason: "missing cycles"
+            })
+        );
+    }
+
+    #[test]
+    fn test_matrix() {
+        let cases = HarteCase::parse_file(CASES).unwrap();
+        assert_eq!(cases[0].run(Variant::Nmos6502), Ok(()));
+        assert_eq!(
+            cases[1].run(Variant::Nmos6502),
+            Err("A is 0x42, expected 0x43".to_string())
+        );
+
+        let mut matrix = HarteMatrix::default();
+        matrix.run(&cases, Variant::Nmos6502);
+        assert!(!matrix.all_passed());
+        let failures: Vec<_> = matrix.failures().collect();
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].0, 0xa9);
+        assert_eq!(failures[0].1.passed, 1);
+        assert_eq!(
+            failures[0].1.first_failure,
+            Some((
+                "a9 42 01".to_string(),
+                "A is 0x42, expected 0x43".to_string()
+            ))
+        );
+        let grid = matrix.to_string();
+        let row = grid.lines().nth(11).unwrap().trim_end();
+        assert_eq!(row, format!("Ax{}     1", " ".repeat(6 * 9)));
+    }
+
+    // The test data isn't distributed with this crate. Put the files from
+    // SingleStepTests' 6502/v1 directory in tests/harte and run this with
+    // --ignored.
+    #[test]
+    #[ignore = "needs SingleStepTests data in tests/harte"]
+    fn test_single_step_tests() {
+        let dir = format!("{}/tests/harte", env!("CARGO_MANIFEST_DIR"));
+        let entries =
+            std::fs::read_dir(&dir).unwrap_or_else(|err| panic!("can't read {}: {}", dir, err));
+        let mut matrix = HarteMatrix::default();
+        for entry in entries {
+            let text = std::fs::read_to_string(entry.unwrap().path()).unwrap();
+            matrix.run(&HarteCase::parse_file(&text).unwrap(), Variant::Nmos6502);
+        }
+        let cases: usize = matrix
+            .results
+            .iter()
+            .map(|result| result.passed + result.failed)
+            .sum();
+        assert!(cases > 0, "no test cases in {}", dir);
+        assert!(matrix.all_passed(), "\n{}", matrix);
+    }
+}
@@ -0,0 +1,6 @@
+// Runners for the well-known 6502 test suites
+mod dormann;
+mod harte;
+
+pub use dormann::{DormannOutcome, DormannReport, DormannTest};
+pub use harte::{HarteCase, HarteCycle, HarteMatrix, HarteState, OpcodeResult};
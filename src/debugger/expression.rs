@@ -0,0 +1,415 @@
+use crate::error::Error;
+use crate::memory::Memory;
+use crate::processor::Processor;
+
+// A condition over the registers and memory, such as
+// `A == $40 && [$0300] > 3`. Numbers are decimal unless they start with $
+// or 0x, [addr] reads a byte of memory, and the flags C, Z, I, D, V and N
+// are 0 or 1. The operators and their precedence follow C.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Expression {
+    root: Node,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Register {
+    A,
+    X,
+    Y,
+    P,
+    Sp,
+    Pc,
+    C,
+    Z,
+    I,
+    D,
+    V,
+    N,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum UnaryOp {
+    Not,
+    Negate,
+    Complement,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum BinaryOp {
+    Or,
+    And,
+    BitOr,
+    BitXor,
+    BitAnd,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Remainder,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Node {
+    Number(i64),
+    Register(Register),
+    Memory(Box<Node>),
+    Unary(UnaryOp, Box<Node>),
+    Binary(BinaryOp, Box<Node>, Box<Node>),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Token {
+    Number(i64),
+    Name(String),
+    Symbol(&'static str),
+}
+
+// Longest first, so that `<=` isn't read as `<`
+const SYMBOLS: [&str; 22] = [
+    "||", "&&", "==", "!=", "<=", ">=", "<", ">", "|", "^", "&", "+", "-", "*", "/", "%", "!", "~",
+    "(", ")", "[", "]",
+];
+
+// Binary operators from the loosest binding to the tightest
+const PRECEDENCE: [&[(&str, BinaryOp)]; 8] = [
+    &[("||", BinaryOp::Or)],
+    &[("&&", BinaryOp::And)],
+    &[("|", BinaryOp::BitOr)],
+    &[("^", BinaryOp::BitXor)],
+    &[("&", BinaryOp::BitAnd)],
+    &[("==", BinaryOp::Equal), ("!=", BinaryOp::NotEqual)],
+    &[
+        ("<", BinaryOp::Less),
+        ("<=", BinaryOp::LessEqual),
+        (">", BinaryOp::Greater),
+        (">=", BinaryOp::GreaterEqual),
+    ],
+    &[("+", BinaryOp::Add), ("-", BinaryOp::Subtract)],
+];
+
+const MULTIPLICATIVE: [(&str, BinaryOp); 3] = [
+    ("*", BinaryOp::Multiply),
+    ("/", BinaryOp::Divide),
+    ("%", BinaryOp::Remainder),
+];
+
+fn invalid(position: usize, reason: &'static str) -> Error {
+    Error::InvalidExpression { position, reason }
+}
+
+fn tokenize(text: &str) -> Result<Vec<(usize, Token)>, Error> {
+    let bytes = text.as_bytes();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let start = i;
+        let c = bytes[i];
+
+        if c.is_ascii_whitespace() {
+            i += 1;
+            continue;
+        }
+
+        let (radix, digits_start) = if c == b'$' {
+            (16, i + 1)
+        } else if c == b'0' && matches!(bytes.get(i + 1), Some(b'x' | b'X')) {
+            (16, i + 2)
+        } else if c.is_ascii_digit() {
+            (10, i)
+        } else {
+            (0, i)
+        };
+
+        if radix != 0 {
+            i = digits_start;
+            while i < bytes.len() && bytes[i].is_ascii_alphanumeric() {
+                i += 1;
+            }
+            let value = i64::from_str_radix(&text[digits_start..i], radix)
+                .map_err(|_| invalid(start, "bad number"))?;
+            tokens.push((start, Token::Number(value)));
+        } else if c.is_ascii_alphabetic() {
+            while i < bytes.len() && bytes[i].is_ascii_alphanumeric() {
+                i += 1;
+            }
+            tokens.push((start, Token::Name(text[start..i].to_ascii_uppercase())));
+        } else {
+            let symbol = SYMBOLS
+                .iter()
+                .find(|symbol| text[i..].starts_with(**symbol))
+                .ok_or(invalid(start, "unexpected character"))?;
+            i += symbol.len();
+            tokens.push((start, Token::Symbol(symbol)));
+        }
+    }
+
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    next: usize,
+    end: usize,
+}
+
+impl Parser {
+    fn position(&self) -> usize {
+        self.tokens
+            .get(self.next)
+            .map(|(position, _)| *position)
+            .unwrap_or(self.end)
+    }
+
+    fn peek_symbol(&self) -> Option<&'static str> {
+        match self.tokens.get(self.next) {
+            Some((_, Token::Symbol(symbol))) => Some(symbol),
+            _ => None,
+        }
+    }
+
+    fn expect(&mut self, symbol: &'static str, reason: &'static str) -> Result<(), Error> {
+        if self.peek_symbol() == Some(symbol) {
+            self.next += 1;
+            Ok(())
+        } else {
+            Err(invalid(self.position(), reason))
+        }
+    }
+
+    fn binary(&mut self, level: usize) -> Result<Node, Error> {
+        let operators: &[(&str, BinaryOp)] = match PRECEDENCE.get(level) {
+            Some(operators) => operators,
+            None if level == PRECEDENCE.len() => &MULTIPLICATIVE,
+            None => return self.unary(),
+        };
+
+        let mut left = self.binary(level + 1)?;
+        while let Some(symbol) = self.peek_symbol() {
+            let Some((_, op)) = operators.iter().find(|(s, _)| *s == symbol) else {
+                break;
+            };
+            self.next += 1;
+            let right = self.binary(level + 1)?;
+            left = Node::Binary(*op, Box::new(left), Box::new(right));
+        }
+
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Node, Error> {
+        let op = match self.peek_symbol() {
+            Some("!") => UnaryOp::Not,
+            Some("-") => UnaryOp::Negate,
+            Some("~") => UnaryOp::Complement,
+            _ => return self.primary(),
+        };
+        self.next += 1;
+        Ok(Node::Unary(op, Box::new(self.unary()?)))
+    }
+
+    fn primary(&mut self) -> Result<Node, Error> {
+        let position = self.position();
+        let Some((_, token)) = self.tokens.get(self.next).cloned() else {
+            return Err(invalid(position, "expected a value"));
+        };
+        self.next += 1;
+
+        match token {
+            Token::Number(value) => Ok(Node::Number(value)),
+            Token::Name(name) => {
+                let register = match name.as_str() {
+                    "A" => Register::A,
+                    "X" => Register::X,
+                    "Y" => Register::Y,
+                    "P" => Register::P,
+                    "S" | "SP" => Register::Sp,
+                    "PC" => Register::Pc,
+                    "C" => Register::C,
+                    "Z" => Register::Z,
+                    "I" => Register::I,
+                    "D" => Register::D,
+                    "V" => Register::V,
+                    "N" => Register::N,
+                    _ => return Err(invalid(position, "unknown register")),
+                };
+                Ok(Node::Register(register))
+            }
+            Token::Symbol("(") => {
+                let inner = self.binary(0)?;
+                self.expect(")", "expected )")?;
+                Ok(inner)
+            }
+            Token::Symbol("[") => {
+                let addr = self.binary(0)?;
+                self.expect("]", "expected ]")?;
+                Ok(Node::Memory(Box::new(addr)))
+            }
+            Token::Symbol(_) => Err(invalid(position, "expected a value")),
+        }
+    }
+}
+
+impl Expression {
+    pub fn parse(text: &str) -> Result<Self, Error> {
+        let mut parser = Parser {
+            tokens: tokenize(text)?,
+            next: 0,
+            end: text.len(),
+        };
+        let root = parser.binary(0)?;
+        if parser.next != parser.tokens.len() {
+            return Err(invalid(parser.position(), "unexpected input"));
+        }
+        Ok(Self { root })
+    }
+
+    pub fn evaluate<T: Memory>(&self, cpu: &Processor<T>) -> i64 {
+        evaluate(&self.root, cpu)
+    }
+
+    pub fn is_true<T: Memory>(&self, cpu: &Processor<T>) -> bool {
+        self.evaluate(cpu) != 0
+    }
+}
+
+fn evaluate<T: Memory>(node: &Node, cpu: &Processor<T>) -> i64 {
+    match node {
+        Node::Number(value) => *value,
+        Node::Register(register) => {
+            let core = &cpu.core;
+            match register {
+                Register::A => core.a as i64,
+                Register::X => core.x as i64,
+                Register::Y => core.y as i64,
+                Register::P => core.f.get_byte() as i64,
+                Register::Sp => core.sp as i64,
+                Register::Pc => core.pc as i64,
+                Register::C => core.f.c as i64,
+                Register::Z => core.f.z as i64,
+                Register::I => core.f.i as i64,
+                Register::D => core.f.d as i64,
+                Register::V => core.f.v as i64,
+                Register::N => core.f.n as i64,
+            }
+        }
+        Node::Memory(addr) => cpu.memory.read(evaluate(addr, cpu) as u16) as i64,
+        Node::Unary(op, operand) => {
+            let value = evaluate(operand, cpu);
+            match op {
+                UnaryOp::Not => (value == 0) as i64,
+                UnaryOp::Negate => value.wrapping_neg(),
+                UnaryOp::Complement => !value,
+            }
+        }
+        Node::Binary(op, left, right) => {
+            let left = evaluate(left, cpu);
+            // The logical operators short circuit like they do in C
+            match op {
+                BinaryOp::Or if left != 0 => return 1,
+                BinaryOp::And if left == 0 => return 0,
+                _ => {}
+            }
+            let right = evaluate(right, cpu);
+            match op {
+                BinaryOp::Or | BinaryOp::And => (right != 0) as i64,
+                BinaryOp::BitOr => left | right,
+                BinaryOp::BitXor => left ^ right,
+                BinaryOp::BitAnd => left & right,
+                BinaryOp::Equal => (left == right) as i64,
+                BinaryOp::NotEqual => (left != right) as i64,
+                BinaryOp::Less => (left < right) as i64,
+                BinaryOp::LessEqual => (left <= right) as i64,
+                BinaryOp::Greater => (left > right) as i64,
+                BinaryOp::GreaterEqual => (left >= right) as i64,
+                BinaryOp::Add => left.wrapping_add(right),
+                BinaryOp::Subtract => left.wrapping_sub(right),
+                BinaryOp::Multiply => left.wrapping_mul(right),
+                // Dividing by zero gives zero rather than stopping the program
+                BinaryOp::Divide => left.checked_div(right).unwrap_or(0),
+                BinaryOp::Remainder => left.checked_rem(right).unwrap_or(0),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::RandomAccessMemory;
+
+    #[test]
+    fn test_evaluate() {
+        let mut cpu = Processor::with_memory(RandomAccessMemory::new(0x10000));
+        cpu.core.a = 0x40;
+        cpu.core.x = 2;
+        cpu.core.f.c = true;
+        cpu.memory.write(0x0300, 5);
+        cpu.memory.write(0x0302, 0x80);
+
+        let eval = |text: &str| Expression::parse(text).unwrap().evaluate(&cpu);
+        assert_eq!(eval("A == $40 && [$0300] > 3"), 1);
+        assert_eq!(eval("a == 0x41 || [$300] >= 6"), 0);
+        assert_eq!(eval("[$0300 + X]"), 0x80);
+        assert_eq!(eval("1 + 2 * 3"), 7);
+        assert_eq!(eval("(1 + 2) * 3"), 9);
+        assert_eq!(eval("A & $f0 | X"), 0x42);
+        assert_eq!(eval("!C"), 0);
+        assert_eq!(eval("-X + 10 / 0"), -2);
+        assert_eq!(eval("P & $01"), 1);
+        assert_eq!(eval("PC != SP"), 1);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let error = |text: &str| Expression::parse(text).unwrap_err();
+        assert_eq!(
+            error("A == "),
+            Error::InvalidExpression {
+                position: 5,
+                reason: "expected a value"
+            }
+        );
+        assert_eq!(
+            error("Q > 1"),
+            Error::InvalidExpression {
+                position: 0,
+                reason: "unknown register"
+            }
+        );
+        assert_eq!(
+            error("[$10"),
+            Error::InvalidExpression {
+                position: 4,
+                reason: "expected ]"
+            }
+        );
+        assert_eq!(
+            error("$zz"),
+            Error::InvalidExpression {
+                position: 0,
+                reason: "bad number"
+            }
+        );
+        assert_eq!(
+            error("1 2"),
+            Error::InvalidExpression {
+                position: 2,
+                reason: "unexpected input"
+            }
+        );
+        assert_eq!(
+            error("A @ 1"),
+            Error::InvalidExpression {
+                position: 2,
+                reason: "unexpected character"
+            }
+        );
+    }
+}
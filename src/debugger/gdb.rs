@@ -0,0 +1,628 @@
+use std::collections::HashMap;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::ops::RangeInclusive;
+
+use crate::memory::Memory;
+use crate::processor::Processor;
+
+use super::{Debugger, Stop, WatchKind};
+
+// How many instructions continue runs between checks for a break from GDB
+const CONTINUE_CHUNK: usize = 10000;
+
+const INTERRUPT: u8 = 0x03;
+
+// The most an m packet reads at once, which fills the packet size we tell
+// GDB about. GDB asks again for the rest of a longer read.
+const MAX_READ: u16 = 0x800;
+
+// Registers in the order of the g packet: A, X, Y, P and SP are one byte each,
+// followed by PC as two bytes, low byte first
+const REGISTER_SIZES: [usize; 6] = [1, 1, 1, 1, 1, 2];
+
+// A stream that GDB is connected to. While the processor runs, the server
+// asks the connection whether GDB has sent a break without blocking. GDB
+// hanging up is an error, so the server stops rather than running on with
+// nobody to report to.
+pub trait GdbConnection: Read + Write {
+    fn poll_interrupt(&mut self) -> io::Result<bool>;
+}
+
+impl GdbConnection for TcpStream {
+    fn poll_interrupt(&mut self) -> io::Result<bool> {
+        self.set_nonblocking(true)?;
+        let mut byte = [0];
+        let result = self.peek(&mut byte);
+        self.set_nonblocking(false)?;
+
+        match result {
+            Ok(1) if byte[0] == INTERRUPT => {
+                self.read_exact(&mut byte)?;
+                Ok(true)
+            }
+            Ok(0) => Err(ErrorKind::UnexpectedEof.into()),
+            Ok(_) => Ok(false),
+            Err(err) if err.kind() == ErrorKind::WouldBlock => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+// The type of a Z packet, with the address and kind it was given
+type PointKey = (u8, u16, u16);
+
+pub struct GdbServer<C: GdbConnection> {
+    connection: C,
+    input: Vec<u8>,
+    acknowledge: bool,
+    debugger: Debugger,
+    // Debugger ids for each breakpoint or watchpoint GDB has inserted
+    points: HashMap<PointKey, Vec<usize>>,
+}
+
+impl GdbServer<TcpStream> {
+    // Waits for GDB to connect to the port on localhost
+    pub fn accept(port: u16) -> io::Result<Self> {
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        let (stream, _) = listener.accept()?;
+        stream.set_nodelay(true)?;
+        Ok(Self::new(stream))
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn from_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn parse_number(text: &str) -> Option<u16> {
+    u16::from_str_radix(text, 16).ok()
+}
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum, byte| sum.wrapping_add(*byte))
+}
+
+impl<C: GdbConnection> GdbServer<C> {
+    pub fn new(connection: C) -> Self {
+        Self {
+            connection,
+            input: Vec::new(),
+            acknowledge: true,
+            debugger: Debugger::new(),
+            points: HashMap::new(),
+        }
+    }
+
+    fn next_byte(&mut self) -> io::Result<Option<u8>> {
+        if self.input.is_empty() {
+            let mut buffer = [0; 1024];
+            let count = self.connection.read(&mut buffer)?;
+            if count == 0 {
+                return Ok(None);
+            }
+            self.input.extend_from_slice(&buffer[..count]);
+        }
+        Ok(Some(self.input.remove(0)))
+    }
+
+    // Returns None when GDB hangs up, and Some(None) for a break
+    fn receive(&mut self) -> io::Result<Option<Option<String>>> {
+        loop {
+            match self.next_byte()? {
+                None => return Ok(None),
+                Some(INTERRUPT) => return Ok(Some(None)),
+                Some(b'$') => {}
+                // Acknowledgements and noise between packets
+                Some(_) => continue,
+            }
+
+            let mut data = Vec::new();
+            loop {
+                match self.next_byte()? {
+                    None => return Ok(None),
+                    Some(b'#') => break,
+                    Some(byte) => data.push(byte),
+                }
+            }
+            let mut sum = [0; 2];
+            for digit in sum.iter_mut() {
+                match self.next_byte()? {
+                    None => return Ok(None),
+                    Some(byte) => *digit = byte,
+                }
+            }
+
+            let expected = std::str::from_utf8(&sum)
+                .ok()
+                .and_then(|sum| u8::from_str_radix(sum, 16).ok());
+            if expected != Some(checksum(&data)) {
+                if self.acknowledge {
+                    self.connection.write_all(b"-")?;
+                }
+                continue;
+            }
+            if self.acknowledge {
+                self.connection.write_all(b"+")?;
+            }
+            return Ok(Some(Some(String::from_utf8_lossy(&data).into_owned())));
+        }
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        let packet = format!("${}#{:02x}", data, checksum(data.as_bytes()));
+        loop {
+            self.connection.write_all(packet.as_bytes())?;
+            self.connection.flush()?;
+            if !self.acknowledge {
+                return Ok(());
+            }
+            match self.next_byte()? {
+                Some(b'-') => continue,
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    // Serves GDB until it detaches, kills the program or hangs up
+    pub fn serve<T: Memory>(&mut self, cpu: &mut Processor<T>) -> io::Result<()> {
+        while let Some(packet) = self.receive()? {
+            let Some(packet) = packet else {
+                // A break while we're already stopped
+                self.send("S02")?;
+                continue;
+            };
+
+            match packet.as_bytes().first() {
+                Some(b'k') => return Ok(()),
+                Some(b'D') => return self.send("OK"),
+                _ => {}
+            }
+            let reply = self.handle(cpu, &packet)?;
+            self.send(&reply)?;
+        }
+        Ok(())
+    }
+
+    fn handle<T: Memory>(&mut self, cpu: &mut Processor<T>, packet: &str) -> io::Result<String> {
+        if packet.is_empty() {
+            return Ok(String::new());
+        }
+        let (command, args) = packet.split_at(1);
+        let reply = match command {
+            "?" => Some("S05".to_string()),
+            "g" => Some(self.read_registers(cpu)),
+            "G" => self.write_registers(cpu, args),
+            "p" => usize::from_str_radix(args, 16)
+                .ok()
+                .and_then(|register| self.read_register(cpu, register)),
+            "P" => self.write_register(cpu, args),
+            "m" => self.read_memory(cpu, args),
+            "M" => self.write_memory(cpu, args),
+            "c" | "s" => {
+                if let Some(addr) = parse_number(args) {
+                    cpu.core.pc = addr;
+                }
+                return self.resume(cpu, command == "s");
+            }
+            "Z" => self.insert_point(args),
+            "z" => self.remove_point(args),
+            "H" => Some("OK".to_string()),
+            "q" | "Q" => Some(self.query(packet)),
+            _ => Some(String::new()),
+        };
+        Ok(reply.unwrap_or_else(|| "E01".to_string()))
+    }
+
+    fn query(&mut self, packet: &str) -> String {
+        if packet.starts_with("qSupported") {
+            "PacketSize=1000;QStartNoAckMode+".to_string()
+        } else if packet == "QStartNoAckMode" {
+            self.acknowledge = false;
+            "OK".to_string()
+        } else if packet == "qAttached" {
+            "1".to_string()
+        } else if packet == "qfThreadInfo" {
+            "m1".to_string()
+        } else if packet == "qsThreadInfo" {
+            "l".to_string()
+        } else if packet == "qC" {
+            "QC1".to_string()
+        } else {
+            String::new()
+        }
+    }
+
+    fn register_bytes<T: Memory>(cpu: &Processor<T>) -> [u8; 7] {
+        let core = &cpu.core;
+        let [pc_low, pc_high] = core.pc.to_le_bytes();
+        [
+            core.a,
+            core.x,
+            core.y,
+            core.f.get_byte(),
+            core.sp,
+            pc_low,
+            pc_high,
+        ]
+    }
+
+    fn read_registers<T: Memory>(&self, cpu: &Processor<T>) -> String {
+        to_hex(&Self::register_bytes(cpu))
+    }
+
+    fn read_register<T: Memory>(&self, cpu: &Processor<T>, register: usize) -> Option<String> {
+        let size = *REGISTER_SIZES.get(register)?;
+        let bytes = Self::register_bytes(cpu);
+        Some(to_hex(&bytes[register..register + size]))
+    }
+
+    fn set_register<T: Memory>(cpu: &mut Processor<T>, register: usize, bytes: &[u8]) {
+        let core = &mut cpu.core;
+        match register {
+            0 => core.a = bytes[0],
+            1 => core.x = bytes[0],
+            2 => core.y = bytes[0],
+            3 => core.f.set_byte(bytes[0]),
+            4 => core.sp = bytes[0],
+            _ => core.pc = u16::from_le_bytes([bytes[0], bytes[1]]),
+        }
+    }
+
+    fn write_registers<T: Memory>(&self, cpu: &mut Processor<T>, args: &str) -> Option<String> {
+        let bytes = from_hex(args)?;
+        if bytes.len() != REGISTER_SIZES.iter().sum() {
+            return None;
+        }
+
+        let mut offset = 0;
+        for (register, size) in REGISTER_SIZES.iter().enumerate() {
+            Self::set_register(cpu, register, &bytes[offset..offset + size]);
+            offset += size;
+        }
+        Some("OK".to_string())
+    }
+
+    fn write_register<T: Memory>(&self, cpu: &mut Processor<T>, args: &str) -> Option<String> {
+        let (register, value) = args.split_once('=')?;
+        let register = usize::from_str_radix(register, 16).ok()?;
+        let bytes = from_hex(value)?;
+        if bytes.len() != *REGISTER_SIZES.get(register)? {
+            return None;
+        }
+
+        Self::set_register(cpu, register, &bytes);
+        Some("OK".to_string())
+    }
+
+    fn read_memory<T: Memory>(&self, cpu: &Processor<T>, args: &str) -> Option<String> {
+        let (addr, length) = args.split_once(',')?;
+        let addr = parse_number(addr)?;
+        let length = parse_number(length)?.min(MAX_READ);
+        if !Self::in_memory(cpu, addr, length as usize) {
+            return None;
+        }
+
+        let bytes: Vec<u8> = (0..length)
+            .map(|i| cpu.memory.read(addr.wrapping_add(i)))
+            .collect();
+        Some(to_hex(&bytes))
+    }
+
+    fn write_memory<T: Memory>(&self, cpu: &mut Processor<T>, args: &str) -> Option<String> {
+        let (location, data) = args.split_once(':')?;
+        let (addr, length) = location.split_once(',')?;
+        let addr = parse_number(addr)?;
+        let bytes = from_hex(data)?;
+        if bytes.len() != parse_number(length)? as usize {
+            return None;
+        }
+        if !Self::in_memory(cpu, addr, bytes.len()) {
+            return None;
+        }
+
+        for (i, byte) in bytes.iter().enumerate() {
+            cpu.memory.write(addr.wrapping_add(i as u16), *byte);
+        }
+        Some("OK".to_string())
+    }
+
+    // Front ends ask for whatever the user types, so anything past the end
+    // of memory gets an error rather than reaching it
+    fn in_memory<T: Memory>(cpu: &Processor<T>, addr: u16, length: usize) -> bool {
+        addr as usize + length <= cpu.memory.length()
+    }
+
+    fn parse_point(args: &str) -> Option<PointKey> {
+        let mut fields = args.split(',');
+        let point_type = fields.next()?.parse().ok()?;
+        let addr = parse_number(fields.next()?)?;
+        let kind = parse_number(fields.next()?.split(';').next()?)?;
+        Some((point_type, addr, kind))
+    }
+
+    fn insert_point(&mut self, args: &str) -> Option<String> {
+        let key = Self::parse_point(args)?;
+        let (point_type, addr, length) = key;
+        // Watchpoint kinds are the length of the watched range, which is
+        // split in two if it wraps around past $FFFF
+        let end = addr.wrapping_add(length.max(1) - 1);
+        let ranges = match end < addr {
+            true => vec![addr..=0xffff, 0..=end],
+            false => vec![addr..=end],
+        };
+
+        let ids = match point_type {
+            // Software and hardware breakpoints are the same thing here
+            0 | 1 => vec![self.debugger.add_breakpoint(addr)],
+            2 => self.add_watchpoints(&ranges, &[WatchKind::Write]),
+            3 => self.add_watchpoints(&ranges, &[WatchKind::Read]),
+            4 => self.add_watchpoints(&ranges, &[WatchKind::Read, WatchKind::Write]),
+            _ => return Some(String::new()),
+        };
+        self.points.entry(key).or_default().extend(ids);
+        Some("OK".to_string())
+    }
+
+    fn add_watchpoints(
+        &mut self,
+        ranges: &[RangeInclusive<u16>],
+        kinds: &[WatchKind],
+    ) -> Vec<usize> {
+        kinds
+            .iter()
+            .flat_map(|kind| ranges.iter().map(move |range| (range.clone(), *kind)))
+            .map(|(range, kind)| self.debugger.add_watchpoint(range, kind))
+            .collect()
+    }
+
+    fn remove_point(&mut self, args: &str) -> Option<String> {
+        let key = Self::parse_point(args)?;
+        for id in self.points.remove(&key)? {
+            self.debugger.remove(id);
+        }
+        Some("OK".to_string())
+    }
+
+    fn stop_reply(&self, stop: Stop) -> String {
+        match stop {
+            Stop::Stepped | Stop::Breakpoint { .. } | Stop::Waiting => "S05".to_string(),
+            Stop::Watchpoint { id, access } => {
+                let point_type = self
+                    .points
+                    .iter()
+                    .find(|(_, ids)| ids.contains(&id))
+                    .map(|((point_type, _, _), _)| *point_type);
+                let name = match point_type {
+                    Some(2) => "watch",
+                    Some(3) => "rwatch",
+                    Some(4) => "awatch",
+                    _ => return "S05".to_string(),
+                };
+                format!("T05{}:{:04x};", name, access.addr)
+            }
+            // SIGILL, since the processor can't go any further
+            Stop::Halted => "S04".to_string(),
+        }
+    }
+
+    fn resume<T: Memory>(
+        &mut self,
+        cpu: &mut Processor<T>,
+        single_step: bool,
+    ) -> io::Result<String> {
+        if single_step {
+            return Ok(match self.debugger.step_into(cpu) {
+                Ok(stop) => self.stop_reply(stop),
+                Err(_) => "S04".to_string(),
+            });
+        }
+
+        loop {
+            match self.debugger.run_for(cpu, CONTINUE_CHUNK) {
+                Ok(Stop::Stepped) => {}
+                Ok(stop) => return Ok(self.stop_reply(stop)),
+                Err(_) => return Ok("S04".to_string()),
+            }
+            if self.connection.poll_interrupt()? {
+                return Ok("S02".to_string());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::RandomAccessMemory;
+    use std::io::Cursor;
+
+    struct Script {
+        input: Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl Read for Script {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for Script {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl GdbConnection for Script {
+        fn poll_interrupt(&mut self) -> io::Result<bool> {
+            Ok(false)
+        }
+    }
+
+    fn packet(data: &str) -> String {
+        format!("${}#{:02x}", data, checksum(data.as_bytes()))
+    }
+
+    // Sends each packet in turn, acknowledging every reply, and returns the
+    // replies
+    fn session(cpu: &mut Processor<RandomAccessMemory>, packets: &[&str]) -> Vec<String> {
+        let input: String = packets.iter().map(|data| packet(data) + "+").collect();
+        let mut server = GdbServer::new(Script {
+            input: Cursor::new(input.into_bytes()),
+            output: Vec::new(),
+        });
+        server.serve(cpu).unwrap();
+
+        let output = String::from_utf8(server.connection.output).unwrap();
+        output
+            .split('$')
+            .skip(1)
+            .map(|reply| reply.split('#').next().unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_session() {
+        let mut cpu = Processor::with_memory(RandomAccessMemory::new(0x10000));
+        // LDA #$42; INC $0300; JMP $0200
+        let program = [0xa9, 0x42, 0xee, 0x00, 0x03, 0x4c, 0x00, 0x02];
+        for (i, byte) in program.iter().enumerate() {
+            cpu.memory.write(0x0200 + i as u16, *byte);
+        }
+        cpu.core.pc = 0x0200;
+        cpu.core.sp = 0xfd;
+
+        let replies = session(
+            &mut cpu,
+            &[
+                "qSupported:multiprocess+",
+                "?",
+                "g",
+                "P1=07",
+                "p1",
+                "m0200,3",
+                "M0400,2:abcd",
+                "m0400,2",
+                "s",
+                "p5",
+                "Z0,205,1",
+                "c",
+                "p5",
+                "z0,205,1",
+                "Z2,300,1",
+                "c",
+                "z2,300,1",
+                "Z4,300,1",
+                "c",
+                "G0102033405fe00",
+                "g",
+                "vMustReplyEmpty",
+                "D",
+            ],
+        );
+
+        assert_eq!(
+            replies,
+            [
+                "PacketSize=1000;QStartNoAckMode+",
+                "S05",
+                "00000020fd0002",
+                "OK",
+                "07",
+                "a942ee",
+                "OK",
+                "abcd",
+                "S05",
+                "0202",
+                "OK",
+                "S05",
+                "0502",
+                "OK",
+                "OK",
+                "T05watch:0300;",
+                "OK",
+                "OK",
+                "T05awatch:0300;",
+                "OK",
+                "0102032405fe00",
+                "",
+                "OK",
+            ]
+        );
+        assert_eq!(cpu.memory.read(0x0401), 0xcd);
+        assert_eq!(cpu.core.f.get_byte(), 0x24);
+        assert_eq!(cpu.core.pc, 0x00fe);
+    }
+
+    #[test]
+    fn test_limits() {
+        let mut cpu = Processor::with_memory(RandomAccessMemory::new(0x10000));
+        // STA $0001; JMP $0200
+        let program = [0x8d, 0x01, 0x00, 0x4c, 0x00, 0x02];
+        for (i, byte) in program.iter().enumerate() {
+            cpu.memory.write(0x0200 + i as u16, *byte);
+        }
+        cpu.core.pc = 0x0200;
+
+        // A watchpoint from $FFFE that wraps around to $0001
+        let replies = session(&mut cpu, &["m0,ffff", "Z2,fffe,4", "c", "D"]);
+        assert_eq!(replies[0].len(), 2 * MAX_READ as usize);
+        assert_eq!(&replies[1..], ["OK", "T05watch:0001;", "OK"]);
+
+        // Memory smaller than the address space ends where it ends
+        let mut cpu = Processor::with_memory(RandomAccessMemory::new(0x1000));
+        let replies = session(
+            &mut cpu,
+            &[
+                "mff0,10",
+                "mff0,11",
+                "mfffe,4",
+                "Mfff,2:0102",
+                "Mffe,2:0102",
+                "D",
+            ],
+        );
+        assert_eq!(replies[0].len(), 0x20);
+        assert_eq!(&replies[1..], ["E01", "E01", "E01", "OK", "OK"]);
+        assert_eq!(cpu.memory.read(0xfff), 0x02);
+    }
+
+    #[test]
+    fn test_hang_up() {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (mut stream, _) = listener.accept().unwrap();
+        assert!(!stream.poll_interrupt().unwrap());
+        drop(client);
+        assert_eq!(
+            stream.poll_interrupt().map_err(|err| err.kind()),
+            Err(ErrorKind::UnexpectedEof)
+        );
+    }
+
+    #[test]
+    fn test_bad_checksum() {
+        let mut cpu = Processor::with_memory(RandomAccessMemory::new(0x10000));
+        let mut server = GdbServer::new(Script {
+            input: Cursor::new(b"$?#00$?#3f+$k#6b".to_vec()),
+            output: Vec::new(),
+        });
+        server.serve(&mut cpu).unwrap();
+        assert_eq!(server.connection.output, b"-+$S05#b8+");
+    }
+}
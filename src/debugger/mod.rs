@@ -0,0 +1,444 @@
+mod expression;
+mod gdb;
+
+pub use expression::Expression;
+pub use gdb::{GdbConnection, GdbServer};
+
+use std::ops::RangeInclusive;
+
+use crate::bus::{AccessKind, BusAccess};
+use crate::error::Error;
+use crate::memory::Memory;
+use crate::processor::Processor;
+use crate::step::{Step, StepKind};
+
+const JSR: u8 = 0x20;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    // Running an instruction that starts in the range
+    Execute,
+}
+
+// Why the debugger handed control back
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stop {
+    // The step command finished
+    Stepped,
+    // PC reached a breakpoint whose condition was true. Nothing at PC has
+    // run yet.
+    Breakpoint { id: usize, pc: u16 },
+    // Read and write watchpoints stop after the instruction that made the
+    // access; execute watchpoints stop before the instruction runs
+    Watchpoint { id: usize, access: BusAccess },
+    Halted,
+    // Waiting for an interrupt that nothing is going to raise
+    Waiting,
+}
+
+struct Breakpoint {
+    id: usize,
+    // None for conditions that are checked before every instruction
+    addr: Option<u16>,
+    condition: Option<Expression>,
+}
+
+struct Watchpoint {
+    id: usize,
+    range: RangeInclusive<u16>,
+    kind: WatchKind,
+}
+
+// The breakpoints and watchpoints for a processor. Commands borrow the
+// processor so that one owned by something else, like a Nes, can be debugged.
+#[derive(Default)]
+pub struct Debugger {
+    breakpoints: Vec<Breakpoint>,
+    watchpoints: Vec<Watchpoint>,
+    next_id: usize,
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn allocate_id(&mut self) -> usize {
+        self.next_id += 1;
+        self.next_id
+    }
+
+    // Breakpoints and watchpoints share ids, which are never reused
+    pub fn add_breakpoint(&mut self, addr: u16) -> usize {
+        let id = self.allocate_id();
+        self.breakpoints.push(Breakpoint {
+            id,
+            addr: Some(addr),
+            condition: None,
+        });
+        id
+    }
+
+    pub fn add_conditional_breakpoint(
+        &mut self,
+        addr: u16,
+        condition: &str,
+    ) -> Result<usize, Error> {
+        let condition = Expression::parse(condition)?;
+        let id = self.allocate_id();
+        self.breakpoints.push(Breakpoint {
+            id,
+            addr: Some(addr),
+            condition: Some(condition),
+        });
+        Ok(id)
+    }
+
+    // Stops before any instruction where the condition is true
+    pub fn add_condition(&mut self, condition: &str) -> Result<usize, Error> {
+        let condition = Expression::parse(condition)?;
+        let id = self.allocate_id();
+        self.breakpoints.push(Breakpoint {
+            id,
+            addr: None,
+            condition: Some(condition),
+        });
+        Ok(id)
+    }
+
+    pub fn add_watchpoint(&mut self, range: RangeInclusive<u16>, kind: WatchKind) -> usize {
+        let id = self.allocate_id();
+        self.watchpoints.push(Watchpoint { id, range, kind });
+        id
+    }
+
+    // Returns false if there was nothing with that id
+    pub fn remove(&mut self, id: usize) -> bool {
+        let count = self.breakpoints.len() + self.watchpoints.len();
+        self.breakpoints.retain(|breakpoint| breakpoint.id != id);
+        self.watchpoints.retain(|watchpoint| watchpoint.id != id);
+        count != self.breakpoints.len() + self.watchpoints.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.breakpoints.clear();
+        self.watchpoints.clear();
+    }
+
+    // Checked before each instruction, apart from the first one run by a
+    // command so that it's possible to continue from a breakpoint
+    fn check_before<T: Memory>(&self, cpu: &Processor<T>) -> Option<Stop> {
+        let pc = cpu.core.pc;
+
+        for breakpoint in &self.breakpoints {
+            let at_addr = breakpoint.addr.is_none_or(|addr| addr == pc);
+            if at_addr
+                && breakpoint
+                    .condition
+                    .as_ref()
+                    .is_none_or(|condition| condition.is_true(cpu))
+            {
+                return Some(Stop::Breakpoint {
+                    id: breakpoint.id,
+                    pc,
+                });
+            }
+        }
+
+        self.watchpoints
+            .iter()
+            .find(|watchpoint| {
+                watchpoint.kind == WatchKind::Execute && watchpoint.range.contains(&pc)
+            })
+            .map(|watchpoint| Stop::Watchpoint {
+                id: watchpoint.id,
+                access: BusAccess {
+                    addr: pc,
+                    value: cpu.memory.read(pc),
+                    kind: AccessKind::OpcodeFetch,
+                },
+            })
+    }
+
+    fn check_accesses<T: Memory>(&self, cpu: &Processor<T>) -> Option<Stop> {
+        if self.watchpoints.is_empty() {
+            return None;
+        }
+
+        for access in cpu.bus_accesses() {
+            for watchpoint in &self.watchpoints {
+                let hit = match watchpoint.kind {
+                    WatchKind::Read => access.kind == AccessKind::Read,
+                    WatchKind::Write => access.kind.is_write(),
+                    WatchKind::Execute => false,
+                };
+                if hit && watchpoint.range.contains(&access.addr) {
+                    return Some(Stop::Watchpoint {
+                        id: watchpoint.id,
+                        access: *access,
+                    });
+                }
+            }
+        }
+
+        None
+    }
+
+    // Runs until finished returns true after a step, or something stops us
+    fn resume<T: Memory>(
+        &self,
+        cpu: &mut Processor<T>,
+        mut finished: impl FnMut(&Processor<T>, &Step) -> bool,
+    ) -> Result<Stop, Error> {
+        loop {
+            let step = cpu.step()?;
+            match step.kind {
+                StepKind::Halted => return Ok(Stop::Halted),
+                StepKind::Waiting => return Ok(Stop::Waiting),
+                _ => {}
+            }
+            if let Some(stop) = self.check_accesses(cpu) {
+                return Ok(stop);
+            }
+            if finished(cpu, &step) {
+                return Ok(Stop::Stepped);
+            }
+            if let Some(stop) = self.check_before(cpu) {
+                return Ok(stop);
+            }
+        }
+    }
+
+    pub fn step_into<T: Memory>(&self, cpu: &mut Processor<T>) -> Result<Stop, Error> {
+        self.resume(cpu, |_, _| true)
+    }
+
+    // Runs a whole subroutine if PC is at a JSR
+    pub fn step_over<T: Memory>(&self, cpu: &mut Processor<T>) -> Result<Stop, Error> {
+        let pc = cpu.core.pc;
+        if cpu.memory.read(pc) != JSR {
+            return self.step_into(cpu);
+        }
+
+        let return_addr = pc.wrapping_add(3);
+        let sp = cpu.core.sp;
+        self.resume(cpu, |cpu, _| {
+            cpu.core.pc == return_addr && cpu.core.sp == sp
+        })
+    }
+
+    // Runs until the RTS that returns from the current subroutine, which
+    // leaves the stack above where it was, even if that wraps past $FF
+    pub fn step_out<T: Memory>(&self, cpu: &mut Processor<T>) -> Result<Stop, Error> {
+        let sp = cpu.core.sp;
+        self.resume(cpu, |cpu, step| {
+            step.kind == StepKind::Instruction
+                && step.mnemonic == "RTS"
+                && cpu.core.sp.wrapping_sub(sp) as i8 > 0
+        })
+    }
+
+    pub fn run<T: Memory>(&self, cpu: &mut Processor<T>) -> Result<Stop, Error> {
+        self.resume(cpu, |_, _| false)
+    }
+
+    // Like run, but gives up with Stop::Stepped after count instructions
+    pub fn run_for<T: Memory>(&self, cpu: &mut Processor<T>, count: usize) -> Result<Stop, Error> {
+        let mut remaining = count;
+        let stop = self.resume(cpu, |_, _| {
+            remaining = remaining.saturating_sub(1);
+            remaining == 0
+        })?;
+
+        // The next command won't check the instruction we stopped in front of
+        if stop == Stop::Stepped {
+            if let Some(stop) = self.check_before(cpu) {
+                return Ok(stop);
+            }
+        }
+        Ok(stop)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::RandomAccessMemory;
+    use crate::Variant;
+
+    fn new_processor() -> Processor<RandomAccessMemory> {
+        let mut cpu = Processor::with_memory(RandomAccessMemory::new(0x10000));
+        let program: [(u16, &[u8]); 4] = [
+            // LDX #0; JSR $0210; INX; JMP $0202
+            (
+                0x0200,
+                &[0xa2, 0x00, 0x20, 0x10, 0x02, 0xe8, 0x4c, 0x02, 0x02],
+            ),
+            // JSR $0220; RTS
+            (0x0210, &[0x20, 0x20, 0x02, 0x60]),
+            // INC $0300; RTS
+            (0x0220, &[0xee, 0x00, 0x03, 0x60]),
+            (0x0300, &[0x00]),
+        ];
+        for (addr, bytes) in program {
+            for (i, byte) in bytes.iter().enumerate() {
+                cpu.memory.write(addr + i as u16, *byte);
+            }
+        }
+        cpu.core.pc = 0x0200;
+        cpu
+    }
+
+    #[test]
+    fn test_breakpoints() {
+        let mut debugger = Debugger::new();
+        let mut cpu = new_processor();
+        let id = debugger.add_breakpoint(0x0220);
+
+        assert_eq!(
+            debugger.run(&mut cpu).unwrap(),
+            Stop::Breakpoint { id, pc: 0x0220 }
+        );
+        // Continuing runs the instruction at the breakpoint
+        assert_eq!(
+            debugger.run(&mut cpu).unwrap(),
+            Stop::Breakpoint { id, pc: 0x0220 }
+        );
+        assert_eq!(cpu.memory.read(0x0300), 1);
+
+        assert!(debugger.remove(id));
+        assert!(!debugger.remove(id));
+        let id = debugger
+            .add_conditional_breakpoint(0x0205, "[$0300] == 3 && X == 2")
+            .unwrap();
+        assert_eq!(
+            debugger.run(&mut cpu).unwrap(),
+            Stop::Breakpoint { id, pc: 0x0205 }
+        );
+        assert_eq!(cpu.core.x, 2);
+
+        debugger.clear();
+        let id = debugger.add_condition("X == 5").unwrap();
+        assert_eq!(
+            debugger.run(&mut cpu).unwrap(),
+            Stop::Breakpoint { id, pc: 0x0206 }
+        );
+
+        assert!(debugger.add_condition("X ==").is_err());
+    }
+
+    #[test]
+    fn test_watchpoints() {
+        let mut debugger = Debugger::new();
+        let mut cpu = new_processor();
+        let id = debugger.add_watchpoint(0x02ff..=0x0300, WatchKind::Write);
+
+        let stop = debugger.run(&mut cpu).unwrap();
+        let Stop::Watchpoint { id: hit, access } = stop else {
+            panic!("expected a watchpoint, got {:?}", stop);
+        };
+        assert_eq!(hit, id);
+        assert_eq!(access.addr, 0x0300);
+        assert!(access.kind.is_write());
+        assert_eq!(cpu.core.pc, 0x0223);
+
+        debugger.clear();
+        let id = debugger.add_watchpoint(0x0300..=0x0300, WatchKind::Read);
+        let stop = debugger.run(&mut cpu).unwrap();
+        assert_eq!(
+            stop,
+            Stop::Watchpoint {
+                id,
+                access: BusAccess {
+                    addr: 0x0300,
+                    value: 1,
+                    kind: AccessKind::Read
+                }
+            }
+        );
+
+        debugger.clear();
+        let id = debugger.add_watchpoint(0x0210..=0x0213, WatchKind::Execute);
+        let stop = debugger.run(&mut cpu).unwrap();
+        assert_eq!(
+            stop,
+            Stop::Watchpoint {
+                id,
+                access: BusAccess {
+                    addr: 0x0213,
+                    value: 0x60,
+                    kind: AccessKind::OpcodeFetch
+                }
+            }
+        );
+    }
+
+    #[test]
+    fn test_stepping() {
+        let mut debugger = Debugger::new();
+        let mut cpu = new_processor();
+        assert_eq!(debugger.step_into(&mut cpu).unwrap(), Stop::Stepped);
+        assert_eq!(cpu.core.pc, 0x0202);
+
+        // Steps over both levels of subroutine
+        assert_eq!(debugger.step_over(&mut cpu).unwrap(), Stop::Stepped);
+        assert_eq!(cpu.core.pc, 0x0205);
+        assert_eq!(cpu.memory.read(0x0300), 1);
+
+        // Not a JSR, so it's a single step
+        assert_eq!(debugger.step_over(&mut cpu).unwrap(), Stop::Stepped);
+        assert_eq!(cpu.core.pc, 0x0206);
+
+        // Into JSR $0210 and JSR $0220, then out of each in turn
+        debugger.step_into(&mut cpu).unwrap();
+        debugger.step_into(&mut cpu).unwrap();
+        debugger.step_into(&mut cpu).unwrap();
+        assert_eq!(cpu.core.pc, 0x0220);
+        assert_eq!(debugger.step_out(&mut cpu).unwrap(), Stop::Stepped);
+        assert_eq!(cpu.core.pc, 0x0213);
+        assert_eq!(debugger.step_out(&mut cpu).unwrap(), Stop::Stepped);
+        assert_eq!(cpu.core.pc, 0x0205);
+
+        // A breakpoint inside the subroutine interrupts a step over
+        let id = debugger.add_breakpoint(0x0223);
+        debugger.step_into(&mut cpu).unwrap();
+        debugger.step_into(&mut cpu).unwrap();
+        assert_eq!(
+            debugger.step_over(&mut cpu).unwrap(),
+            Stop::Breakpoint { id, pc: 0x0223 }
+        );
+    }
+
+    #[test]
+    fn test_halted() {
+        let debugger = Debugger::new();
+        let mut cpu = new_processor();
+        cpu.memory.write(0x0200, 0x02);
+        assert_eq!(debugger.run(&mut cpu).unwrap(), Stop::Halted);
+    }
+
+    #[test]
+    fn test_waiting() {
+        let debugger = Debugger::new();
+        let mut cpu = Processor::with_variant(RandomAccessMemory::new(0x10000), Variant::Cmos65C02);
+        cpu.core.pc = 0x0200;
+        cpu.memory.write(0x0200, 0xcb);
+        assert_eq!(debugger.run(&mut cpu).unwrap(), Stop::Waiting);
+        assert_eq!(debugger.step_into(&mut cpu).unwrap(), Stop::Waiting);
+    }
+
+    #[test]
+    fn test_step_out_with_stack_wrap() {
+        // JSR $0210 pushes the return address over the bottom of the stack
+        let debugger = Debugger::new();
+        let mut cpu = new_processor();
+        cpu.core.sp = 0x01;
+        debugger.step_into(&mut cpu).unwrap();
+        debugger.step_into(&mut cpu).unwrap();
+        assert_eq!((cpu.core.pc, cpu.core.sp), (0x0210, 0xff));
+        assert_eq!(debugger.step_out(&mut cpu).unwrap(), Stop::Stepped);
+        assert_eq!((cpu.core.pc, cpu.core.sp), (0x0205, 0x01));
+    }
+}
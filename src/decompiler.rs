@@ -37,10 +37,19 @@ impl<'a> Decompiler<'a> {
         u16::from_le_bytes([self.program[self.pc + 1], self.program[self.pc + 2]])
     }
 
+    pub(crate) fn implied(&self, _cycles: usize) {}
+
+    pub(crate) fn operand_address(&self, _operand: &str) {}
+
     pub(crate) fn immediate(&self) -> String {
         format!("#${:02x}", self.arg_u8())
     }
 
+    pub(crate) fn relative(&self) -> String {
+        let target = (self.pc + 2).wrapping_add(self.arg_u8() as i8 as usize);
+        format!("${:04x}", target & 0xffff)
+    }
+
     pub(crate) fn zero_page(&self) -> String {
         format!("${:02x}", self.arg_u8())
     }
@@ -57,6 +66,10 @@ impl<'a> Decompiler<'a> {
         format!("${:04x}", self.arg_u16())
     }
 
+    pub(crate) fn absolute_for_jsr(&self) -> String {
+        self.absolute()
+    }
+
     pub(crate) fn absolute_x(&self) -> String {
         format!("${:04x},X", self.arg_u16())
     }
@@ -90,9 +103,13 @@ impl<'a> Decompiler<'a> {
     }
 
     decompile_mnemonic!(adc, arg);
+    decompile_mnemonic!(alr, arg);
+    decompile_mnemonic!(anc, arg);
     decompile_mnemonic!(and, arg);
+    decompile_mnemonic!(arr, arg);
     decompile_mnemonic!(asla, A);
     decompile_mnemonic!(asl, arg);
+    decompile_mnemonic!(axs, arg);
     decompile_mnemonic!(bcc, arg);
     decompile_mnemonic!(bcs, arg);
     decompile_mnemonic!(beq, arg);
@@ -119,14 +136,17 @@ impl<'a> Decompiler<'a> {
     decompile_mnemonic!(inx);
     decompile_mnemonic!(iny);
     decompile_mnemonic!(isc, arg);
+    decompile_mnemonic!(jam);
     decompile_mnemonic!(jmp, arg);
     decompile_mnemonic!(jsr, arg);
+    decompile_mnemonic!(las, arg);
     decompile_mnemonic!(lax, arg);
     decompile_mnemonic!(lda, arg);
     decompile_mnemonic!(ldx, arg);
     decompile_mnemonic!(ldy, arg);
     decompile_mnemonic!(lsr, arg);
     decompile_mnemonic!(lsra, A);
+    decompile_mnemonic!(lxa, arg);
     decompile_mnemonic!(nop);
     decompile_mnemonic!(nop_addr, arg);
     decompile_mnemonic!(ora, arg);
@@ -143,6 +163,9 @@ impl<'a> Decompiler<'a> {
     decompile_mnemonic!(rti);
     decompile_mnemonic!(rts);
     decompile_mnemonic!(sax, arg);
+    decompile_mnemonic!(sha, arg);
+    decompile_mnemonic!(shx, arg);
+    decompile_mnemonic!(shy, arg);
     decompile_mnemonic!(sbc, arg);
     decompile_mnemonic!(sec);
     decompile_mnemonic!(sed);
@@ -152,16 +175,25 @@ impl<'a> Decompiler<'a> {
     decompile_mnemonic!(sta, arg);
     decompile_mnemonic!(stx, arg);
     decompile_mnemonic!(sty, arg);
+    decompile_mnemonic!(tas, arg);
     decompile_mnemonic!(tax);
     decompile_mnemonic!(tay);
     decompile_mnemonic!(tsx);
     decompile_mnemonic!(txa);
     decompile_mnemonic!(txs);
     decompile_mnemonic!(tya);
+    decompile_mnemonic!(xaa, arg);
 
     pub fn decompile(mut self) {
         while self.pc < self.program.len() {
-            self.pc += decode_6502!(self.program[self.pc]; self).0 as usize;
+            let opcode = self.program[self.pc];
+            self.pc += match decode_6502!(opcode; self) {
+                Some(decoded) => decoded.length as usize,
+                None => {
+                    println!(".byte ${:02x}", opcode);
+                    1
+                }
+            };
         }
     }
 }
@@ -0,0 +1,49 @@
+use std::fmt::Display;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    InvalidOpcode { pc: u16, opcode: u8 },
+    // A header field that holds a value we can't load
+    InvalidHeader { field: &'static str, value: u32 },
+    // A valid ROM for a mapper we don't emulate
+    UnsupportedMapper { mapper: u16 },
+    // The ROM is shorter than its header says it should be
+    TruncatedRom { expected: usize, actual: usize },
+    // position is the byte offset into the expression text
+    InvalidExpression { position: usize, reason: &'static str },
+    // A snapshot that is corrupt or doesn't fit what it is loaded into
+    InvalidSnapshot { reason: &'static str },
+    // position is the byte offset into the JSON text
+    InvalidJson { position: usize, reason: &'static str },
+    // A test case that parsed but is missing something we need
+    InvalidTestCase { index: usize, reason: &'static str },
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::InvalidOpcode { pc, opcode } => {
+                write!(f, "invalid opcode 0x{:02x} at 0x{:04x}", opcode, pc)
+            }
+            Error::InvalidHeader { field, value } => {
+                write!(f, "invalid {} in ROM header: 0x{:x}", field, value)
+            }
+            Error::UnsupportedMapper { mapper } => write!(f, "mapper {} isn't supported", mapper),
+            Error::TruncatedRom { expected, actual } => {
+                write!(f, "ROM is {} bytes long, expected {}", actual, expected)
+            }
+            Error::InvalidExpression { position, reason } => {
+                write!(f, "invalid expression at offset {}: {}", position, reason)
+            }
+            Error::InvalidSnapshot { reason } => write!(f, "invalid snapshot: {}", reason),
+            Error::InvalidJson { position, reason } => {
+                write!(f, "invalid JSON at offset {}: {}", position, reason)
+            }
+            Error::InvalidTestCase { index, reason } => {
+                write!(f, "invalid test case {}: {}", index, reason)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
@@ -0,0 +1,72 @@
+use std::collections::VecDeque;
+
+use crate::processor::Core;
+
+// What an instruction (or interrupt, or cycle spent waiting) changed, so it
+// can be undone: the processor state from before it started and the old value
+// of every byte it wrote, in the order they were written. A write to a device
+// register can't be taken back, and makes the entry impossible to undo.
+#[derive(Clone, Debug)]
+pub(crate) struct Entry {
+    pub core: Core,
+    pub cycles: usize,
+    pub jumped: bool,
+    pub nmi_pending: bool,
+    pub halted: bool,
+    pub waiting: bool,
+    pub writes: Vec<(u16, u8)>,
+    pub undoable: bool,
+}
+
+// The most recent entries, oldest first. Once it is full, starting an entry
+// forgets the oldest one.
+#[derive(Clone, Debug)]
+pub(crate) struct History {
+    entries: VecDeque<Entry>,
+    capacity: usize,
+}
+
+impl History {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    pub fn begin(&mut self, entry: Entry) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+    }
+
+    // old_value is None when it couldn't be peeked at
+    pub fn record_write(&mut self, addr: u16, old_value: Option<u8>) {
+        if let Some(entry) = self.entries.back_mut() {
+            match old_value {
+                Some(old_value) => entry.writes.push((addr, old_value)),
+                None => entry.undoable = false,
+            }
+        }
+    }
+
+    // The most recent entry, unless it can't be undone
+    pub fn pop(&mut self) -> Option<Entry> {
+        match self.entries.back() {
+            Some(entry) if entry.undoable => self.entries.pop_back(),
+            _ => None,
+        }
+    }
+}
@@ -3,277 +3,667 @@ macro_rules! decode {
     (
         $opcode:expr;
         $self:ident,
-        $($code:literal => ($cycles:literal, $operation:ident, $($addressing:ident)?)),+
+        $($code:literal => $($undocumented:ident)? ($cycles:literal, $operation:ident, $($addressing:ident)?)),+
     ) => {
         match $opcode {
             $($code => {
-                $(let $addressing = $self.$addressing();)?
+                $crate::decode!(@implied $self, $cycles $(, $addressing)?);
+                $(
+                    let $addressing = $self.$addressing();
+                    $self.operand_address(&$addressing);
+                )?
                 $self.$operation($($addressing)?);
-                let length = 1 $( - 1 + $crate::instructions::length::$addressing)?;
-                (length, $cycles)
+                let operation = $crate::instructions::operation::$operation;
+                Some($crate::instructions::Decoded {
+                    length: 1 $( - 1 + $crate::instructions::length::$addressing)?,
+                    cycles: $cycles,
+                    mnemonic: operation.mnemonic,
+                    mode: $crate::decode!(@mode operation $(, $addressing)?),
+                })
             })+
-            _ => panic!("invalid opcode 0x{:x}", $opcode)
+            // Tables that define every opcode never reach this arm
+            #[allow(unreachable_patterns)]
+            _ => None
         }
-    }
+    };
+    // Instructions without an operand still spend a cycle reading one
+    (@implied $self:ident, $cycles:literal) => {
+        $self.implied($cycles)
+    };
+    (@implied $self:ident, $cycles:literal, $addressing:ident) => {};
+    (@mode $operation:ident) => {
+        $operation.mode()
+    };
+    (@mode $operation:ident, $addressing:ident) => {
+        $crate::instructions::mode::$addressing
+    };
+}
+
+// Builds an array of OpcodeInfo, one entry per opcode
+macro_rules! opcode_table {
+    (
+        $($code:literal => $($undocumented:ident)? ($cycles:literal, $operation:ident, $($addressing:ident)?)),+
+    ) => {{
+        let mut table = [$crate::OpcodeInfo::UNDEFINED; 256];
+        $(
+            let operation = $crate::instructions::operation::$operation;
+            table[$code] = $crate::OpcodeInfo {
+                opcode: $code,
+                mnemonic: operation.mnemonic,
+                mode: $crate::decode!(@mode operation $(, $addressing)?),
+                length: 1 $( - 1 + $crate::instructions::length::$addressing)?,
+                cycles: $cycles,
+                page_cross_penalty: false $(|| $crate::instructions::page_penalty::$addressing)?,
+                official: $crate::instructions::opcode_table!(@official $($undocumented)?),
+                access: operation.access,
+            };
+        )+
+        table
+    }};
+    (@official) => {
+        true
+    };
+    (@official undocumented) => {
+        false
+    };
 }
+pub(crate) use opcode_table;
 
 #[macro_export]
 macro_rules! decode_6502 {
     ($opcode:expr; $self:ident) => {
-        $crate::decode! {
-            $opcode;
-            $self,
+        $crate::instructions::opcodes_6502!(decode! { $opcode; $self, })
+    };
+}
+
+// Passes every opcode to the callback macro after its own arguments
+macro_rules! opcodes_6502 {
+    ($($callback:ident)::+! { $($args:tt)* }) => {
+        $crate::$($callback)::+! {
+            $($args)*
 
             0x00 => (7, brk,),
             0x01 => (6, ora, indexed_indirect),
-            0x03 => (8, slo, indexed_indirect),
-            0x04 => (3, nop_addr, zero_page), // UNDOCUMENTED
+            0x02 => undocumented (2, jam, ),
+            0x03 => undocumented (8, slo, indexed_indirect),
+            0x04 => undocumented (3, nop_addr, zero_page),
             0x05 => (3, ora, zero_page),
             0x06 => (5, asl, zero_page),
-            0x07 => (5, slo, zero_page),
+            0x07 => undocumented (5, slo, zero_page),
             0x08 => (3, php,),
             0x09 => (2, ora, immediate),
             0x0a => (2, asla, ),
-            0x0c => (4, nop_addr, absolute), // UNDOCUMENTED
+            0x0b => undocumented (2, anc, immediate),
+            0x0c => undocumented (4, nop_addr, absolute),
             0x0d => (4, ora, absolute),
             0x0e => (6, asl, absolute),
-            0x0f => (6, slo, absolute),
+            0x0f => undocumented (6, slo, absolute),
 
-            0x10 => (2, bpl, immediate),
+            0x10 => (2, bpl, relative),
             0x11 => (5, ora, indirect_indexed),
-            0x13 => (8, slo, indirect_indexed_for_store),
-            0x14 => (4, nop_addr, zero_page_x), // UNDOCUMENTED
+            0x12 => undocumented (2, jam, ),
+            0x13 => undocumented (8, slo, indirect_indexed_for_store),
+            0x14 => undocumented (4, nop_addr, zero_page_x),
             0x15 => (4, ora, zero_page_x),
             0x16 => (6, asl, zero_page_x),
-            0x17 => (6, slo, zero_page_x),
+            0x17 => undocumented (6, slo, zero_page_x),
             0x18 => (2, clc, ),
             0x19 => (4, ora, absolute_y),
-            0x1a => (2, nop, ), // UNDOCUMENTED
-            0x1b => (7, slo, absolute_y_for_store),
-            0x1c => (4, nop_addr, absolute_x), // UNDOCUMENTED
+            0x1a => undocumented (2, nop, ),
+            0x1b => undocumented (7, slo, absolute_y_for_store),
+            0x1c => undocumented (4, nop_addr, absolute_x),
             0x1d => (4, ora, absolute_x),
-            0x1e => (7, asl, absolute_x),
-            0x1f => (7, slo, absolute_x_for_store),
+            0x1e => (7, asl, absolute_x_for_store),
+            0x1f => undocumented (7, slo, absolute_x_for_store),
 
-            0x20 => (6, jsr, absolute),
+            0x20 => (6, jsr, absolute_for_jsr),
             0x21 => (6, and, indexed_indirect),
-            0x23 => (8, rla, indexed_indirect),
+            0x22 => undocumented (2, jam, ),
+            0x23 => undocumented (8, rla, indexed_indirect),
             0x24 => (3, bit, zero_page),
             0x25 => (3, and, zero_page),
             0x26 => (5, rol, zero_page),
-            0x27 => (5, rla, zero_page),
+            0x27 => undocumented (5, rla, zero_page),
             0x28 => (4, plp, ),
             0x29 => (2, and, immediate),
             0x2a => (2, rola, ),
+            0x2b => undocumented (2, anc, immediate),
             0x2c => (4, bit, absolute),
             0x2d => (4, and, absolute),
             0x2e => (6, rol, absolute),
-            0x2f => (6, rla, absolute),
+            0x2f => undocumented (6, rla, absolute),
 
-            0x30 => (2, bmi, immediate),
+            0x30 => (2, bmi, relative),
             0x31 => (5, and, indirect_indexed),
-            0x33 => (8, rla, indirect_indexed_for_store),
-            0x34 => (4, nop_addr, zero_page_x), // UNDOCUMENTED
+            0x32 => undocumented (2, jam, ),
+            0x33 => undocumented (8, rla, indirect_indexed_for_store),
+            0x34 => undocumented (4, nop_addr, zero_page_x),
             0x35 => (4, and, zero_page_x),
             0x36 => (6, rol, zero_page_x),
-            0x37 => (6, rla, zero_page_x),
+            0x37 => undocumented (6, rla, zero_page_x),
             0x38 => (2, sec, ),
             0x39 => (4, and, absolute_y),
-            0x3a => (2, nop, ), // UNDOCUMENTED
-            0x3b => (7, rla, absolute_y_for_store),
-            0x3c => (4, nop_addr, absolute_x), // UNDOCUMENTED
+            0x3a => undocumented (2, nop, ),
+            0x3b => undocumented (7, rla, absolute_y_for_store),
+            0x3c => undocumented (4, nop_addr, absolute_x),
             0x3d => (4, and, absolute_x),
-            0x3e => (7, rol, absolute_x),
-            0x3f => (7, rla, absolute_x_for_store),
+            0x3e => (7, rol, absolute_x_for_store),
+            0x3f => undocumented (7, rla, absolute_x_for_store),
 
             0x40 => (6, rti, ),
             0x41 => (6, eor, indexed_indirect),
-            0x43 => (8, sre, indexed_indirect),
-            0x44 => (3, nop_addr, zero_page), // UNDOCUMENTED
+            0x42 => undocumented (2, jam, ),
+            0x43 => undocumented (8, sre, indexed_indirect),
+            0x44 => undocumented (3, nop_addr, zero_page),
             0x45 => (3, eor, zero_page),
             0x46 => (5, lsr, zero_page),
-            0x47 => (5, sre, zero_page),
+            0x47 => undocumented (5, sre, zero_page),
             0x48 => (3, pha, ),
             0x49 => (2, eor, immediate),
             0x4a => (2, lsra, ),
+            0x4b => undocumented (2, alr, immediate),
             0x4c => (3, jmp, absolute),
             0x4d => (4, eor, absolute),
             0x4e => (6, lsr, absolute),
-            0x4f => (6, sre, absolute),
+            0x4f => undocumented (6, sre, absolute),
 
-            0x50 => (2, bvc, immediate),
+            0x50 => (2, bvc, relative),
             0x51 => (5, eor, indirect_indexed),
-            0x53 => (8, sre, indirect_indexed_for_store),
-            0x54 => (4, nop_addr, zero_page_x), // UNDOCUMENTED
+            0x52 => undocumented (2, jam, ),
+            0x53 => undocumented (8, sre, indirect_indexed_for_store),
+            0x54 => undocumented (4, nop_addr, zero_page_x),
             0x55 => (4, eor, zero_page_x),
             0x56 => (6, lsr, zero_page_x),
-            0x57 => (6, sre, zero_page_x),
+            0x57 => undocumented (6, sre, zero_page_x),
             0x58 => (2, cli, ),
             0x59 => (4, eor, absolute_y),
-            0x5a => (2, nop, ), // UNDOCUMENTED
-            0x5b => (7, sre, absolute_y_for_store),
-            0x5c => (4, nop_addr, absolute_x), // UNDOCUMENTED
+            0x5a => undocumented (2, nop, ),
+            0x5b => undocumented (7, sre, absolute_y_for_store),
+            0x5c => undocumented (4, nop_addr, absolute_x),
             0x5d => (4, eor, absolute_x),
-            0x5e => (7, lsr, absolute_x),
-            0x5f => (7, sre, absolute_x_for_store),
+            0x5e => (7, lsr, absolute_x_for_store),
+            0x5f => undocumented (7, sre, absolute_x_for_store),
 
             0x60 => (6, rts, ),
             0x61 => (6, adc, indexed_indirect),
-            0x63 => (8, rra, indexed_indirect),
-            0x64 => (3, nop_addr, zero_page), // UNDOCUMENTED
+            0x62 => undocumented (2, jam, ),
+            0x63 => undocumented (8, rra, indexed_indirect),
+            0x64 => undocumented (3, nop_addr, zero_page),
             0x65 => (3, adc, zero_page),
             0x66 => (5, ror, zero_page),
-            0x67 => (5, rra, zero_page),
+            0x67 => undocumented (5, rra, zero_page),
             0x68 => (4, pla,),
             0x69 => (2, adc, immediate),
             0x6a => (2, rora, ),
+            0x6b => undocumented (2, arr, immediate),
             0x6c => (5, jmp, indirect),
             0x6d => (4, adc, absolute),
             0x6e => (6, ror, absolute),
-            0x6f => (6, rra, absolute),
+            0x6f => undocumented (6, rra, absolute),
+
+            0x70 => (2, bvs, relative),
+            0x71 => (5, adc, indirect_indexed),
+            0x72 => undocumented (2, jam, ),
+            0x73 => undocumented (8, rra, indirect_indexed_for_store),
+            0x74 => undocumented (4, nop_addr, zero_page_x),
+            0x75 => (4, adc, zero_page_x),
+            0x76 => (6, ror, zero_page_x),
+            0x77 => undocumented (6, rra, zero_page_x),
+            0x78 => (2, sei, ),
+            0x79 => (4, adc, absolute_y),
+            0x7a => undocumented (2, nop, ),
+            0x7b => undocumented (7, rra, absolute_y_for_store),
+            0x7c => undocumented (4, nop_addr, absolute_x),
+            0x7d => (4, adc, absolute_x),
+            0x7e => (7, ror, absolute_x_for_store),
+            0x7f => undocumented (7, rra, absolute_x_for_store),
+
+            0x80 => undocumented (2, nop_addr, immediate),
+            0x81 => (6, sta, indexed_indirect),
+            0x82 => undocumented (2, nop_addr, immediate),
+            0x83 => undocumented (6, sax, indexed_indirect),
+            0x84 => (3, sty, zero_page),
+            0x85 => (3, sta, zero_page),
+            0x86 => (3, stx, zero_page),
+            0x87 => undocumented (3, sax, zero_page),
+            0x88 => (2, dey, ),
+            0x89 => undocumented (2, nop_addr, immediate),
+            0x8a => (2, txa, ),
+            0x8b => undocumented (2, xaa, immediate),
+            0x8c => (4, sty, absolute),
+            0x8d => (4, sta, absolute),
+            0x8e => (4, stx, absolute),
+            0x8f => undocumented (4, sax, absolute),
+
+            0x90 => (2, bcc, relative),
+            0x91 => (6, sta, indirect_indexed_for_store),
+            0x92 => undocumented (2, jam, ),
+            0x93 => undocumented (6, sha, indirect_indexed_for_store),
+            0x94 => (4, sty, zero_page_x),
+            0x95 => (4, sta, zero_page_x),
+            0x96 => (4, stx, zero_page_y),
+            0x97 => undocumented (4, sax, zero_page_y),
+            0x98 => (2, tya, ),
+            0x99 => (5, sta, absolute_y_for_store),
+            0x9a => (2, txs, ),
+            0x9b => undocumented (5, tas, absolute_y_for_store),
+            0x9c => undocumented (5, shy, absolute_x_for_store),
+            0x9d => (5, sta, absolute_x_for_store),
+            0x9e => undocumented (5, shx, absolute_y_for_store),
+            0x9f => undocumented (5, sha, absolute_y_for_store),
+
+            0xa0 => (2, ldy, immediate),
+            0xa1 => (6, lda, indexed_indirect),
+            0xa2 => (2, ldx, immediate),
+            0xa3 => undocumented (6, lax, indexed_indirect),
+            0xa4 => (3, ldy, zero_page),
+            0xa5 => (3, lda, zero_page),
+            0xa6 => (3, ldx, zero_page),
+            0xa7 => undocumented (3, lax, zero_page),
+            0xa8 => (2, tay, ),
+            0xa9 => (2, lda, immediate),
+            0xaa => (2, tax, ),
+            0xab => undocumented (2, lxa, immediate),
+            0xac => (4, ldy, absolute),
+            0xad => (4, lda, absolute),
+            0xae => (4, ldx, absolute),
+            0xaf => undocumented (4, lax, absolute),
+
+            0xb0 => (2, bcs, relative),
+            0xb1 => (5, lda, indirect_indexed),
+            0xb2 => undocumented (2, jam, ),
+            0xb3 => undocumented (5, lax, indirect_indexed),
+            0xb4 => (4, ldy, zero_page_x),
+            0xb5 => (4, lda, zero_page_x),
+            0xb6 => (4, ldx, zero_page_y),
+            0xb7 => undocumented (4, lax, zero_page_y),
+            0xb8 => (2, clv, ),
+            0xb9 => (4, lda, absolute_y),
+            0xba => (2, tsx, ),
+            0xbb => undocumented (4, las, absolute_y),
+            0xbc => (4, ldy, absolute_x),
+            0xbd => (4, lda, absolute_x),
+            0xbe => (4, ldx, absolute_y),
+            0xbf => undocumented (4, lax, absolute_y),
+
+            0xc0 => (2, cpy, immediate),
+            0xc1 => (6, cmp, indexed_indirect),
+            0xc2 => undocumented (2, nop_addr, immediate),
+            0xc3 => undocumented (8, dcp, indexed_indirect),
+            0xc4 => (3, cpy, zero_page),
+            0xc5 => (3, cmp, zero_page),
+            0xc6 => (5, dec, zero_page),
+            0xc7 => undocumented (5, dcp, zero_page),
+            0xc8 => (2, iny, ),
+            0xc9 => (2, cmp, immediate),
+            0xca => (2, dex, ),
+            0xcb => undocumented (2, axs, immediate),
+            0xcc => (4, cpy, absolute),
+            0xcd => (4, cmp, absolute),
+            0xce => (6, dec, absolute),
+            0xcf => undocumented (6, dcp, absolute),
+
+            0xd0 => (2, bne, relative),
+            0xd1 => (5, cmp, indirect_indexed),
+            0xd2 => undocumented (2, jam, ),
+            0xd3 => undocumented (8, dcp, indirect_indexed_for_store),
+            0xd4 => undocumented (4, nop_addr, zero_page_x),
+            0xd5 => (4, cmp, zero_page_x),
+            0xd6 => (6, dec, zero_page_x),
+            0xd7 => undocumented (6, dcp, zero_page_x),
+            0xd8 => (2, cld, ),
+            0xd9 => (4, cmp, absolute_y),
+            0xda => undocumented (2, nop, ),
+            0xdb => undocumented (7, dcp, absolute_y_for_store),
+            0xdc => undocumented (4, nop_addr, absolute_x),
+            0xdd => (4, cmp, absolute_x),
+            0xde => (7, dec, absolute_x_for_store),
+            0xdf => undocumented (7, dcp, absolute_x_for_store),
+
+            0xe0 => (2, cpx, immediate),
+            0xe1 => (6, sbc, indexed_indirect),
+            0xe2 => undocumented (2, nop_addr, immediate),
+            0xe3 => undocumented (8, isc, indexed_indirect),
+            0xe4 => (3, cpx, zero_page),
+            0xe5 => (3, sbc, zero_page),
+            0xe6 => (5, inc, zero_page),
+            0xe7 => undocumented (5, isc, zero_page),
+            0xe8 => (2, inx, ),
+            0xe9 => (2, sbc, immediate),
+            0xea => (2, nop, ),
+            0xeb => undocumented (2, sbc, immediate),
+            0xec => (4, cpx, absolute),
+            0xed => (4, sbc, absolute),
+            0xee => (6, inc, absolute),
+            0xef => undocumented (6, isc, absolute),
+
+            0xf0 => (2, beq, relative),
+            0xf1 => (5, sbc, indirect_indexed),
+            0xf2 => undocumented (2, jam, ),
+            0xf3 => undocumented (8, isc, indirect_indexed_for_store),
+            0xf4 => undocumented (4, nop_addr, zero_page_x),
+            0xf5 => (4, sbc, zero_page_x),
+            0xf6 => (6, inc, zero_page_x),
+            0xf7 => undocumented (6, isc, zero_page_x),
+            0xf8 => (2, sed, ),
+            0xf9 => (4, sbc, absolute_y),
+            0xfa => undocumented (2, nop, ),
+            0xfb => undocumented (7, isc, absolute_y_for_store),
+            0xfc => undocumented (4, nop_addr, absolute_x),
+            0xfd => (4, sbc, absolute_x),
+            0xfe => (7, inc, absolute_x_for_store),
+            0xff => undocumented (7, isc, absolute_x_for_store)
+        }
+    };
+}
+pub(crate) use opcodes_6502;
+
+#[macro_export]
+macro_rules! decode_65c02 {
+    ($opcode:expr; $self:ident) => {
+        $crate::instructions::opcodes_65c02!(decode! { $opcode; $self, })
+    };
+}
+
+// Passes every opcode to the callback macro after its own arguments
+macro_rules! opcodes_65c02 {
+    ($($callback:ident)::+! { $($args:tt)* }) => {
+        $crate::$($callback)::+! {
+            $($args)*
+
+            0x00 => (7, brk, ),
+            0x01 => (6, ora, indexed_indirect),
+            0x02 => undocumented (2, nop_addr, immediate),
+            0x03 => undocumented (1, nop, ),
+            0x04 => (5, tsb, zero_page),
+            0x05 => (3, ora, zero_page),
+            0x06 => (5, asl, zero_page),
+            0x07 => (5, rmb0, zero_page),
+            0x08 => (3, php, ),
+            0x09 => (2, ora, immediate),
+            0x0a => (2, asla, ),
+            0x0b => undocumented (1, nop, ),
+            0x0c => (6, tsb, absolute),
+            0x0d => (4, ora, absolute),
+            0x0e => (6, asl, absolute),
+            0x0f => (5, bbr0, zero_page_relative),
+
+            0x10 => (2, bpl, relative),
+            0x11 => (5, ora, indirect_indexed),
+            0x12 => (5, ora, zero_page_indirect),
+            0x13 => undocumented (1, nop, ),
+            0x14 => (5, trb, zero_page),
+            0x15 => (4, ora, zero_page_x),
+            0x16 => (6, asl, zero_page_x),
+            0x17 => (5, rmb1, zero_page),
+            0x18 => (2, clc, ),
+            0x19 => (4, ora, absolute_y),
+            0x1a => (2, inca, ),
+            0x1b => undocumented (1, nop, ),
+            0x1c => (6, trb, absolute),
+            0x1d => (4, ora, absolute_x),
+            0x1e => (6, asl, absolute_x),
+            0x1f => (5, bbr1, zero_page_relative),
+
+            0x20 => (6, jsr, absolute_for_jsr),
+            0x21 => (6, and, indexed_indirect),
+            0x22 => undocumented (2, nop_addr, immediate),
+            0x23 => undocumented (1, nop, ),
+            0x24 => (3, bit, zero_page),
+            0x25 => (3, and, zero_page),
+            0x26 => (5, rol, zero_page),
+            0x27 => (5, rmb2, zero_page),
+            0x28 => (4, plp, ),
+            0x29 => (2, and, immediate),
+            0x2a => (2, rola, ),
+            0x2b => undocumented (1, nop, ),
+            0x2c => (4, bit, absolute),
+            0x2d => (4, and, absolute),
+            0x2e => (6, rol, absolute),
+            0x2f => (5, bbr2, zero_page_relative),
+
+            0x30 => (2, bmi, relative),
+            0x31 => (5, and, indirect_indexed),
+            0x32 => (5, and, zero_page_indirect),
+            0x33 => undocumented (1, nop, ),
+            0x34 => (4, bit, zero_page_x),
+            0x35 => (4, and, zero_page_x),
+            0x36 => (6, rol, zero_page_x),
+            0x37 => (5, rmb3, zero_page),
+            0x38 => (2, sec, ),
+            0x39 => (4, and, absolute_y),
+            0x3a => (2, deca, ),
+            0x3b => undocumented (1, nop, ),
+            0x3c => (4, bit, absolute_x),
+            0x3d => (4, and, absolute_x),
+            0x3e => (6, rol, absolute_x),
+            0x3f => (5, bbr3, zero_page_relative),
+
+            0x40 => (6, rti, ),
+            0x41 => (6, eor, indexed_indirect),
+            0x42 => undocumented (2, nop_addr, immediate),
+            0x43 => undocumented (1, nop, ),
+            0x44 => undocumented (3, nop_addr, zero_page),
+            0x45 => (3, eor, zero_page),
+            0x46 => (5, lsr, zero_page),
+            0x47 => (5, rmb4, zero_page),
+            0x48 => (3, pha, ),
+            0x49 => (2, eor, immediate),
+            0x4a => (2, lsra, ),
+            0x4b => undocumented (1, nop, ),
+            0x4c => (3, jmp, absolute),
+            0x4d => (4, eor, absolute),
+            0x4e => (6, lsr, absolute),
+            0x4f => (5, bbr4, zero_page_relative),
+
+            0x50 => (2, bvc, relative),
+            0x51 => (5, eor, indirect_indexed),
+            0x52 => (5, eor, zero_page_indirect),
+            0x53 => undocumented (1, nop, ),
+            0x54 => undocumented (4, nop_addr, zero_page_x),
+            0x55 => (4, eor, zero_page_x),
+            0x56 => (6, lsr, zero_page_x),
+            0x57 => (5, rmb5, zero_page),
+            0x58 => (2, cli, ),
+            0x59 => (4, eor, absolute_y),
+            0x5a => (3, phy, ),
+            0x5b => undocumented (1, nop, ),
+            0x5c => undocumented (8, nop_long, absolute),
+            0x5d => (4, eor, absolute_x),
+            0x5e => (6, lsr, absolute_x),
+            0x5f => (5, bbr5, zero_page_relative),
+
+            0x60 => (6, rts, ),
+            0x61 => (6, adc, indexed_indirect),
+            0x62 => undocumented (2, nop_addr, immediate),
+            0x63 => undocumented (1, nop, ),
+            0x64 => (3, stz, zero_page),
+            0x65 => (3, adc, zero_page),
+            0x66 => (5, ror, zero_page),
+            0x67 => (5, rmb6, zero_page),
+            0x68 => (4, pla, ),
+            0x69 => (2, adc, immediate),
+            0x6a => (2, rora, ),
+            0x6b => undocumented (1, nop, ),
+            0x6c => (6, jmp, absolute_indirect),
+            0x6d => (4, adc, absolute),
+            0x6e => (6, ror, absolute),
+            0x6f => (5, bbr6, zero_page_relative),
 
-            0x70 => (2, bvs, immediate),
+            0x70 => (2, bvs, relative),
             0x71 => (5, adc, indirect_indexed),
-            0x73 => (8, rra, indirect_indexed_for_store),
-            0x74 => (4, nop_addr, zero_page_x), // UNDOCUMENTED
+            0x72 => (5, adc, zero_page_indirect),
+            0x73 => undocumented (1, nop, ),
+            0x74 => (4, stz, zero_page_x),
             0x75 => (4, adc, zero_page_x),
             0x76 => (6, ror, zero_page_x),
-            0x77 => (6, rra, zero_page_x),
+            0x77 => (5, rmb7, zero_page),
             0x78 => (2, sei, ),
             0x79 => (4, adc, absolute_y),
-            0x7a => (2, nop, ), // UNDOCUMENTED
-            0x7b => (7, rra, absolute_y_for_store),
-            0x7c => (4, nop_addr, absolute_x), // UNDOCUMENTED
+            0x7a => (4, ply, ),
+            0x7b => undocumented (1, nop, ),
+            0x7c => (6, jmp, absolute_indexed_indirect),
             0x7d => (4, adc, absolute_x),
-            0x7e => (7, ror, absolute_x),
-            0x7f => (7, rra, absolute_x_for_store),
+            0x7e => (6, ror, absolute_x),
+            0x7f => (5, bbr7, zero_page_relative),
 
-            0x80 => (2, nop_addr, immediate), // UNDOCUMENTED
+            0x80 => (2, bra, relative),
             0x81 => (6, sta, indexed_indirect),
-            0x83 => (6, sax, indexed_indirect),
+            0x82 => undocumented (2, nop_addr, immediate),
+            0x83 => undocumented (1, nop, ),
             0x84 => (3, sty, zero_page),
             0x85 => (3, sta, zero_page),
             0x86 => (3, stx, zero_page),
-            0x87 => (3, sax, zero_page),
+            0x87 => (5, smb0, zero_page),
             0x88 => (2, dey, ),
+            0x89 => (2, bit_imm, immediate),
             0x8a => (2, txa, ),
+            0x8b => undocumented (1, nop, ),
             0x8c => (4, sty, absolute),
             0x8d => (4, sta, absolute),
             0x8e => (4, stx, absolute),
-            0x8f => (4, sax, absolute),
+            0x8f => (5, bbs0, zero_page_relative),
 
-            0x90 => (2, bcc, immediate),
-            0x91 => (6, sta, indirect_indexed),
+            0x90 => (2, bcc, relative),
+            0x91 => (6, sta, indirect_indexed_for_store),
+            0x92 => (5, sta, zero_page_indirect),
+            0x93 => undocumented (1, nop, ),
             0x94 => (4, sty, zero_page_x),
             0x95 => (4, sta, zero_page_x),
             0x96 => (4, stx, zero_page_y),
-            0x97 => (4, sax, zero_page_y),
+            0x97 => (5, smb1, zero_page),
             0x98 => (2, tya, ),
             0x99 => (5, sta, absolute_y_for_store),
             0x9a => (2, txs, ),
+            0x9b => undocumented (1, nop, ),
+            0x9c => (4, stz, absolute),
             0x9d => (5, sta, absolute_x_for_store),
+            0x9e => (5, stz, absolute_x_for_store),
+            0x9f => (5, bbs1, zero_page_relative),
 
             0xa0 => (2, ldy, immediate),
             0xa1 => (6, lda, indexed_indirect),
             0xa2 => (2, ldx, immediate),
-            0xa3 => (6, lax, indexed_indirect),
+            0xa3 => undocumented (1, nop, ),
             0xa4 => (3, ldy, zero_page),
             0xa5 => (3, lda, zero_page),
             0xa6 => (3, ldx, zero_page),
-            0xa7 => (3, lax, zero_page),
+            0xa7 => (5, smb2, zero_page),
             0xa8 => (2, tay, ),
             0xa9 => (2, lda, immediate),
             0xaa => (2, tax, ),
+            0xab => undocumented (1, nop, ),
             0xac => (4, ldy, absolute),
             0xad => (4, lda, absolute),
             0xae => (4, ldx, absolute),
-            0xaf => (4, lax, absolute),
+            0xaf => (5, bbs2, zero_page_relative),
 
-            0xb0 => (2, bcs, immediate),
+            0xb0 => (2, bcs, relative),
             0xb1 => (5, lda, indirect_indexed),
-            0xb3 => (5, lax, indirect_indexed),
+            0xb2 => (5, lda, zero_page_indirect),
+            0xb3 => undocumented (1, nop, ),
             0xb4 => (4, ldy, zero_page_x),
             0xb5 => (4, lda, zero_page_x),
             0xb6 => (4, ldx, zero_page_y),
-            0xb7 => (4, lax, zero_page_y),
+            0xb7 => (5, smb3, zero_page),
             0xb8 => (2, clv, ),
             0xb9 => (4, lda, absolute_y),
             0xba => (2, tsx, ),
+            0xbb => undocumented (1, nop, ),
             0xbc => (4, ldy, absolute_x),
             0xbd => (4, lda, absolute_x),
             0xbe => (4, ldx, absolute_y),
-            0xbf => (4, lax, absolute_y),
+            0xbf => (5, bbs3, zero_page_relative),
 
             0xc0 => (2, cpy, immediate),
             0xc1 => (6, cmp, indexed_indirect),
-            0xc3 => (8, dcp, indexed_indirect),
+            0xc2 => undocumented (2, nop_addr, immediate),
+            0xc3 => undocumented (1, nop, ),
             0xc4 => (3, cpy, zero_page),
             0xc5 => (3, cmp, zero_page),
             0xc6 => (5, dec, zero_page),
-            0xc7 => (5, dcp, zero_page),
+            0xc7 => (5, smb4, zero_page),
             0xc8 => (2, iny, ),
             0xc9 => (2, cmp, immediate),
             0xca => (2, dex, ),
+            0xcb => (3, wai, ),
             0xcc => (4, cpy, absolute),
             0xcd => (4, cmp, absolute),
             0xce => (6, dec, absolute),
-            0xcf => (6, dcp, absolute),
+            0xcf => (5, bbs4, zero_page_relative),
 
-            0xd0 => (2, bne, immediate),
+            0xd0 => (2, bne, relative),
             0xd1 => (5, cmp, indirect_indexed),
-            0xd3 => (8, dcp, indirect_indexed_for_store),
-            0xd4 => (4, nop_addr, zero_page_x), // UNDOCUMENTED
+            0xd2 => (5, cmp, zero_page_indirect),
+            0xd3 => undocumented (1, nop, ),
+            0xd4 => undocumented (4, nop_addr, zero_page_x),
             0xd5 => (4, cmp, zero_page_x),
             0xd6 => (6, dec, zero_page_x),
-            0xd7 => (6, dcp, zero_page_x),
+            0xd7 => (5, smb5, zero_page),
             0xd8 => (2, cld, ),
             0xd9 => (4, cmp, absolute_y),
-            0xda => (2, nop, ), // UNDOCUMENTED
-            0xdb => (7, dcp, absolute_y_for_store),
-            0xdc => (4, nop_addr, absolute_x), // UNDOCUMENTED
+            0xda => (3, phx, ),
+            0xdb => (3, stp, ),
+            0xdc => undocumented (4, nop_addr, absolute),
             0xdd => (4, cmp, absolute_x),
-            0xde => (7, dec, absolute_x),
-            0xdf => (7, dcp, absolute_x_for_store),
+            0xde => (7, dec, absolute_x_for_store),
+            0xdf => (5, bbs5, zero_page_relative),
 
             0xe0 => (2, cpx, immediate),
             0xe1 => (6, sbc, indexed_indirect),
-            0xe3 => (8, isc, indexed_indirect),
+            0xe2 => undocumented (2, nop_addr, immediate),
+            0xe3 => undocumented (1, nop, ),
             0xe4 => (3, cpx, zero_page),
             0xe5 => (3, sbc, zero_page),
             0xe6 => (5, inc, zero_page),
-            0xe7 => (5, isc, zero_page),
+            0xe7 => (5, smb6, zero_page),
             0xe8 => (2, inx, ),
             0xe9 => (2, sbc, immediate),
             0xea => (2, nop, ),
-            0xeb => (2, sbc, immediate), // UNDOCUMENTED
+            0xeb => undocumented (1, nop, ),
             0xec => (4, cpx, absolute),
             0xed => (4, sbc, absolute),
             0xee => (6, inc, absolute),
-            0xef => (6, isc, absolute),
+            0xef => (5, bbs6, zero_page_relative),
 
-            0xf0 => (2, beq, immediate),
+            0xf0 => (2, beq, relative),
             0xf1 => (5, sbc, indirect_indexed),
-            0xf3 => (8, isc, indirect_indexed_for_store),
-            0xf4 => (4, nop_addr, zero_page_x), // UNDOCUMENTED
+            0xf2 => (5, sbc, zero_page_indirect),
+            0xf3 => undocumented (1, nop, ),
+            0xf4 => undocumented (4, nop_addr, zero_page_x),
             0xf5 => (4, sbc, zero_page_x),
             0xf6 => (6, inc, zero_page_x),
-            0xf7 => (6, isc, zero_page_x),
+            0xf7 => (5, smb7, zero_page),
             0xf8 => (2, sed, ),
             0xf9 => (4, sbc, absolute_y),
-            0xfa => (2, nop, ), // UNDOCUMENTED
-            0xfb => (7, isc, absolute_y_for_store),
-            0xfc => (4, nop_addr, absolute_x), // UNDOCUMENTED
+            0xfa => (4, plx, ),
+            0xfb => undocumented (1, nop, ),
+            0xfc => undocumented (4, nop_addr, absolute),
             0xfd => (4, sbc, absolute_x),
-            0xfe => (7, inc, absolute_x),
-            0xff => (7, isc, absolute_x_for_store)
+            0xfe => (7, inc, absolute_x_for_store),
+            0xff => (5, bbs7, zero_page_relative)
         }
     };
 }
+pub(crate) use opcodes_65c02;
+
+use crate::opcode::AddressingMode;
+
+// What the processor learns about an instruction while running it
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Decoded {
+    pub length: u16,
+    pub cycles: usize,
+    pub mnemonic: &'static str,
+    pub mode: AddressingMode,
+}
 
 #[allow(non_upper_case_globals)]
 pub mod length {
     pub const immediate: u16 = 2;
+    pub const relative: u16 = 2;
     pub const absolute: u16 = 3;
+    pub const absolute_for_jsr: u16 = 3;
     pub const absolute_x: u16 = 3;
     pub const absolute_y: u16 = 3;
     pub const absolute_x_for_store: u16 = 3;
@@ -285,4 +675,181 @@ pub mod length {
     pub const indexed_indirect: u16 = 2;
     pub const indirect_indexed: u16 = 2;
     pub const indirect_indexed_for_store: u16 = 2;
+    pub const zero_page_indirect: u16 = 2;
+    pub const zero_page_relative: u16 = 3;
+    pub const absolute_indirect: u16 = 3;
+    pub const absolute_indexed_indirect: u16 = 3;
+}
+
+// Whether crossing a page adds a cycle: indexed reads pay to fix up the high
+// byte, and taken branches pay to move to the new page
+#[allow(non_upper_case_globals)]
+pub mod page_penalty {
+    pub const immediate: bool = false;
+    pub const relative: bool = true;
+    pub const absolute: bool = false;
+    pub const absolute_for_jsr: bool = false;
+    pub const absolute_x: bool = true;
+    pub const absolute_y: bool = true;
+    pub const absolute_x_for_store: bool = false;
+    pub const absolute_y_for_store: bool = false;
+    pub const zero_page: bool = false;
+    pub const zero_page_x: bool = false;
+    pub const zero_page_y: bool = false;
+    pub const indirect: bool = false;
+    pub const indexed_indirect: bool = false;
+    pub const indirect_indexed: bool = true;
+    pub const indirect_indexed_for_store: bool = false;
+    pub const zero_page_indirect: bool = false;
+    pub const zero_page_relative: bool = true;
+    pub const absolute_indirect: bool = false;
+    pub const absolute_indexed_indirect: bool = false;
+}
+
+#[allow(non_upper_case_globals)]
+pub mod mode {
+    use crate::opcode::AddressingMode;
+
+    pub const immediate: AddressingMode = AddressingMode::Immediate;
+    pub const relative: AddressingMode = AddressingMode::Relative;
+    pub const absolute: AddressingMode = AddressingMode::Absolute;
+    pub const absolute_for_jsr: AddressingMode = AddressingMode::Absolute;
+    pub const absolute_x: AddressingMode = AddressingMode::AbsoluteX;
+    pub const absolute_y: AddressingMode = AddressingMode::AbsoluteY;
+    pub const absolute_x_for_store: AddressingMode = AddressingMode::AbsoluteX;
+    pub const absolute_y_for_store: AddressingMode = AddressingMode::AbsoluteY;
+    pub const zero_page: AddressingMode = AddressingMode::ZeroPage;
+    pub const zero_page_x: AddressingMode = AddressingMode::ZeroPageX;
+    pub const zero_page_y: AddressingMode = AddressingMode::ZeroPageY;
+    pub const indirect: AddressingMode = AddressingMode::Indirect;
+    pub const indexed_indirect: AddressingMode = AddressingMode::IndexedIndirect;
+    pub const indirect_indexed: AddressingMode = AddressingMode::IndirectIndexed;
+    pub const indirect_indexed_for_store: AddressingMode = AddressingMode::IndirectIndexed;
+    pub const zero_page_indirect: AddressingMode = AddressingMode::ZeroPageIndirect;
+    pub const zero_page_relative: AddressingMode = AddressingMode::ZeroPageRelative;
+    pub const absolute_indirect: AddressingMode = AddressingMode::Indirect;
+    pub const absolute_indexed_indirect: AddressingMode = AddressingMode::AbsoluteIndexedIndirect;
+}
+
+macro_rules! bit_operations {
+    ($($rmb:ident, $smb:ident, $bbr:ident, $bbs:ident => $bit:literal),+) => {
+        $(
+            pub const $rmb: Operation = Operation::modify(concat!("RMB", $bit));
+            pub const $smb: Operation = Operation::modify(concat!("SMB", $bit));
+            pub const $bbr: Operation = Operation::read(concat!("BBR", $bit));
+            pub const $bbs: Operation = Operation::read(concat!("BBS", $bit));
+        )+
+    };
+}
+
+#[allow(non_upper_case_globals)]
+pub mod operation {
+    use crate::opcode::Operation;
+
+    pub const adc: Operation = Operation::read("ADC");
+    pub const alr: Operation = Operation::read("ALR");
+    pub const anc: Operation = Operation::read("ANC");
+    pub const and: Operation = Operation::read("AND");
+    pub const arr: Operation = Operation::read("ARR");
+    pub const asl: Operation = Operation::modify("ASL");
+    pub const asla: Operation = Operation::accumulator("ASL");
+    pub const axs: Operation = Operation::read("AXS");
+    pub const bcc: Operation = Operation::none("BCC");
+    pub const bcs: Operation = Operation::none("BCS");
+    pub const beq: Operation = Operation::none("BEQ");
+    pub const bit: Operation = Operation::read("BIT");
+    pub const bit_imm: Operation = Operation::read("BIT");
+    pub const bmi: Operation = Operation::none("BMI");
+    pub const bne: Operation = Operation::none("BNE");
+    pub const bpl: Operation = Operation::none("BPL");
+    pub const bra: Operation = Operation::none("BRA");
+    pub const brk: Operation = Operation::none("BRK");
+    pub const bvc: Operation = Operation::none("BVC");
+    pub const bvs: Operation = Operation::none("BVS");
+    pub const clc: Operation = Operation::none("CLC");
+    pub const cld: Operation = Operation::none("CLD");
+    pub const cli: Operation = Operation::none("CLI");
+    pub const clv: Operation = Operation::none("CLV");
+    pub const cmp: Operation = Operation::read("CMP");
+    pub const cpx: Operation = Operation::read("CPX");
+    pub const cpy: Operation = Operation::read("CPY");
+    pub const dcp: Operation = Operation::modify("DCP");
+    pub const dec: Operation = Operation::modify("DEC");
+    pub const deca: Operation = Operation::accumulator("DEC");
+    pub const dex: Operation = Operation::none("DEX");
+    pub const dey: Operation = Operation::none("DEY");
+    pub const eor: Operation = Operation::read("EOR");
+    pub const inc: Operation = Operation::modify("INC");
+    pub const inca: Operation = Operation::accumulator("INC");
+    pub const inx: Operation = Operation::none("INX");
+    pub const iny: Operation = Operation::none("INY");
+    pub const isc: Operation = Operation::modify("ISC");
+    pub const jam: Operation = Operation::none("JAM");
+    pub const jmp: Operation = Operation::none("JMP");
+    pub const jsr: Operation = Operation::none("JSR");
+    pub const las: Operation = Operation::read("LAS");
+    pub const lax: Operation = Operation::read("LAX");
+    pub const lda: Operation = Operation::read("LDA");
+    pub const ldx: Operation = Operation::read("LDX");
+    pub const ldy: Operation = Operation::read("LDY");
+    pub const lsr: Operation = Operation::modify("LSR");
+    pub const lsra: Operation = Operation::accumulator("LSR");
+    pub const lxa: Operation = Operation::read("LXA");
+    pub const nop: Operation = Operation::none("NOP");
+    pub const nop_addr: Operation = Operation::read("NOP");
+    pub const nop_long: Operation = Operation::none("NOP");
+    pub const ora: Operation = Operation::read("ORA");
+    pub const pha: Operation = Operation::none("PHA");
+    pub const php: Operation = Operation::none("PHP");
+    pub const phx: Operation = Operation::none("PHX");
+    pub const phy: Operation = Operation::none("PHY");
+    pub const pla: Operation = Operation::none("PLA");
+    pub const plp: Operation = Operation::none("PLP");
+    pub const plx: Operation = Operation::none("PLX");
+    pub const ply: Operation = Operation::none("PLY");
+    pub const rla: Operation = Operation::modify("RLA");
+    pub const rol: Operation = Operation::modify("ROL");
+    pub const rola: Operation = Operation::accumulator("ROL");
+    pub const ror: Operation = Operation::modify("ROR");
+    pub const rora: Operation = Operation::accumulator("ROR");
+    pub const rra: Operation = Operation::modify("RRA");
+    pub const rti: Operation = Operation::none("RTI");
+    pub const rts: Operation = Operation::none("RTS");
+    pub const sax: Operation = Operation::write("SAX");
+    pub const sbc: Operation = Operation::read("SBC");
+    pub const sec: Operation = Operation::none("SEC");
+    pub const sed: Operation = Operation::none("SED");
+    pub const sei: Operation = Operation::none("SEI");
+    pub const sha: Operation = Operation::write("SHA");
+    pub const shx: Operation = Operation::write("SHX");
+    pub const shy: Operation = Operation::write("SHY");
+    pub const slo: Operation = Operation::modify("SLO");
+    pub const sre: Operation = Operation::modify("SRE");
+    pub const sta: Operation = Operation::write("STA");
+    pub const stp: Operation = Operation::none("STP");
+    pub const stx: Operation = Operation::write("STX");
+    pub const sty: Operation = Operation::write("STY");
+    pub const stz: Operation = Operation::write("STZ");
+    pub const tas: Operation = Operation::write("TAS");
+    pub const tax: Operation = Operation::none("TAX");
+    pub const tay: Operation = Operation::none("TAY");
+    pub const trb: Operation = Operation::modify("TRB");
+    pub const tsb: Operation = Operation::modify("TSB");
+    pub const tsx: Operation = Operation::none("TSX");
+    pub const txa: Operation = Operation::none("TXA");
+    pub const txs: Operation = Operation::none("TXS");
+    pub const tya: Operation = Operation::none("TYA");
+    pub const wai: Operation = Operation::none("WAI");
+    pub const xaa: Operation = Operation::read("XAA");
+
+    bit_operations!(
+        rmb0, smb0, bbr0, bbs0 => 0,
+        rmb1, smb1, bbr1, bbs1 => 1,
+        rmb2, smb2, bbr2, bbs2 => 2,
+        rmb3, smb3, bbr3, bbs3 => 3,
+        rmb4, smb4, bbr4, bbs4 => 4,
+        rmb5, smb5, bbr5, bbs5 => 5,
+        rmb6, smb6, bbr6, bbs6 => 6,
+        rmb7, smb7, bbr7, bbs7 => 7
+    );
 }
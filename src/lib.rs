@@ -1,12 +1,37 @@
+mod bus;
+mod debugger;
 mod decompiler;
+mod error;
 mod flags;
+mod history;
 mod instructions;
+mod json;
 mod macros;
 mod memory;
+mod opcode;
 mod processor;
+mod profile;
+mod run;
+mod snapshot;
+mod step;
+mod trace;
+mod tracediff;
+mod variant;
 
+pub mod conformance;
 pub mod nintendo;
 
+pub use bus::{AccessKind, BusAccess};
+pub use debugger::{Debugger, Expression, GdbConnection, GdbServer, Stop, WatchKind};
 pub use decompiler::Decompiler;
+pub use error::Error;
 pub use memory::{Memory, MirroredMemory, RandomAccessMemory, ReadOnlyMemory};
+pub use opcode::{Access, AddressingMode, OpcodeInfo, CMOS_OPCODES, OPCODES};
 pub use processor::Processor;
+pub use profile::{AddressProfile, Profile, RoutineProfile};
+pub use run::StopReason;
+pub use snapshot::{SaveState, StateReader, StateWriter};
+pub use step::{Step, StepKind};
+pub use trace::{PpuPosition, TraceFormat, TraceLogger};
+pub use tracediff::{parse_trace, Divergence, TraceComparison, TraceDiff, TraceField, TraceRecord};
+pub use variant::Variant;
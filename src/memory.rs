@@ -1,12 +1,30 @@
 pub trait Memory {
     fn read(&self, addr: u16) -> u8;
+    // A read made by the processor on the bus, which devices such as I/O
+    // registers can react to. read and peek are for looking at memory
+    // without disturbing it.
+    fn read_bus(&mut self, addr: u16) -> u8 {
+        self.read(addr)
+    }
     fn read_word(&self, addr: u16) -> u16 {
         u16::from_le_bytes([self.read(addr), self.read(addr + 1)])
     }
     fn read_signed(&self, addr: u16) -> i8 {
         i8::from_le_bytes([self.read(addr)])
     }
+    // What read would return, without any of its side effects, and where
+    // writing that value back restores things as they were. None for device
+    // registers, where neither holds.
+    fn peek(&self, addr: u16) -> Option<u8> {
+        Some(self.read(addr))
+    }
     fn write(&mut self, addr: u16, data: u8);
+    // A write made by the processor on the cycle straight after another
+    // write, as read-modify-write instructions do. Only a few devices can
+    // tell the difference.
+    fn write_consecutive(&mut self, addr: u16, data: u8) {
+        self.write(addr, data)
+    }
     fn length(&self) -> usize;
 }
 
@@ -106,6 +124,14 @@ where
         self.underlying.read(addr & self.mask)
     }
 
+    fn read_bus(&mut self, addr: u16) -> u8 {
+        self.underlying.read_bus(addr & self.mask)
+    }
+
+    fn peek(&self, addr: u16) -> Option<u8> {
+        self.underlying.peek(addr & self.mask)
+    }
+
     fn write(&mut self, addr: u16, data: u8) {
         self.underlying.write(addr & self.mask, data)
     }
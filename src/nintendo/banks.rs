@@ -0,0 +1,143 @@
+use super::RomHeader;
+use crate::snapshot::{SaveState, StateReader, StateWriter};
+use crate::{Error, Memory};
+
+// ROM or RAM bigger than the part of the address space it is seen through,
+// which mappers switch between in banks. The window is split into equal
+// slots, each showing part of a bank.
+#[derive(Clone, Debug)]
+pub struct BankedMemory {
+    pub data: Vec<u8>,
+    pub writable: bool,
+    base: u16,
+    slot_size: usize,
+    // Where in data each slot starts
+    slots: Vec<usize>,
+}
+
+impl BankedMemory {
+    // At first the window shows the start of data
+    pub fn new(data: Vec<u8>, writable: bool, base: u16, window: usize, slot_size: usize) -> Self {
+        let slots = (0..window / slot_size)
+            .map(|slot| slot * slot_size)
+            .collect();
+        let mut memory = Self {
+            data,
+            writable,
+            base,
+            slot_size,
+            slots,
+        };
+        memory.map(base, window, 0);
+        memory
+    }
+
+    // PRG ROM at $8000-$FFFF in 8K slots
+    pub fn prg_rom(data: &[u8]) -> Self {
+        Self::new(data.to_vec(), false, 0x8000, 0x8000, 0x2000)
+    }
+
+    // All of the work RAM the header asks for at $6000-$7FFF, which may be
+    // none
+    pub fn prg_ram(header: &RomHeader) -> Self {
+        let size = header.prg_ram_size + header.prg_nvram_size;
+        Self::new(vec![0; size], true, 0x6000, 0x2000, 0x2000)
+    }
+
+    // CHR ROM in 1K slots, or CHR RAM when the file has no CHR ROM
+    pub fn chr(header: &RomHeader, data: &[u8]) -> Self {
+        match data.len() {
+            0 => {
+                let size = header.chr_ram_size + header.chr_nvram_size;
+                Self::new(vec![0; size.max(0x2000)], true, 0, 0x2000, 0x0400)
+            }
+            _ => Self::new(data.to_vec(), false, 0, 0x2000, 0x0400),
+        }
+    }
+
+    // Shows bank number `bank` in the `size` bytes from `addr`, counting in
+    // banks of `size`. Numbers past the last bank wrap around, as boards
+    // leave the extra bank lines unconnected.
+    pub fn map(&mut self, addr: u16, size: usize, bank: usize) {
+        if self.data.is_empty() {
+            return;
+        }
+        let start = bank * size % self.data.len();
+        let first = (addr - self.base) as usize / self.slot_size;
+        for i in 0..size / self.slot_size {
+            self.slots[first + i] = (start + i * self.slot_size) % self.data.len();
+        }
+    }
+
+    // Where in data an address in the window is, or None if there's no data
+    pub fn offset(&self, addr: u16) -> Option<usize> {
+        let addr = addr.checked_sub(self.base)? as usize;
+        let slot = self.slots.get(addr / self.slot_size)?;
+        (!self.data.is_empty()).then(|| slot + addr % self.slot_size)
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+}
+
+impl Memory for BankedMemory {
+    fn read(&self, addr: u16) -> u8 {
+        self.offset(addr).map_or(0, |offset| self.data[offset])
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        if let (true, Some(offset)) = (self.writable, self.offset(addr)) {
+            self.data[offset] = data;
+        }
+    }
+
+    fn length(&self) -> usize {
+        self.slots.len() * self.slot_size
+    }
+}
+
+// Only RAM is saved. The banks are put back by the mapper from its
+// registers.
+impl SaveState for BankedMemory {
+    fn save(&self, writer: &mut StateWriter) {
+        if self.writable {
+            writer.bytes(&self.data);
+        }
+    }
+
+    fn load(&mut self, reader: &mut StateReader) -> Result<(), Error> {
+        match self.writable {
+            true => reader.bytes_into(&mut self.data),
+            false => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_map() {
+        let data: Vec<u8> = (0..8).collect();
+        let mut memory = BankedMemory::new(data, false, 0x8000, 4, 1);
+        assert_eq!(memory.read(0x8003), 3);
+        memory.map(0x8002, 2, 3);
+        assert_eq!((memory.read(0x8002), memory.read(0x8003)), (6, 7));
+        // Bank 5 of 4 is bank 1
+        memory.map(0x8000, 2, 5);
+        assert_eq!((memory.read(0x8000), memory.offset(0x8001)), (2, Some(3)));
+        memory.write(0x8000, 9);
+        assert_eq!(memory.read(0x8000), 2);
+        assert_eq!(memory.offset(0x7fff), None);
+        assert_eq!(memory.offset(0x8004), None);
+
+        let empty = BankedMemory::new(Vec::new(), true, 0x6000, 0x2000, 0x2000);
+        assert_eq!((empty.read(0x6000), empty.offset(0x6000)), (0, None));
+    }
+}
@@ -1,20 +1,127 @@
-use crate::{memory::Memory, ReadOnlyMemory};
+use super::banks::BankedMemory;
+use super::RomHeader;
+use crate::snapshot::{SaveState, StateReader, StateWriter};
+use crate::{memory::Memory, Error};
 
+// How the PPU's 2K of nametable RAM fills the four nametables
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mirroring {
+    // $2000 and $2400 share the first 1K, $2800 and $2C00 the second
+    Horizontal,
+    // $2000 and $2800 share the first 1K, $2400 and $2C00 the second
+    Vertical,
+    SingleScreenLower,
+    SingleScreenUpper,
+    // The cartridge supplies another 2K so each nametable has its own
+    FourScreen,
+}
+
+impl Mirroring {
+    // Where a nametable address ($2000-$2FFF) lands in 4K of nametable RAM
+    pub fn nametable_addr(&self, addr: u16) -> u16 {
+        let table = (addr >> 10) & 3;
+        let page = match self {
+            Mirroring::Horizontal => table >> 1,
+            Mirroring::Vertical => table & 1,
+            Mirroring::SingleScreenLower => 0,
+            Mirroring::SingleScreenUpper => 1,
+            Mirroring::FourScreen => table,
+        };
+        page << 10 | (addr & 0x03ff)
+    }
+}
+
+// The board in a game cartridge. The CPU sees it at $4020-$FFFF and the PPU
+// at $0000-$1FFF, and mappers hook the rest of the methods to switch banks,
+// count scanlines and raise interrupts.
 pub trait Cartridge {
     fn prg(&self) -> &dyn Memory;
     fn prg_mut(&mut self) -> &mut dyn Memory;
 
     fn chr(&self) -> &dyn Memory;
     fn chr_mut(&mut self) -> &mut dyn Memory;
+
+    fn cpu_read(&self, addr: u16) -> u8 {
+        self.prg().read(addr)
+    }
+
+    // Memory::peek for the cartridge's part of the CPU's address space. Only
+    // boards that know an address is plain RAM or ROM say so.
+    fn cpu_peek(&self, _addr: u16) -> Option<u8> {
+        None
+    }
+
+    // Mappers with registers in the PRG ROM area catch the writes here
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        self.prg_mut().write(addr, data)
+    }
+
+    // Used instead of cpu_write when the CPU also wrote on the cycle before,
+    // as read-modify-write instructions do
+    fn consecutive_write(&mut self, addr: u16, data: u8) {
+        self.cpu_write(addr, data)
+    }
+
+    // Called with every address the PPU puts on its bus, for mappers that
+    // watch it, such as MMC3 counting scanlines from A12
+    fn ppu_address(&mut self, _addr: u16) {}
+
+    // Called once for each CPU cycle
+    fn clock(&mut self) {}
+
+    // Whether the cartridge is pulling the IRQ line low
+    fn irq(&self) -> bool {
+        false
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        Mirroring::Horizontal
+    }
+
+    // Where in PRG ROM the byte the CPU sees at `addr` comes from, given the
+    // banks mapped in now. None for RAM, registers and open bus.
+    fn prg_rom_offset(&self, _addr: u16) -> Option<usize> {
+        None
+    }
+
+    fn prg_rom_size(&self) -> usize {
+        0
+    }
+
+    // The same for the PPU's pattern tables
+    fn chr_offset(&self, addr: u16) -> Option<usize> {
+        ((addr as usize) < self.chr().length()).then_some(addr as usize)
+    }
+
+    // 0 for boards with CHR RAM
+    fn chr_rom_size(&self) -> usize {
+        self.chr().length()
+    }
+
+    // Bank registers, cartridge RAM and anything else that changes while the
+    // game runs. The ROM itself isn't saved.
+    fn save_state(&self, _writer: &mut StateWriter) {}
+
+    fn load_state(&mut self, _reader: &mut StateReader) -> Result<(), Error> {
+        Ok(())
+    }
 }
 
 impl<T: Cartridge + ?Sized> Memory for T {
     fn read(&self, addr: u16) -> u8 {
-        self.prg().read(addr)
+        self.cpu_read(addr)
+    }
+
+    fn peek(&self, addr: u16) -> Option<u8> {
+        self.cpu_peek(addr)
     }
 
     fn write(&mut self, addr: u16, data: u8) {
-        self.prg_mut().write(addr, data)
+        self.cpu_write(addr, data)
+    }
+
+    fn write_consecutive(&mut self, addr: u16, data: u8) {
+        self.consecutive_write(addr, data)
     }
 
     fn length(&self) -> usize {
@@ -22,9 +129,12 @@ impl<T: Cartridge + ?Sized> Memory for T {
     }
 }
 
-#[derive(Debug, Clone)]
+// Stands in for a cartridge in tests that don't need one
+#[cfg(test)]
+#[derive(Debug, Clone, Default)]
 pub struct NullMemory;
 
+#[cfg(test)]
 impl Memory for NullMemory {
     fn read(&self, _addr: u16) -> u8 {
         0
@@ -37,12 +147,14 @@ impl Memory for NullMemory {
     }
 }
 
-#[derive(Debug, Clone)]
+#[cfg(test)]
+#[derive(Debug, Clone, Default)]
 pub struct NullCartridge {
     memory: NullMemory,
     chr_rom: NullMemory,
 }
 
+#[cfg(test)]
 impl Cartridge for NullCartridge {
     fn prg(&self) -> &dyn Memory {
         &self.memory
@@ -118,19 +230,29 @@ impl Memory for NROM16KBMemory {
 
 pub struct NROMCartridge {
     pub prg_rom: Box<dyn Memory>,
-    pub chr_rom: ReadOnlyMemory,
+    // CHR ROM, or 8K of CHR RAM when the file has none
+    pub chr: BankedMemory,
+    pub mirroring: Mirroring,
+    prg_size: usize,
 }
 
 impl NROMCartridge {
-    pub fn new(prg_bytes: &[u8], chr_bytes: &[u8]) -> Self {
-        Self {
+    pub fn new(header: &RomHeader, prg_bytes: &[u8], chr_bytes: &[u8]) -> Result<Self, Error> {
+        Ok(Self {
             prg_rom: (match prg_bytes.len() {
                 0x4000 => Box::new(NROM16KBMemory::new(prg_bytes.try_into().unwrap())),
                 0x8000 => Box::new(NROM32KBMemory::new(prg_bytes.try_into().unwrap())),
-                _ => panic!("NROM PRG size wrong"),
+                size => {
+                    return Err(Error::InvalidHeader {
+                        field: "PRG ROM size",
+                        value: size as u32,
+                    })
+                }
             }),
-            chr_rom: chr_bytes.into(),
-        }
+            chr: BankedMemory::chr(header, chr_bytes),
+            mirroring: header.mirroring,
+            prg_size: prg_bytes.len(),
+        })
     }
 }
 
@@ -144,10 +266,50 @@ impl Cartridge for NROMCartridge {
     }
 
     fn chr(&self) -> &dyn Memory {
-        &self.chr_rom
+        &self.chr
     }
 
     fn chr_mut(&mut self) -> &mut dyn Memory {
-        &mut self.chr_rom
+        &mut self.chr
+    }
+
+    // There are no registers, and writes to ROM do nothing
+    fn cpu_peek(&self, addr: u16) -> Option<u8> {
+        Some(self.cpu_read(addr))
+    }
+
+    // 16K games see the same bank at $8000 and $C000
+    fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        (addr >= 0x8000).then(|| (addr as usize - 0x8000) % self.prg_size)
+    }
+
+    fn prg_rom_size(&self) -> usize {
+        self.prg_size
+    }
+
+    fn chr_offset(&self, addr: u16) -> Option<usize> {
+        (!self.chr.writable)
+            .then(|| self.chr.offset(addr))
+            .flatten()
+    }
+
+    fn chr_rom_size(&self) -> usize {
+        match self.chr.writable {
+            true => 0,
+            false => self.chr.len(),
+        }
+    }
+
+    // Soldered on the board
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        self.chr.save(writer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), Error> {
+        self.chr.load(reader)
     }
 }
@@ -0,0 +1,191 @@
+use super::cartridge::Cartridge;
+use crate::{AccessKind, AddressingMode, BusAccess, Step, StepKind};
+
+// Bits 2 and 3 of an FCEUX PRG entry hold which 8K window of $8000-$FFFF the
+// byte was seen in. We keep them in the top two bits.
+const WINDOW: u8 = 0xc0;
+const WINDOW_SHIFT: u8 = 6;
+
+// How each byte of PRG ROM has been used and which CHR bytes the PPU has
+// fetched, while rendering or through $2007, built up while the game runs.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CodeDataLog {
+    prg: Vec<u8>,
+    chr: Vec<bool>,
+}
+
+impl CodeDataLog {
+    // Uses of a PRG byte, which can be combined
+    pub const OPCODE: u8 = 0x01;
+    pub const OPERAND: u8 = 0x02;
+    pub const DATA: u8 = 0x04;
+    // Code jumped to by JMP ($xxxx)
+    pub const INDIRECT_CODE: u8 = 0x08;
+    // Data read through a pointer by an indirect addressing mode
+    pub const INDIRECT_DATA: u8 = 0x10;
+
+    pub fn new(prg_size: usize, chr_size: usize) -> Self {
+        Self {
+            prg: vec![0; prg_size],
+            chr: vec![false; chr_size],
+        }
+    }
+
+    pub fn prg(&self, offset: usize) -> u8 {
+        self.prg[offset] & !WINDOW
+    }
+
+    pub fn chr_fetched(&self, offset: usize) -> bool {
+        self.chr[offset]
+    }
+
+    fn mark(&mut self, cartridge: &dyn Cartridge, addr: u16, usage: u8) {
+        if let Some(byte) = cartridge
+            .prg_rom_offset(addr)
+            .and_then(|offset| self.prg.get_mut(offset))
+        {
+            *byte = (*byte & !WINDOW) | usage | ((addr >> 13) as u8 & 3) << WINDOW_SHIFT;
+        }
+    }
+
+    // Classifies the PRG ROM bytes an instruction or interrupt touched, from
+    // its bus accesses. `pc` is where the processor went next.
+    pub(crate) fn record_step(
+        &mut self,
+        cartridge: &dyn Cartridge,
+        step: &Step,
+        accesses: &[BusAccess],
+        pc: u16,
+    ) {
+        let indirect = matches!(
+            step.mode,
+            AddressingMode::IndexedIndirect
+                | AddressingMode::IndirectIndexed
+                | AddressingMode::ZeroPageIndirect
+        );
+        for access in accesses {
+            let usage = match access.kind {
+                AccessKind::OpcodeFetch => Self::OPCODE,
+                AccessKind::OperandFetch => Self::OPERAND,
+                AccessKind::Read if indirect && step.effective_addr == Some(access.addr) => {
+                    Self::DATA | Self::INDIRECT_DATA
+                }
+                AccessKind::Read => Self::DATA,
+                _ => continue,
+            };
+            self.mark(cartridge, access.addr, usage);
+        }
+        let jumped_indirectly = step.kind == StepKind::Instruction
+            && step.mnemonic == "JMP"
+            && matches!(
+                step.mode,
+                AddressingMode::Indirect | AddressingMode::AbsoluteIndexedIndirect
+            );
+        if jumped_indirectly {
+            self.mark(cartridge, pc, Self::INDIRECT_CODE);
+        }
+    }
+
+    pub(crate) fn record_chr(&mut self, cartridge: &dyn Cartridge, addr: u16) {
+        if let Some(fetched) = cartridge
+            .chr_offset(addr)
+            .and_then(|offset| self.chr.get_mut(offset))
+        {
+            *fetched = true;
+        }
+    }
+
+    // The log as FCEUX writes it: a byte for each byte of PRG ROM followed by
+    // a byte for each byte of CHR ROM
+    pub fn to_fceux(&self) -> Vec<u8> {
+        let prg = self.prg.iter().map(|usage| {
+            let code = usage & (Self::OPCODE | Self::OPERAND) != 0;
+            let data = usage & Self::DATA != 0;
+            code as u8
+                | (data as u8) << 1
+                | (usage & WINDOW) >> WINDOW_SHIFT << 2
+                // FCEUX keeps the two indirect bits one place higher
+                | (usage & (Self::INDIRECT_CODE | Self::INDIRECT_DATA)) << 1
+        });
+        // Bit 0 is set for bytes the PPU read while rendering
+        let chr = self.chr.iter().map(|fetched| *fetched as u8);
+        prg.chain(chr).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nintendo::Nes;
+    use crate::Memory;
+
+    #[test]
+    fn test_cdl() {
+        let mut rom = vec![0x4e, 0x45, 0x53, 0x1a, 1, 1];
+        rom.resize(16 + 0x4000 + 0x2000, 0);
+        // LDA ($10),Y, reading the opcode of the LDA after it; LDA $8020;
+        // JMP ($8030), which lands on a NOP through the mirror at $C000
+        let program = [0xb1, 0x10, 0xad, 0x20, 0x80, 0x6c, 0x30, 0x80, 0xea];
+        rom[16..16 + program.len()].copy_from_slice(&program);
+        rom[16 + 0x30..16 + 0x32].copy_from_slice(&[0x08, 0xc0]);
+
+        let mut nes = Nes::new(&rom).unwrap();
+        nes.cpu.core.pc = 0x8000;
+        nes.cpu.memory.write(0x10, 0x02);
+        nes.cpu.memory.write(0x11, 0x80);
+        nes.enable_cdl();
+        for _ in 0..4 {
+            nes.step().unwrap();
+        }
+
+        let cdl = nes.disable_cdl().unwrap();
+        assert_eq!(cdl.prg(0), CodeDataLog::OPCODE);
+        assert_eq!(cdl.prg(1), CodeDataLog::OPERAND);
+        assert_eq!(
+            cdl.prg(2),
+            CodeDataLog::OPCODE | CodeDataLog::DATA | CodeDataLog::INDIRECT_DATA
+        );
+        assert_eq!(cdl.prg(0x20), CodeDataLog::DATA);
+        assert_eq!(cdl.prg(0x31), CodeDataLog::DATA);
+        assert_eq!(cdl.prg(8), CodeDataLog::OPCODE | CodeDataLog::INDIRECT_CODE);
+        assert_eq!(cdl.prg(9), 0);
+        assert!(!cdl.chr_fetched(0));
+        assert!(nes.cdl().is_none());
+
+        let fceux = cdl.to_fceux();
+        assert_eq!(fceux.len(), 0x6000);
+        assert_eq!(&fceux[..2], [0x01, 0x01]);
+        // Code reached by a jump, seen at $C000-$DFFF
+        assert_eq!(fceux[8], 0x19);
+        // Code that was also read through a pointer is only indirect data
+        assert_eq!(fceux[2], 0x23);
+        assert_eq!(fceux[0x4000], 0x00);
+    }
+
+    #[test]
+    fn test_cdl_chr() {
+        let mut rom = vec![0x4e, 0x45, 0x53, 0x1a, 1, 1];
+        rom.resize(16 + 0x4000 + 0x2000, 0);
+        // Turn the background on from $0000, then wait. Every tile is 0, and
+        // so is every sprite.
+        let program = [0xa9, 0x08, 0x8d, 0x01, 0x20, 0x4c, 0x05, 0x80];
+        rom[16..16 + program.len()].copy_from_slice(&program);
+
+        let mut nes = Nes::new(&rom).unwrap();
+        nes.cpu.core.pc = 0x8000;
+        nes.enable_cdl();
+        while nes.ppu.frame == 0 {
+            nes.step().unwrap();
+        }
+
+        // Tile 0 for the background and sprites, and the top row of tile $FF
+        // for empty sprite slots
+        let cdl = nes.disable_cdl().unwrap();
+        assert!((0x0000..0x0010).all(|offset| cdl.chr_fetched(offset)));
+        assert!(cdl.chr_fetched(0x0ff0) && cdl.chr_fetched(0x0ff8));
+        assert!(!cdl.chr_fetched(0x0010));
+        assert!(!cdl.chr_fetched(0x0ff1));
+        assert!(!cdl.chr_fetched(0x1000));
+        assert_eq!(cdl.to_fceux()[0x4005], 0x01);
+    }
+}
@@ -0,0 +1,335 @@
+use super::banks::BankedMemory;
+use super::cartridge::{Cartridge, Mirroring};
+use super::RomHeader;
+use crate::snapshot::{SaveState, StateReader, StateWriter};
+use crate::{Error, Memory};
+
+// Boards that bank with a latch chip rather than a mapper ASIC. Writing to
+// ROM space stores the byte in the latch, whose outputs pick the banks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DiscreteBoard {
+    // Mapper 2: 16K at $8000, the last 16K fixed at $C000
+    UxRom,
+    // Mapper 3: 8K of CHR
+    CnRom,
+    // Mapper 7: 32K of PRG and which nametable is shown
+    AxRom,
+    // Mapper 11: 32K of PRG in the low bits, 8K of CHR in the high ones
+    ColorDreams,
+    // Mapper 34 with CHR RAM: 32K of PRG
+    BnRom,
+    // Mapper 34 with CHR ROM: registers at $7FFD-$7FFF for 32K of PRG and
+    // two 4K of CHR, on top of 8K of work RAM
+    Nina001,
+    // Mapper 66: 32K of PRG in bits 4-5, 8K of CHR in bits 0-1
+    GxRom,
+}
+
+impl DiscreteBoard {
+    // NES 2.0 submappers 1 and 2 of mapper 34 say which board it is. Older
+    // files only give it away by having CHR ROM.
+    pub fn mapper_34(header: &RomHeader, chr: &[u8]) -> Self {
+        match (header.submapper, chr.is_empty()) {
+            (1, _) | (0, false) => DiscreteBoard::Nina001,
+            _ => DiscreteBoard::BnRom,
+        }
+    }
+
+    // Whether the ROM drives the data bus along with the CPU when the latch
+    // is written, so that only bits both agree on get through
+    fn has_bus_conflicts(self) -> bool {
+        // Games for AOROM boards, which lack them, don't avoid them
+        !matches!(self, DiscreteBoard::AxRom | DiscreteBoard::Nina001)
+    }
+}
+
+pub struct DiscreteCartridge {
+    pub board: DiscreteBoard,
+    pub bus_conflicts: bool,
+    prg: BankedMemory,
+    prg_ram: BankedMemory,
+    chr: BankedMemory,
+    mirroring: Mirroring,
+    // The latch, or NINA-001's three registers
+    registers: [u8; 3],
+}
+
+impl DiscreteCartridge {
+    // Every board here switches PRG in 16K or 32K banks, and a 16K game is
+    // mirrored into the whole window
+    pub fn new(
+        board: DiscreteBoard,
+        header: &RomHeader,
+        prg: &[u8],
+        chr: &[u8],
+    ) -> Result<Self, Error> {
+        if prg.is_empty() || !prg.len().is_multiple_of(0x4000) {
+            return Err(Error::InvalidHeader {
+                field: "PRG ROM size",
+                value: prg.len() as u32,
+            });
+        }
+        let mut prg_ram = BankedMemory::prg_ram(header);
+        if board == DiscreteBoard::Nina001 && prg_ram.is_empty() {
+            prg_ram = BankedMemory::new(vec![0; 0x2000], true, 0x6000, 0x2000, 0x2000);
+        }
+        // NES 2.0 submapper 1 means no bus conflicts and 2 means conflicts
+        // on the boards that can go either way
+        let either = matches!(
+            board,
+            DiscreteBoard::UxRom | DiscreteBoard::CnRom | DiscreteBoard::AxRom
+        );
+        let bus_conflicts = match (either, header.submapper) {
+            (true, 1) => false,
+            (true, 2) => true,
+            _ => board.has_bus_conflicts(),
+        };
+        let mut cartridge = Self {
+            board,
+            bus_conflicts,
+            prg: BankedMemory::prg_rom(prg),
+            prg_ram,
+            chr: BankedMemory::chr(header, chr),
+            mirroring: header.mirroring,
+            registers: [0; 3],
+        };
+        cartridge.update_banks();
+        Ok(cartridge)
+    }
+
+    fn update_banks(&mut self) {
+        let latch = self.registers[0] as usize;
+        match self.board {
+            DiscreteBoard::UxRom => {
+                self.prg.map(0x8000, 0x4000, latch);
+                self.prg.map(0xc000, 0x4000, self.prg.len() / 0x4000 - 1);
+            }
+            DiscreteBoard::CnRom => self.chr.map(0x0000, 0x2000, latch),
+            DiscreteBoard::AxRom => self.prg.map(0x8000, 0x8000, latch & 7),
+            DiscreteBoard::ColorDreams => {
+                self.prg.map(0x8000, 0x8000, latch & 3);
+                self.chr.map(0x0000, 0x2000, latch >> 4);
+            }
+            DiscreteBoard::BnRom => self.prg.map(0x8000, 0x8000, latch),
+            DiscreteBoard::Nina001 => {
+                self.prg.map(0x8000, 0x8000, latch & 1);
+                self.chr.map(0x0000, 0x1000, self.registers[1] as usize);
+                self.chr.map(0x1000, 0x1000, self.registers[2] as usize);
+            }
+            DiscreteBoard::GxRom => {
+                self.prg.map(0x8000, 0x8000, latch >> 4 & 3);
+                self.chr.map(0x0000, 0x2000, latch & 3);
+            }
+        }
+    }
+}
+
+impl Cartridge for DiscreteCartridge {
+    fn prg(&self) -> &dyn Memory {
+        &self.prg
+    }
+
+    fn prg_mut(&mut self) -> &mut dyn Memory {
+        &mut self.prg
+    }
+
+    fn chr(&self) -> &dyn Memory {
+        &self.chr
+    }
+
+    fn chr_mut(&mut self) -> &mut dyn Memory {
+        &mut self.chr
+    }
+
+    fn cpu_read(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7fff if !self.prg_ram.is_empty() => self.prg_ram.read(addr),
+            0x8000..=0xffff => self.prg.read(addr),
+            _ => (addr >> 8) as u8,
+        }
+    }
+
+    fn cpu_peek(&self, addr: u16) -> Option<u8> {
+        match (self.board, addr) {
+            (DiscreteBoard::Nina001, 0x7ffd..=0x7fff) => None,
+            (_, 0x6000..=0x7fff) if !self.prg_ram.is_empty() => Some(self.prg_ram.read(addr)),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match (self.board, addr) {
+            // NINA-001's registers are written through to the RAM beneath
+            (DiscreteBoard::Nina001, 0x7ffd..=0x7fff) => {
+                self.prg_ram.write(addr, data);
+                self.registers[(addr - 0x7ffd) as usize] = data;
+                self.update_banks();
+            }
+            (_, 0x6000..=0x7fff) => self.prg_ram.write(addr, data),
+            (DiscreteBoard::Nina001, _) => {}
+            (_, 0x8000..=0xffff) => {
+                self.registers[0] = match self.bus_conflicts {
+                    true => data & self.prg.read(addr),
+                    false => data,
+                };
+                self.update_banks();
+            }
+            _ => {}
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match (self.board, self.registers[0] & 0x10) {
+            (DiscreteBoard::AxRom, 0) => Mirroring::SingleScreenLower,
+            (DiscreteBoard::AxRom, _) => Mirroring::SingleScreenUpper,
+            _ => self.mirroring,
+        }
+    }
+
+    fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        self.prg.offset(addr)
+    }
+
+    fn prg_rom_size(&self) -> usize {
+        self.prg.len()
+    }
+
+    fn chr_offset(&self, addr: u16) -> Option<usize> {
+        (!self.chr.writable)
+            .then(|| self.chr.offset(addr))
+            .flatten()
+    }
+
+    fn chr_rom_size(&self) -> usize {
+        match self.chr.writable {
+            true => 0,
+            false => self.chr.len(),
+        }
+    }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        for register in self.registers {
+            writer.u8(register);
+        }
+        self.prg_ram.save(writer);
+        self.chr.save(writer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), Error> {
+        for register in &mut self.registers {
+            *register = reader.u8()?;
+        }
+        self.prg_ram.load(reader)?;
+        self.chr.load(reader)?;
+        self.update_banks();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // PRG ROM of $FF, so that writes don't conflict, except that each 16K
+    // bank starts with its number. 4K CHR banks start with their number.
+    fn board(board: DiscreteBoard, prg_banks: usize, chr_banks: usize) -> DiscreteCartridge {
+        let mut header = [0; 16];
+        header[0..4].copy_from_slice(b"NES\x1a");
+        header[4] = prg_banks as u8;
+        header[5] = (chr_banks / 2) as u8;
+        let header = RomHeader::parse(&header).unwrap();
+        let mut prg = vec![0xff; prg_banks * 0x4000];
+        for bank in 0..prg_banks {
+            prg[bank * 0x4000] = bank as u8;
+        }
+        let mut chr = vec![0; chr_banks * 0x1000];
+        for bank in 0..chr_banks {
+            chr[bank * 0x1000] = bank as u8;
+        }
+        DiscreteCartridge::new(board, &header, &prg, &chr).unwrap()
+    }
+
+    fn prg_banks(cartridge: &DiscreteCartridge) -> (u8, u8) {
+        (cartridge.cpu_read(0x8000), cartridge.cpu_read(0xc000))
+    }
+
+    fn chr_banks(cartridge: &DiscreteCartridge) -> (u8, u8) {
+        (cartridge.chr.read(0x0000), cartridge.chr.read(0x1000))
+    }
+
+    #[test]
+    fn test_boards() {
+        let mut uxrom = board(DiscreteBoard::UxRom, 8, 0);
+        assert_eq!(prg_banks(&uxrom), (0, 7));
+        uxrom.cpu_write(0x8001, 5);
+        assert_eq!(prg_banks(&uxrom), (5, 7));
+        assert_eq!(uxrom.prg_rom_offset(0x8001), Some(5 * 0x4000 + 1));
+
+        let mut cnrom = board(DiscreteBoard::CnRom, 2, 8);
+        cnrom.cpu_write(0x8001, 3);
+        assert_eq!((prg_banks(&cnrom), chr_banks(&cnrom)), ((0, 1), (6, 7)));
+
+        let mut axrom = board(DiscreteBoard::AxRom, 16, 0);
+        axrom.cpu_write(0x8001, 0x13);
+        assert_eq!(prg_banks(&axrom), (6, 7));
+        assert_eq!(axrom.mirroring(), Mirroring::SingleScreenUpper);
+
+        let mut color_dreams = board(DiscreteBoard::ColorDreams, 8, 8);
+        color_dreams.cpu_write(0x8001, 0x31);
+        assert_eq!(
+            (prg_banks(&color_dreams), chr_banks(&color_dreams)),
+            ((2, 3), (6, 7))
+        );
+
+        let mut bnrom = board(DiscreteBoard::BnRom, 8, 0);
+        bnrom.cpu_write(0xffff, 2);
+        assert_eq!(prg_banks(&bnrom), (4, 5));
+
+        let mut gxrom = board(DiscreteBoard::GxRom, 8, 8);
+        gxrom.cpu_write(0x8001, 0x12);
+        assert_eq!((prg_banks(&gxrom), chr_banks(&gxrom)), ((2, 3), (4, 5)));
+    }
+
+    #[test]
+    fn test_nina_001() {
+        let mut nina = board(DiscreteBoard::Nina001, 4, 16);
+        nina.cpu_write(0x8000, 1);
+        assert_eq!(prg_banks(&nina), (0, 1));
+        nina.cpu_write(0x7ffd, 1);
+        nina.cpu_write(0x7ffe, 9);
+        nina.cpu_write(0x7fff, 12);
+        assert_eq!((prg_banks(&nina), chr_banks(&nina)), ((2, 3), (9, 12)));
+        assert_eq!(nina.cpu_read(0x7ffe), 9);
+    }
+
+    #[test]
+    fn test_bus_conflicts() {
+        // $C000 holds 7, so only the bits 5 shares with it get through
+        let mut uxrom = board(DiscreteBoard::UxRom, 8, 0);
+        uxrom.cpu_write(0xc000, 0x05);
+        assert_eq!(prg_banks(&uxrom), (5, 7));
+        uxrom.cpu_write(0xc000, 0x0a);
+        assert_eq!(prg_banks(&uxrom), (2, 7));
+        uxrom.cpu_write(0x8000, 0x06);
+        assert_eq!(prg_banks(&uxrom), (2, 7));
+
+        let mut axrom = board(DiscreteBoard::AxRom, 8, 0);
+        axrom.cpu_write(0x8000, 0x02);
+        assert_eq!(prg_banks(&axrom), (4, 5));
+    }
+
+    #[test]
+    fn test_prg_size() {
+        let header = RomHeader::parse(b"NES\x1a\x01\x00\x20\0\0\0\0\0\0\0\0\0").unwrap();
+        for size in [0, 0x2000, 0x6000] {
+            assert_eq!(
+                DiscreteCartridge::new(DiscreteBoard::UxRom, &header, &vec![0; size], &[]).err(),
+                Some(Error::InvalidHeader {
+                    field: "PRG ROM size",
+                    value: size as u32
+                })
+            );
+        }
+        assert!(DiscreteCartridge::new(DiscreteBoard::CnRom, &header, &[0; 0x4000], &[]).is_ok());
+    }
+}
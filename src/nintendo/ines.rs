@@ -1,24 +1,381 @@
-use super::cartridge::{Cartridge, NROMCartridge};
+use super::cartridge::{Cartridge, Mirroring};
+use super::mapper;
+use crate::Error;
 
-// panics if the rom is invalid
-pub fn parse(rom: &[u8]) -> Box<dyn Cartridge> {
-    let header = &rom[0..16];
+const MAGIC: [u8; 4] = [0x4e, 0x45, 0x53, 0x1a];
+const HEADER_SIZE: usize = 16;
+const TRAINER_SIZE: usize = 512;
 
-    // check magic number
-    assert_eq!(header[0], 0x4e);
-    assert_eq!(header[1], 0x45);
-    assert_eq!(header[2], 0x53);
-    assert_eq!(header[3], 0x1a);
+// Which TV standard the game was made for
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Timing {
+    Ntsc,
+    Pal,
+    // Runs on either
+    MultiRegion,
+    Dendy,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConsoleType {
+    Nes,
+    VsSystem,
+    Playchoice10,
+    // NES 2.0's extended console types, such as Famiclones with decimal mode
+    Extended(u8),
+}
+
+// Everything the 16 byte header of an iNES or NES 2.0 file says. Sizes are
+// in bytes. iNES 1.0 files can't describe everything NES 2.0 can, so the
+// rest is filled in the way emulators usually guess it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RomHeader {
+    pub nes2: bool,
+    pub mapper: u16,
+    pub submapper: u8,
+    pub prg_rom_size: usize,
+    pub chr_rom_size: usize,
+    // Work RAM at $6000-$7FFF, and the part of it kept by a battery
+    pub prg_ram_size: usize,
+    pub prg_nvram_size: usize,
+    pub chr_ram_size: usize,
+    pub chr_nvram_size: usize,
+    pub mirroring: Mirroring,
+    pub battery: bool,
+    // 512 bytes between the header and PRG ROM, meant to be loaded at $7000
+    pub trainer: bool,
+    pub timing: Timing,
+    pub console: ConsoleType,
+}
+
+// NES 2.0 ROM sizes are a count of banks, or when the high nibble is $F, a
+// multiplier and a power of two
+fn rom_size(field: &'static str, lsb: u8, msb: u8, bank_size: usize) -> Result<usize, Error> {
+    if msb != 0x0f {
+        return Ok(((msb as usize) << 8 | lsb as usize) * bank_size);
+    }
+    let multiplier = (lsb & 0x03) as usize * 2 + 1;
+    1usize
+        .checked_shl((lsb >> 2) as u32)
+        .and_then(|size| size.checked_mul(multiplier))
+        .ok_or(Error::InvalidHeader {
+            field,
+            value: lsb as u32,
+        })
+}
+
+// NES 2.0 RAM sizes are shift counts, with 0 meaning none
+fn ram_size(shift: u8) -> usize {
+    match shift {
+        0 => 0,
+        shift => 64 << shift,
+    }
+}
+
+impl RomHeader {
+    pub fn parse(rom: &[u8]) -> Result<Self, Error> {
+        if rom.len() < HEADER_SIZE {
+            return Err(Error::TruncatedRom {
+                expected: HEADER_SIZE,
+                actual: rom.len(),
+            });
+        }
+        let header = &rom[0..HEADER_SIZE];
+
+        // check magic number
+        if header[0..4] != MAGIC {
+            return Err(Error::InvalidHeader {
+                field: "magic number",
+                value: u32::from_be_bytes(header[0..4].try_into().unwrap()),
+            });
+        }
+
+        let mirroring = match (header[6] & 0x08 != 0, header[6] & 0x01 != 0) {
+            (true, _) => Mirroring::FourScreen,
+            (false, true) => Mirroring::Vertical,
+            (false, false) => Mirroring::Horizontal,
+        };
+        let battery = header[6] & 0x02 != 0;
+        let trainer = header[6] & 0x04 != 0;
+        let console = match header[7] & 0x03 {
+            0 => ConsoleType::Nes,
+            1 => ConsoleType::VsSystem,
+            2 => ConsoleType::Playchoice10,
+            _ => ConsoleType::Extended(header[13] & 0x0f),
+        };
+
+        if header[7] & 0x0c == 0x08 {
+            return Ok(Self {
+                nes2: true,
+                mapper: (header[6] >> 4) as u16
+                    | (header[7] & 0xf0) as u16
+                    | ((header[8] & 0x0f) as u16) << 8,
+                submapper: header[8] >> 4,
+                prg_rom_size: rom_size("PRG ROM size", header[4], header[9] & 0x0f, 0x4000)?,
+                chr_rom_size: rom_size("CHR ROM size", header[5], header[9] >> 4, 0x2000)?,
+                prg_ram_size: ram_size(header[10] & 0x0f),
+                prg_nvram_size: ram_size(header[10] >> 4),
+                chr_ram_size: ram_size(header[11] & 0x0f),
+                chr_nvram_size: ram_size(header[11] >> 4),
+                mirroring,
+                battery,
+                trainer,
+                timing: match header[12] & 0x03 {
+                    0 => Timing::Ntsc,
+                    1 => Timing::Pal,
+                    2 => Timing::MultiRegion,
+                    _ => Timing::Dendy,
+                },
+                console,
+            });
+        }
+
+        // Old dumping tools wrote their name into bytes 7-15, so when the
+        // end of the header isn't blank, byte 7 can't be trusted either
+        let tail_blank = header[12..16].iter().all(|byte| *byte == 0);
+        let (mapper_high, console) = match tail_blank {
+            true => (header[7] & 0xf0, console),
+            false => (0, ConsoleType::Nes),
+        };
+        let chr_rom_size = header[5] as usize * 0x2000;
+        // A size of 0 means 8K, which is also what games without a size
+        // were given
+        let prg_ram_size = match (tail_blank, header[8]) {
+            (true, banks) if banks > 0 => banks as usize * 0x2000,
+            _ => 0x2000,
+        };
+        Ok(Self {
+            nes2: false,
+            mapper: (header[6] >> 4 | mapper_high) as u16,
+            submapper: 0,
+            prg_rom_size: header[4] as usize * 0x4000,
+            chr_rom_size,
+            prg_ram_size: if battery { 0 } else { prg_ram_size },
+            prg_nvram_size: if battery { prg_ram_size } else { 0 },
+            // Boards without CHR ROM have 8K of CHR RAM
+            chr_ram_size: if chr_rom_size == 0 { 0x2000 } else { 0 },
+            chr_nvram_size: 0,
+            mirroring,
+            battery,
+            trainer,
+            timing: match tail_blank && header[9] & 0x01 != 0 {
+                true => Timing::Pal,
+                false => Timing::Ntsc,
+            },
+            console,
+        })
+    }
+
+    // Where PRG ROM starts in the file, after the header and any trainer
+    pub fn prg_rom_offset(&self) -> usize {
+        HEADER_SIZE + if self.trainer { TRAINER_SIZE } else { 0 }
+    }
+
+    pub fn chr_rom_offset(&self) -> usize {
+        self.prg_rom_offset().saturating_add(self.prg_rom_size)
+    }
+}
 
-    let prg_size = 0x4000 * (header[4] as usize);
-    let chr_size = 0x2000 * (header[5] as usize);
+pub fn parse(rom: &[u8]) -> Result<Box<dyn Cartridge>, Error> {
+    let header = RomHeader::parse(rom)?;
 
-    // ignoring trainers for now
-    let chr_start_offset = prg_size + 16;
-    let chr_end_offset = chr_start_offset + chr_size;
+    let chr_start_offset = header.chr_rom_offset();
+    let chr_end_offset = chr_start_offset.saturating_add(header.chr_rom_size);
+    if rom.len() < chr_end_offset {
+        return Err(Error::TruncatedRom {
+            expected: chr_end_offset,
+            actual: rom.len(),
+        });
+    }
 
-    let prg = &rom[16..chr_start_offset];
+    let prg = &rom[header.prg_rom_offset()..chr_start_offset];
     let chr = &rom[chr_start_offset..chr_end_offset];
 
-    Box::new(NROMCartridge::new(prg, chr))
+    mapper::create(&header, prg, chr)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rom(prg_banks: u8, chr_banks: u8) -> Vec<u8> {
+        let mut rom = vec![0x4e, 0x45, 0x53, 0x1a, prg_banks, chr_banks];
+        rom.resize(16 + 0x4000 * prg_banks as usize + 0x2000 * chr_banks as usize, 0);
+        rom
+    }
+
+    #[test]
+    fn test_parse() {
+        assert!(parse(&rom(1, 1)).is_ok());
+
+        // No CHR ROM means 8K of CHR RAM
+        let mut cartridge = parse(&rom(2, 0)).unwrap();
+        cartridge.chr_mut().write(0x1fff, 0x42);
+        assert_eq!(cartridge.chr().read(0x1fff), 0x42);
+        assert_eq!(cartridge.chr_rom_size(), 0);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(
+            parse(&[0x4e, 0x45]).err(),
+            Some(Error::TruncatedRom {
+                expected: 16,
+                actual: 2
+            })
+        );
+
+        let mut bad_magic = rom(1, 1);
+        bad_magic[3] = 0x00;
+        assert_eq!(
+            parse(&bad_magic).err(),
+            Some(Error::InvalidHeader {
+                field: "magic number",
+                value: 0x4e455300
+            })
+        );
+
+        let mut truncated = rom(2, 1);
+        truncated.truncate(0x8000);
+        assert_eq!(
+            parse(&truncated).err(),
+            Some(Error::TruncatedRom {
+                expected: 0xa010,
+                actual: 0x8000
+            })
+        );
+
+        assert_eq!(
+            parse(&rom(3, 1)).err(),
+            Some(Error::InvalidHeader {
+                field: "PRG ROM size",
+                value: 0xc000
+            })
+        );
+
+        // The mapper number is split across two nibbles
+        let mut mapper = rom(1, 1);
+        mapper[6] = 0x51;
+        mapper[7] = 0xa0;
+        assert_eq!(
+            parse(&mapper).err(),
+            Some(Error::UnsupportedMapper { mapper: 0xa5 })
+        );
+    }
+
+    #[test]
+    fn test_mirroring() {
+        let mut vertical = rom(1, 1);
+        vertical[6] = 0x01;
+        assert_eq!(parse(&vertical).unwrap().mirroring(), Mirroring::Vertical);
+        assert_eq!(
+            parse(&rom(1, 1)).unwrap().mirroring(),
+            Mirroring::Horizontal
+        );
+        vertical[6] = 0x09;
+        assert_eq!(parse(&vertical).unwrap().mirroring(), Mirroring::FourScreen);
+    }
+
+    #[test]
+    fn test_trainer() {
+        // CHR ROM comes after the trainer as well as PRG ROM
+        let mut rom = rom(1, 1);
+        rom[6] = 0x04;
+        rom.splice(16..16, [0xff; 512]);
+        rom[16 + 512 + 0x4000] = 0x42;
+        let header = RomHeader::parse(&rom).unwrap();
+        assert!(header.trainer);
+        assert_eq!(header.prg_rom_offset(), 528);
+        let cartridge = parse(&rom).unwrap();
+        assert_eq!(cartridge.chr().read(0), 0x42);
+        assert_eq!(cartridge.cpu_read(0x8000), 0);
+    }
+
+    #[test]
+    fn test_ines_header() {
+        let mut rom = rom(2, 0);
+        rom[6] = 0x13;
+        rom[7] = 0x41;
+        rom[8] = 2;
+        rom[9] = 1;
+        assert_eq!(
+            RomHeader::parse(&rom),
+            Ok(RomHeader {
+                nes2: false,
+                mapper: 0x41,
+                submapper: 0,
+                prg_rom_size: 0x8000,
+                chr_rom_size: 0,
+                prg_ram_size: 0,
+                prg_nvram_size: 0x4000,
+                chr_ram_size: 0x2000,
+                chr_nvram_size: 0,
+                mirroring: Mirroring::Vertical,
+                battery: true,
+                trainer: false,
+                timing: Timing::Pal,
+                console: ConsoleType::VsSystem,
+            })
+        );
+
+        // Junk at the end of the header means byte 7 is junk too
+        rom[7] = 0x44;
+        rom[12..16].copy_from_slice(b"Dude");
+        let header = RomHeader::parse(&rom).unwrap();
+        assert_eq!(header.mapper, 0x01);
+        assert_eq!(header.console, ConsoleType::Nes);
+        assert_eq!(header.prg_nvram_size, 0x2000);
+    }
+
+    #[test]
+    fn test_nes2_header() {
+        let mut header = [0; 16];
+        header[0..4].copy_from_slice(&MAGIC);
+        header[4] = 0x02;
+        header[5] = 0x00;
+        header[6] = 0x4a;
+        header[7] = 0x0b;
+        header[8] = 0x31;
+        header[9] = 0x00;
+        header[10] = 0x70;
+        header[11] = 0x07;
+        header[12] = 0x03;
+        header[13] = 0x05;
+        assert_eq!(
+            RomHeader::parse(&header),
+            Ok(RomHeader {
+                nes2: true,
+                mapper: 0x104,
+                submapper: 3,
+                prg_rom_size: 0x8000,
+                chr_rom_size: 0,
+                prg_ram_size: 0,
+                prg_nvram_size: 0x2000,
+                chr_ram_size: 0x2000,
+                chr_nvram_size: 0,
+                mirroring: Mirroring::FourScreen,
+                battery: true,
+                trainer: false,
+                timing: Timing::Dendy,
+                console: ConsoleType::Extended(5),
+            })
+        );
+
+        // 2^10 * 3 bytes of PRG ROM, and 0x103 banks of CHR ROM
+        header[4] = 10 << 2 | 1;
+        header[5] = 0x03;
+        header[9] = 0x1f;
+        let parsed = RomHeader::parse(&header).unwrap();
+        assert_eq!(parsed.prg_rom_size, 3072);
+        assert_eq!(parsed.chr_rom_size, 0x103 * 0x2000);
+
+        header[4] = 63 << 2 | 3;
+        assert_eq!(
+            RomHeader::parse(&header),
+            Err(Error::InvalidHeader {
+                field: "PRG ROM size",
+                value: 0xff
+            })
+        );
+    }
 }
@@ -0,0 +1,98 @@
+use super::cartridge::{Cartridge, NROMCartridge};
+use super::discrete::{DiscreteBoard, DiscreteCartridge};
+use super::mmc1::Mmc1Cartridge;
+use super::mmc3::Mmc3Cartridge;
+use super::RomHeader;
+use crate::Error;
+
+// Builds a cartridge from the ROM file's header and its PRG and CHR ROM
+type Constructor = fn(&RomHeader, &[u8], &[u8]) -> Result<Box<dyn Cartridge>, Error>;
+
+// Every mapper we emulate, by iNES mapper number
+const MAPPERS: &[(u16, &str, Constructor)] = &[
+    (0, "NROM", |header, prg, chr| {
+        Ok(Box::new(NROMCartridge::new(header, prg, chr)?))
+    }),
+    (1, "MMC1", |header, prg, chr| {
+        Ok(Box::new(Mmc1Cartridge::new(header, prg, chr)))
+    }),
+    (2, "UxROM", |header, prg, chr| {
+        discrete(DiscreteBoard::UxRom, header, prg, chr)
+    }),
+    (3, "CNROM", |header, prg, chr| {
+        discrete(DiscreteBoard::CnRom, header, prg, chr)
+    }),
+    (4, "MMC3", |header, prg, chr| {
+        Ok(Box::new(Mmc3Cartridge::new(header, prg, chr)?))
+    }),
+    (7, "AxROM", |header, prg, chr| {
+        discrete(DiscreteBoard::AxRom, header, prg, chr)
+    }),
+    (11, "Color Dreams", |header, prg, chr| {
+        discrete(DiscreteBoard::ColorDreams, header, prg, chr)
+    }),
+    (34, "BNROM/NINA-001", |header, prg, chr| {
+        discrete(DiscreteBoard::mapper_34(header, chr), header, prg, chr)
+    }),
+    (66, "GxROM", |header, prg, chr| {
+        discrete(DiscreteBoard::GxRom, header, prg, chr)
+    }),
+];
+
+fn discrete(
+    board: DiscreteBoard,
+    header: &RomHeader,
+    prg: &[u8],
+    chr: &[u8],
+) -> Result<Box<dyn Cartridge>, Error> {
+    Ok(Box::new(DiscreteCartridge::new(board, header, prg, chr)?))
+}
+
+pub fn mapper_name(mapper: u16) -> Option<&'static str> {
+    MAPPERS
+        .iter()
+        .find(|(number, _, _)| *number == mapper)
+        .map(|(_, name, _)| *name)
+}
+
+pub fn supported_mappers() -> impl Iterator<Item = (u16, &'static str)> {
+    MAPPERS.iter().map(|(number, name, _)| (*number, *name))
+}
+
+pub(crate) fn create(
+    header: &RomHeader,
+    prg: &[u8],
+    chr: &[u8],
+) -> Result<Box<dyn Cartridge>, Error> {
+    let (_, _, constructor) = MAPPERS
+        .iter()
+        .find(|(number, _, _)| *number == header.mapper)
+        .ok_or(Error::UnsupportedMapper {
+            mapper: header.mapper,
+        })?;
+    constructor(header, prg, chr)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nintendo::Mirroring;
+
+    #[test]
+    fn test_create() {
+        assert_eq!(mapper_name(0), Some("NROM"));
+        assert_eq!(mapper_name(4000), None);
+
+        let mut header = RomHeader::parse(&[
+            0x4e, 0x45, 0x53, 0x1a, 1, 1, 0x01, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        ])
+        .unwrap();
+        let cartridge = create(&header, &[0; 0x4000], &[0; 0x2000]).unwrap();
+        assert_eq!(cartridge.mirroring(), Mirroring::Vertical);
+        header.mapper = 4000;
+        assert_eq!(
+            create(&header, &[0; 0x4000], &[]).err(),
+            Some(Error::UnsupportedMapper { mapper: 4000 })
+        );
+    }
+}
@@ -1,16 +1,33 @@
 use crate::memory::{self, Memory};
+use crate::snapshot::{SaveState, StateReader, StateWriter};
+use crate::Error;
 
 use super::cartridge::Cartridge;
+use super::ppu::Ppu;
 
+// The PPU's registers, as the CPU sees them. The PPU is owned by Nes.
 #[derive(Debug, Clone)]
-pub struct PpuProxy;
+pub struct PpuProxy {
+    pub ppu: *mut Ppu,
+}
 
 impl Memory for PpuProxy {
-    fn read(&self, _addr: u16) -> u8 {
-        0
+    fn read(&self, addr: u16) -> u8 {
+        unsafe { (*self.ppu).register(addr) }
     }
 
-    fn write(&mut self, _addr: u16, _data: u8) {}
+    fn read_bus(&mut self, addr: u16) -> u8 {
+        unsafe { (*self.ppu).read_register(addr) }
+    }
+
+    // Reading a register can change it
+    fn peek(&self, _addr: u16) -> Option<u8> {
+        None
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        unsafe { (*self.ppu).write_register(addr, data) }
+    }
 
     fn length(&self) -> usize {
         0x8
@@ -40,20 +57,31 @@ pub struct NesMemoryMap {
 }
 
 impl NesMemoryMap {
-    pub fn new(cartridge: *mut dyn Cartridge) -> Self {
+    pub fn new(cartridge: *mut dyn Cartridge, ppu: *mut Ppu) -> Self {
         Self {
             mirrored_ram: memory::MirroredMemory::new(
                 memory::RandomAccessMemory::new(0x0800),
                 0x07ff,
                 0x2000,
             ),
-            ppu_proxy: memory::MirroredMemory::new(PpuProxy, 0x0007, 0x2000),
+            ppu_proxy: memory::MirroredMemory::new(PpuProxy { ppu }, 0x0007, 0x2000),
             apu_io_proxy: ApuIoProxy,
             cartridge,
         }
     }
 }
 
+// The cartridge is saved by Nes, which owns it
+impl SaveState for NesMemoryMap {
+    fn save(&self, writer: &mut StateWriter) {
+        self.mirrored_ram.save(writer);
+    }
+
+    fn load(&mut self, reader: &mut StateReader) -> Result<(), Error> {
+        self.mirrored_ram.load(reader)
+    }
+}
+
 impl Memory for NesMemoryMap {
     fn read(&self, addr: u16) -> u8 {
         match addr {
@@ -64,6 +92,23 @@ impl Memory for NesMemoryMap {
         }
     }
 
+    fn read_bus(&mut self, addr: u16) -> u8 {
+        match addr {
+            0x2000..=0x3fff => self.ppu_proxy.read_bus(addr - 0x2000),
+            0x4000..=0x401f => self.apu_io_proxy.read_bus(addr - 0x4000),
+            _ => self.read(addr),
+        }
+    }
+
+    // The PPU and APU registers can't be peeked at
+    fn peek(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x0000..=0x1fff => Some(self.mirrored_ram.read(addr)),
+            0x2000..=0x401f => None,
+            _ => unsafe { (*self.cartridge).cpu_peek(addr) },
+        }
+    }
+
     fn write(&mut self, addr: u16, data: u8) {
         match addr {
             0x0000..=0x1fff => self.mirrored_ram.write(addr, data),
@@ -73,6 +118,15 @@ impl Memory for NesMemoryMap {
         }
     }
 
+    // Only the cartridge cares, for mappers like MMC1 that ignore the second
+    // write of a read-modify-write instruction
+    fn write_consecutive(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x4020..=0xffff => unsafe { (*self.cartridge).consecutive_write(addr, data) },
+            _ => self.write(addr, data),
+        }
+    }
+
     fn length(&self) -> usize {
         0x10000
     }
@@ -0,0 +1,365 @@
+use super::banks::BankedMemory;
+use super::cartridge::{Cartridge, Mirroring};
+use super::RomHeader;
+use crate::snapshot::{SaveState, StateReader, StateWriter};
+use crate::{Error, Memory};
+
+// The shift register is empty when this bit reaches bit 0
+const SHIFT_EMPTY: u8 = 0x10;
+
+// Nintendo's MMC1, on the SxROM boards. Its registers are loaded a bit at a
+// time through a serial port at $8000-$FFFF. Boards with 512K of PRG ROM
+// (SUROM, SXROM) or more than 8K of work RAM (SOROM, SXROM) use the spare
+// CHR bank bits to pick the PRG half and RAM bank; this follows the first
+// CHR register for both.
+pub struct Mmc1Cartridge {
+    prg: BankedMemory,
+    prg_ram: BankedMemory,
+    chr: BankedMemory,
+    shift: u8,
+    control: u8,
+    chr_banks: [u8; 2],
+    prg_bank: u8,
+}
+
+impl Mmc1Cartridge {
+    pub fn new(header: &RomHeader, prg: &[u8], chr: &[u8]) -> Self {
+        let mut cartridge = Self {
+            prg: BankedMemory::prg_rom(prg),
+            prg_ram: BankedMemory::prg_ram(header),
+            chr: BankedMemory::chr(header, chr),
+            shift: SHIFT_EMPTY,
+            // Starts with the last bank fixed at $C000, so the reset vector
+            // is always there
+            control: 0x0c,
+            chr_banks: [0; 2],
+            prg_bank: 0,
+        };
+        cartridge.update_banks();
+        cartridge
+    }
+
+    fn update_banks(&mut self) {
+        let outer = match self.prg.len() > 0x40000 {
+            true => (self.chr_banks[0] & 0x10) as usize,
+            false => 0,
+        };
+        let bank = outer | (self.prg_bank & 0x0f) as usize;
+        match (self.control >> 2) & 3 {
+            0 | 1 => self.prg.map(0x8000, 0x8000, bank >> 1),
+            2 => {
+                self.prg.map(0x8000, 0x4000, outer);
+                self.prg.map(0xc000, 0x4000, bank);
+            }
+            _ => {
+                self.prg.map(0x8000, 0x4000, bank);
+                self.prg.map(0xc000, 0x4000, outer | 0x0f);
+            }
+        }
+
+        match self.control & 0x10 {
+            0 => self
+                .chr
+                .map(0x0000, 0x2000, (self.chr_banks[0] >> 1) as usize),
+            _ => {
+                self.chr.map(0x0000, 0x1000, self.chr_banks[0] as usize);
+                self.chr.map(0x1000, 0x1000, self.chr_banks[1] as usize);
+            }
+        }
+
+        let ram_bank = match self.prg_ram.len() {
+            0x4000 => (self.chr_banks[0] >> 3) & 1,
+            0x8000 => (self.chr_banks[0] >> 2) & 3,
+            _ => 0,
+        };
+        self.prg_ram.map(0x6000, 0x2000, ram_bank as usize);
+    }
+
+    fn ram_enabled(&self) -> bool {
+        self.prg_bank & 0x10 == 0 && !self.prg_ram.is_empty()
+    }
+
+    fn write_register(&mut self, addr: u16, data: u8) {
+        if data & 0x80 != 0 {
+            self.shift = SHIFT_EMPTY;
+            self.control |= 0x0c;
+            self.update_banks();
+            return;
+        }
+        let full = self.shift & 1 != 0;
+        self.shift = self.shift >> 1 | (data & 1) << 4;
+        if !full {
+            return;
+        }
+
+        let value = self.shift;
+        self.shift = SHIFT_EMPTY;
+        match (addr >> 13) & 3 {
+            0 => self.control = value,
+            1 => self.chr_banks[0] = value,
+            2 => self.chr_banks[1] = value,
+            _ => self.prg_bank = value,
+        }
+        self.update_banks();
+    }
+}
+
+impl Cartridge for Mmc1Cartridge {
+    fn prg(&self) -> &dyn Memory {
+        &self.prg
+    }
+
+    fn prg_mut(&mut self) -> &mut dyn Memory {
+        &mut self.prg
+    }
+
+    fn chr(&self) -> &dyn Memory {
+        &self.chr
+    }
+
+    fn chr_mut(&mut self) -> &mut dyn Memory {
+        &mut self.chr
+    }
+
+    // Disabled work RAM reads as open bus, which is usually the high byte
+    // of the address
+    fn cpu_read(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7fff if self.ram_enabled() => self.prg_ram.read(addr),
+            0x8000..=0xffff => self.prg.read(addr),
+            _ => (addr >> 8) as u8,
+        }
+    }
+
+    fn cpu_peek(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x6000..=0x7fff if self.ram_enabled() => Some(self.prg_ram.read(addr)),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x6000..=0x7fff if self.ram_enabled() => self.prg_ram.write(addr, data),
+            0x8000..=0xffff => self.write_register(addr, data),
+            _ => {}
+        }
+    }
+
+    // The serial port ignores the second write of a read-modify-write
+    // instruction
+    fn consecutive_write(&mut self, addr: u16, data: u8) {
+        if addr < 0x8000 {
+            self.cpu_write(addr, data);
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match self.control & 3 {
+            0 => Mirroring::SingleScreenLower,
+            1 => Mirroring::SingleScreenUpper,
+            2 => Mirroring::Vertical,
+            _ => Mirroring::Horizontal,
+        }
+    }
+
+    fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        self.prg.offset(addr)
+    }
+
+    fn prg_rom_size(&self) -> usize {
+        self.prg.len()
+    }
+
+    fn chr_offset(&self, addr: u16) -> Option<usize> {
+        (!self.chr.writable)
+            .then(|| self.chr.offset(addr))
+            .flatten()
+    }
+
+    fn chr_rom_size(&self) -> usize {
+        match self.chr.writable {
+            true => 0,
+            false => self.chr.len(),
+        }
+    }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.u8(self.shift);
+        writer.u8(self.control);
+        writer.u8(self.chr_banks[0]);
+        writer.u8(self.chr_banks[1]);
+        writer.u8(self.prg_bank);
+        self.prg_ram.save(writer);
+        self.chr.save(writer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), Error> {
+        self.shift = reader.u8()?;
+        self.control = reader.u8()?;
+        self.chr_banks[0] = reader.u8()?;
+        self.chr_banks[1] = reader.u8()?;
+        self.prg_bank = reader.u8()?;
+        self.prg_ram.load(reader)?;
+        self.chr.load(reader)?;
+        self.update_banks();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nintendo::Nes;
+
+    // PRG ROM where each 16K bank starts with its number, and a header
+    // asking for `ram` bytes of work RAM and CHR RAM
+    fn board(prg_banks: usize, ram: usize) -> Mmc1Cartridge {
+        let mut header = [0; 16];
+        header[0..4].copy_from_slice(b"NES\x1a");
+        header[4] = prg_banks as u8;
+        header[6] = 0x10;
+        let mut header = RomHeader::parse(&header).unwrap();
+        header.prg_ram_size = ram;
+        let mut prg = vec![0; prg_banks * 0x4000];
+        for bank in 0..prg_banks {
+            prg[bank * 0x4000] = bank as u8;
+        }
+        Mmc1Cartridge::new(&header, &prg, &[])
+    }
+
+    fn load(cartridge: &mut Mmc1Cartridge, addr: u16, value: u8) {
+        for bit in 0..5 {
+            cartridge.cpu_write(addr, value >> bit & 1);
+        }
+    }
+
+    #[test]
+    fn test_prg_banks() {
+        let mut cartridge = board(8, 0x2000);
+        assert_eq!(cartridge.cpu_read(0xc000), 7);
+        load(&mut cartridge, 0xe000, 3);
+        assert_eq!(
+            (cartridge.cpu_read(0x8000), cartridge.cpu_read(0xc000)),
+            (3, 7)
+        );
+
+        // 16K at $C000, first bank fixed at $8000
+        load(&mut cartridge, 0x8000, 0x08);
+        assert_eq!(
+            (cartridge.cpu_read(0x8000), cartridge.cpu_read(0xc000)),
+            (0, 3)
+        );
+        assert_eq!(cartridge.mirroring(), Mirroring::SingleScreenLower);
+
+        // 32K, ignoring the low bit
+        load(&mut cartridge, 0x8000, 0x02);
+        assert_eq!(
+            (cartridge.cpu_read(0x8000), cartridge.cpu_read(0xc000)),
+            (2, 3)
+        );
+        assert_eq!(cartridge.mirroring(), Mirroring::Vertical);
+
+        // A write with bit 7 set empties the shift register and fixes the
+        // last bank again
+        cartridge.cpu_write(0x8000, 1);
+        cartridge.cpu_write(0x8000, 0x80);
+        assert_eq!(cartridge.shift, SHIFT_EMPTY);
+        assert_eq!(
+            (cartridge.cpu_read(0x8000), cartridge.cpu_read(0xc000)),
+            (3, 7)
+        );
+
+        // Work RAM, then switched off
+        cartridge.cpu_write(0x6123, 0x55);
+        assert_eq!(cartridge.cpu_read(0x6123), 0x55);
+        load(&mut cartridge, 0xe000, 0x10);
+        assert_eq!(cartridge.cpu_read(0x6123), 0x61);
+        cartridge.cpu_write(0x6123, 0x66);
+        load(&mut cartridge, 0xe000, 0);
+        assert_eq!(cartridge.cpu_read(0x6123), 0x55);
+    }
+
+    #[test]
+    fn test_chr_banks() {
+        let header = RomHeader::parse(b"NES\x1a\x02\x04\x10\0\0\0\0\0\0\0\0\0").unwrap();
+        let mut chr = vec![0; 0x8000];
+        for bank in 0..8 {
+            chr[bank * 0x1000] = bank as u8;
+        }
+        let mut cartridge = Mmc1Cartridge::new(&header, &[0; 0x8000], &chr);
+        load(&mut cartridge, 0xa000, 5);
+        assert_eq!(
+            (cartridge.chr.read(0x0000), cartridge.chr.read(0x1000)),
+            (4, 5)
+        );
+
+        // Two 4K banks
+        load(&mut cartridge, 0x8000, 0x1c);
+        load(&mut cartridge, 0xc000, 2);
+        assert_eq!(
+            (cartridge.chr.read(0x0000), cartridge.chr.read(0x1000)),
+            (5, 2)
+        );
+        assert_eq!(cartridge.chr_offset(0x1001), Some(0x2001));
+        assert_eq!(cartridge.chr_rom_size(), 0x8000);
+    }
+
+    #[test]
+    fn test_large_boards() {
+        // SUROM: the first CHR register picks the 256K half
+        let mut cartridge = board(32, 0x2000);
+        assert_eq!(cartridge.cpu_read(0xc000), 15);
+        load(&mut cartridge, 0xa000, 0x10);
+        load(&mut cartridge, 0xe000, 2);
+        assert_eq!(
+            (cartridge.cpu_read(0x8000), cartridge.cpu_read(0xc000)),
+            (18, 31)
+        );
+
+        // SXROM: and bits 2 and 3 pick the 8K RAM bank
+        let mut cartridge = board(32, 0x8000);
+        cartridge.cpu_write(0x6000, 1);
+        load(&mut cartridge, 0xa000, 0x08);
+        cartridge.cpu_write(0x6000, 2);
+        assert_eq!(cartridge.cpu_read(0x6000), 2);
+        load(&mut cartridge, 0xa000, 0);
+        assert_eq!(cartridge.cpu_read(0x6000), 1);
+        assert_eq!(cartridge.prg_ram.data[0x4000], 2);
+    }
+
+    #[test]
+    fn test_consecutive_writes() {
+        let mut rom = vec![
+            0x4e, 0x45, 0x53, 0x1a, 2, 0, 0x10, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        ];
+        rom.resize(16 + 0x8000, 0);
+        // LDA #$80; STA $8000; INC $8000
+        let program = [0xa9, 0x80, 0x8d, 0x00, 0x80, 0xee, 0x00, 0x80];
+        rom[16 + 0x4000..16 + 0x4000 + program.len()].copy_from_slice(&program);
+        rom[16] = 0x03;
+
+        // History reads each byte before it is written, which mustn't make
+        // the writes look further apart than they are
+        for history in [false, true] {
+            let mut nes = Nes::new(&rom).unwrap();
+            if history {
+                nes.cpu.enable_history(10);
+            }
+            nes.cpu.core.pc = 0xc000;
+            for _ in 0..3 {
+                nes.step().unwrap();
+            }
+
+            // Only the first of INC's two writes reaches the shift register
+            let cartridge = unsafe { &*(nes.cartridge as *const Mmc1Cartridge) };
+            assert_eq!(cartridge.shift, 0x18);
+
+            // And writing to the port can't be undone
+            if history {
+                assert!(!nes.cpu.step_back());
+                assert_eq!(cartridge.shift, 0x18);
+            }
+        }
+    }
+}
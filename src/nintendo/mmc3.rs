@@ -0,0 +1,444 @@
+use super::banks::BankedMemory;
+use super::cartridge::{Cartridge, Mirroring};
+use super::RomHeader;
+use crate::snapshot::{SaveState, StateReader, StateWriter};
+use crate::{Error, Memory};
+
+// A12 has to stay low for this many CPU cycles before it rising again
+// counts. Sprite fetches toggle it several times in a few dots, and the
+// chip only sees one edge per scanline.
+const A12_FILTER_CYCLES: u32 = 3;
+
+// Nintendo's MMC3, on the TxROM boards: 8K PRG banks, 1K and 2K CHR banks,
+// and a counter clocked by PPU A12 that raises an IRQ at a chosen scanline
+pub struct Mmc3Cartridge {
+    prg: BankedMemory,
+    prg_ram: BankedMemory,
+    chr: BankedMemory,
+    four_screen: bool,
+    bank_select: u8,
+    registers: [u8; 8],
+    mirroring: u8,
+    ram_protect: u8,
+    irq_latch: u8,
+    irq_counter: u8,
+    irq_reload: bool,
+    irq_enabled: bool,
+    irq_pending: bool,
+    a12: bool,
+    // CPU cycles since A12 was last seen high
+    a12_low_cycles: u32,
+}
+
+impl Mmc3Cartridge {
+    // The last two 8K banks are always mapped, so there must be at least two
+    pub fn new(header: &RomHeader, prg: &[u8], chr: &[u8]) -> Result<Self, Error> {
+        if prg.len() < 0x4000 || !prg.len().is_multiple_of(0x2000) {
+            return Err(Error::InvalidHeader {
+                field: "PRG ROM size",
+                value: prg.len() as u32,
+            });
+        }
+        let mut cartridge = Self {
+            prg: BankedMemory::prg_rom(prg),
+            prg_ram: BankedMemory::prg_ram(header),
+            chr: BankedMemory::chr(header, chr),
+            four_screen: header.mirroring == Mirroring::FourScreen,
+            bank_select: 0,
+            registers: [0, 2, 4, 5, 6, 7, 0, 1],
+            mirroring: 0,
+            ram_protect: 0,
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_reload: false,
+            irq_enabled: false,
+            irq_pending: false,
+            a12: false,
+            a12_low_cycles: 0,
+        };
+        cartridge.update_banks();
+        Ok(cartridge)
+    }
+
+    fn update_banks(&mut self) {
+        let r = self.registers.map(|register| register as usize);
+        // -2 and -1 wrap around to the last two banks
+        let second_last = self.prg.len() / 0x2000 - 2;
+        let (low, high) = match self.bank_select & 0x40 {
+            0 => (r[6] & 0x3f, second_last),
+            _ => (second_last, r[6] & 0x3f),
+        };
+        self.prg.map(0x8000, 0x2000, low);
+        self.prg.map(0xa000, 0x2000, r[7] & 0x3f);
+        self.prg.map(0xc000, 0x2000, high);
+        self.prg.map(0xe000, 0x2000, second_last + 1);
+
+        // Inversion swaps the 2K and 1K halves
+        let invert = match self.bank_select & 0x80 {
+            0 => 0,
+            _ => 0x1000,
+        };
+        self.chr.map(invert, 0x0800, r[0] >> 1);
+        self.chr.map(invert | 0x0800, 0x0800, r[1] >> 1);
+        for i in 0..4 {
+            let addr = (invert ^ 0x1000) | (i as u16) << 10;
+            self.chr.map(addr, 0x0400, r[2 + i]);
+        }
+    }
+
+    fn ram_readable(&self) -> bool {
+        self.ram_protect & 0x80 != 0 && !self.prg_ram.is_empty()
+    }
+
+    fn ram_writable(&self) -> bool {
+        self.ram_readable() && self.ram_protect & 0x40 == 0
+    }
+
+    fn write_register(&mut self, addr: u16, data: u8) {
+        match (addr & 0xe000, addr & 1) {
+            (0x8000, 0) => {
+                self.bank_select = data;
+                self.update_banks();
+            }
+            (0x8000, _) => {
+                self.registers[(self.bank_select & 7) as usize] = data;
+                self.update_banks();
+            }
+            (0xa000, 0) => self.mirroring = data & 1,
+            (0xa000, _) => self.ram_protect = data,
+            (0xc000, 0) => self.irq_latch = data,
+            // The counter is reloaded from the latch at the next clock
+            (0xc000, _) => {
+                self.irq_counter = 0;
+                self.irq_reload = true;
+            }
+            (0xe000, 0) => {
+                self.irq_enabled = false;
+                self.irq_pending = false;
+            }
+            _ => self.irq_enabled = true,
+        }
+    }
+
+    fn clock_counter(&mut self) {
+        if self.irq_counter == 0 || self.irq_reload {
+            self.irq_counter = self.irq_latch;
+            self.irq_reload = false;
+        } else {
+            self.irq_counter -= 1;
+        }
+        if self.irq_counter == 0 && self.irq_enabled {
+            self.irq_pending = true;
+        }
+    }
+}
+
+impl Cartridge for Mmc3Cartridge {
+    fn prg(&self) -> &dyn Memory {
+        &self.prg
+    }
+
+    fn prg_mut(&mut self) -> &mut dyn Memory {
+        &mut self.prg
+    }
+
+    fn chr(&self) -> &dyn Memory {
+        &self.chr
+    }
+
+    fn chr_mut(&mut self) -> &mut dyn Memory {
+        &mut self.chr
+    }
+
+    fn cpu_read(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7fff if self.ram_readable() => self.prg_ram.read(addr),
+            0x8000..=0xffff => self.prg.read(addr),
+            _ => (addr >> 8) as u8,
+        }
+    }
+
+    fn cpu_peek(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x6000..=0x7fff if self.ram_readable() => Some(self.prg_ram.read(addr)),
+            _ => None,
+        }
+    }
+
+    fn cpu_write(&mut self, addr: u16, data: u8) {
+        match addr {
+            0x6000..=0x7fff if self.ram_writable() => self.prg_ram.write(addr, data),
+            0x8000..=0xffff => self.write_register(addr, data),
+            _ => {}
+        }
+    }
+
+    fn ppu_address(&mut self, addr: u16) {
+        let a12 = addr & 0x1000 != 0;
+        if a12 && !self.a12 && self.a12_low_cycles >= A12_FILTER_CYCLES {
+            self.clock_counter();
+        }
+        if a12 {
+            self.a12_low_cycles = 0;
+        }
+        self.a12 = a12;
+    }
+
+    fn clock(&mut self) {
+        if !self.a12 {
+            self.a12_low_cycles = self.a12_low_cycles.saturating_add(1);
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match (self.four_screen, self.mirroring) {
+            (true, _) => Mirroring::FourScreen,
+            (false, 0) => Mirroring::Vertical,
+            (false, _) => Mirroring::Horizontal,
+        }
+    }
+
+    fn prg_rom_offset(&self, addr: u16) -> Option<usize> {
+        self.prg.offset(addr)
+    }
+
+    fn prg_rom_size(&self) -> usize {
+        self.prg.len()
+    }
+
+    fn chr_offset(&self, addr: u16) -> Option<usize> {
+        (!self.chr.writable)
+            .then(|| self.chr.offset(addr))
+            .flatten()
+    }
+
+    fn chr_rom_size(&self) -> usize {
+        match self.chr.writable {
+            true => 0,
+            false => self.chr.len(),
+        }
+    }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.u8(self.bank_select);
+        for register in self.registers {
+            writer.u8(register);
+        }
+        writer.u8(self.mirroring);
+        writer.u8(self.ram_protect);
+        writer.u8(self.irq_latch);
+        writer.u8(self.irq_counter);
+        writer.bool(self.irq_reload);
+        writer.bool(self.irq_enabled);
+        writer.bool(self.irq_pending);
+        writer.bool(self.a12);
+        writer.u32(self.a12_low_cycles);
+        self.prg_ram.save(writer);
+        self.chr.save(writer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), Error> {
+        self.bank_select = reader.u8()?;
+        for register in &mut self.registers {
+            *register = reader.u8()?;
+        }
+        self.mirroring = reader.u8()?;
+        self.ram_protect = reader.u8()?;
+        self.irq_latch = reader.u8()?;
+        self.irq_counter = reader.u8()?;
+        self.irq_reload = reader.bool()?;
+        self.irq_enabled = reader.bool()?;
+        self.irq_pending = reader.bool()?;
+        self.a12 = reader.bool()?;
+        self.a12_low_cycles = reader.u32()?;
+        self.prg_ram.load(reader)?;
+        self.chr.load(reader)?;
+        self.update_banks();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nintendo::Nes;
+
+    // 8K PRG and 1K CHR banks that each start with their number
+    fn rom(prg_banks: usize, chr_banks: usize) -> Vec<u8> {
+        let mut rom = vec![0x4e, 0x45, 0x53, 0x1a];
+        rom.extend([(prg_banks / 2) as u8, (chr_banks / 8) as u8, 0x40, 0]);
+        rom.resize(16, 0);
+        for bank in 0..prg_banks {
+            rom.push(bank as u8);
+            rom.resize(rom.len() + 0x1fff, 0);
+        }
+        for bank in 0..chr_banks {
+            rom.push(bank as u8);
+            rom.resize(rom.len() + 0x3ff, 0);
+        }
+        rom
+    }
+
+    fn board(prg_banks: usize, chr_banks: usize) -> Mmc3Cartridge {
+        let rom = rom(prg_banks, chr_banks);
+        let header = RomHeader::parse(&rom).unwrap();
+        let chr_start = header.chr_rom_offset();
+        Mmc3Cartridge::new(&header, &rom[16..chr_start], &rom[chr_start..]).unwrap()
+    }
+
+    fn prg_banks(cartridge: &Mmc3Cartridge) -> [u8; 4] {
+        [0x8000, 0xa000, 0xc000, 0xe000].map(|addr| cartridge.cpu_read(addr))
+    }
+
+    fn chr_banks(cartridge: &Mmc3Cartridge) -> [u8; 8] {
+        std::array::from_fn(|i| cartridge.chr.read(i as u16 * 0x400))
+    }
+
+    #[test]
+    fn test_banks() {
+        let mut cartridge = board(16, 64);
+        for (register, bank) in [10, 13, 40, 41, 42, 43, 5, 6].into_iter().enumerate() {
+            cartridge.cpu_write(0x8000, register as u8);
+            cartridge.cpu_write(0x8001, bank);
+        }
+        assert_eq!(prg_banks(&cartridge), [5, 6, 14, 15]);
+        // The low bit of the 2K banks is ignored
+        assert_eq!(chr_banks(&cartridge), [10, 11, 12, 13, 40, 41, 42, 43]);
+
+        cartridge.cpu_write(0x8000, 0xc0);
+        assert_eq!(prg_banks(&cartridge), [14, 6, 5, 15]);
+        assert_eq!(chr_banks(&cartridge), [40, 41, 42, 43, 10, 11, 12, 13]);
+        assert_eq!(cartridge.chr_offset(0x1000), Some(10 * 0x400));
+
+        assert_eq!(cartridge.mirroring(), Mirroring::Vertical);
+        cartridge.cpu_write(0xa000, 1);
+        assert_eq!(cartridge.mirroring(), Mirroring::Horizontal);
+
+        // Work RAM is off until enabled, and can be write protected
+        cartridge.cpu_write(0x6000, 1);
+        assert_eq!(cartridge.cpu_read(0x6000), 0x60);
+        assert_eq!(cartridge.cpu_peek(0x6000), None);
+        cartridge.cpu_write(0xa001, 0x80);
+        cartridge.cpu_write(0x6000, 2);
+        cartridge.cpu_write(0xa001, 0xc0);
+        cartridge.cpu_write(0x6000, 3);
+        assert_eq!(cartridge.cpu_read(0x6000), 2);
+        assert_eq!(cartridge.cpu_peek(0x6000), Some(2));
+
+        // Too small for the two fixed banks
+        assert_eq!(
+            crate::nintendo::parse(&rom(0, 8)).err(),
+            Some(Error::InvalidHeader {
+                field: "PRG ROM size",
+                value: 0
+            })
+        );
+    }
+
+    // One scanline's worth of A12: low for the background, then high
+    fn scanline(cartridge: &mut Mmc3Cartridge) {
+        cartridge.ppu_address(0x0000);
+        for _ in 0..100 {
+            cartridge.clock();
+        }
+        cartridge.ppu_address(0x1000);
+        // Sprite fetches wobble A12 too quickly to count again
+        cartridge.ppu_address(0x0ff0);
+        cartridge.clock();
+        cartridge.ppu_address(0x1010);
+    }
+
+    #[test]
+    fn test_irq_counter() {
+        let mut cartridge = board(4, 8);
+        cartridge.cpu_write(0xc000, 2);
+        cartridge.cpu_write(0xc001, 0);
+        cartridge.cpu_write(0xe001, 0);
+
+        // Reloads to 2, then counts 1 and 0
+        scanline(&mut cartridge);
+        assert_eq!(cartridge.irq_counter, 2);
+        scanline(&mut cartridge);
+        assert!(!cartridge.irq());
+        scanline(&mut cartridge);
+        assert!(cartridge.irq());
+
+        // Acknowledged and disabled, then reloaded from the latch again
+        cartridge.cpu_write(0xe000, 0);
+        assert!(!cartridge.irq());
+        scanline(&mut cartridge);
+        assert_eq!(cartridge.irq_counter, 2);
+        assert!(!cartridge.irq());
+
+        // A reload takes effect at the next clock
+        cartridge.cpu_write(0xe001, 0);
+        cartridge.cpu_write(0xc000, 5);
+        cartridge.cpu_write(0xc001, 0);
+        scanline(&mut cartridge);
+        assert_eq!(cartridge.irq_counter, 5);
+    }
+
+    #[test]
+    fn test_a12_filter() {
+        let mut cartridge = board(4, 8);
+        cartridge.cpu_write(0xc000, 5);
+        scanline(&mut cartridge);
+        assert_eq!(cartridge.irq_counter, 5);
+
+        // Two cycles low is too short to be a new scanline
+        cartridge.ppu_address(0x0000);
+        cartridge.clock();
+        cartridge.clock();
+        cartridge.ppu_address(0x1000);
+        assert_eq!(cartridge.irq_counter, 5);
+
+        cartridge.ppu_address(0x0000);
+        for _ in 0..3 {
+            cartridge.clock();
+        }
+        cartridge.ppu_address(0x1000);
+        assert_eq!(cartridge.irq_counter, 4);
+    }
+
+    #[test]
+    fn test_irq_reaches_cpu() {
+        let mut rom = rom(4, 8);
+        // Background from $0000 and sprites from $1000, so A12 rises once a
+        // line, rendering on, an IRQ after the first counted line, then CLI
+        // and wait. The IRQ handler at $E020 is INX; STA $E000; RTI.
+        let program = [
+            0xa9, 0x08, 0x8d, 0x00, 0x20, // LDA #$08; STA $2000
+            0xa9, 0x18, 0x8d, 0x01, 0x20, // LDA #$18; STA $2001
+            0xa9, 0x01, 0x8d, 0x00, 0xc0, // LDA #$01; STA $C000
+            0x8d, 0x01, 0xc0, 0x8d, 0x01, 0xe0, // STA $C001; STA $E001
+            0x58, 0x4c, 0x16, 0xe0, // CLI; JMP $E016
+        ];
+        let last = 16 + 3 * 0x2000;
+        rom[last..last + program.len()].copy_from_slice(&program);
+        rom[last + 0x20..last + 0x25].copy_from_slice(&[0xe8, 0x8d, 0x00, 0xe0, 0x40]);
+        rom[last + 0x1ffc..last + 0x2000].copy_from_slice(&[0x00, 0xe0, 0x20, 0xe0]);
+
+        let mut nes = Nes::new(&rom).unwrap();
+        nes.cpu.reset();
+        // The first line's sprite fetches reload the counter with 1, and the
+        // second's take it to 0
+        while nes.cpu.core.x == 0 && nes.ppu.frame == 0 {
+            nes.step().unwrap();
+        }
+        assert_eq!(nes.cpu.core.x, 1);
+        assert_eq!(nes.ppu.scanline, 1);
+        assert!(nes.ppu.dot > 260);
+
+        // Acknowledged and disabled, so it doesn't fire again
+        for _ in 0..10 {
+            nes.step().unwrap();
+        }
+        assert!(!nes.cpu.irq_asserted());
+        assert_eq!(nes.cpu.core.pc, 0xe016);
+        assert_eq!(nes.cpu.core.x, 1);
+    }
+}
@@ -1,9 +1,24 @@
+mod banks;
 mod cartridge;
+mod cdl;
+mod discrete;
 mod ines;
+mod mapper;
 mod memory;
+mod mmc1;
+mod mmc3;
 mod nes;
+mod nestest;
 mod ppu;
 
-pub use ines::parse;
+pub use banks::BankedMemory;
+pub use cartridge::{Cartridge, Mirroring, NROMCartridge};
+pub use cdl::CodeDataLog;
+pub use discrete::{DiscreteBoard, DiscreteCartridge};
+pub use ines::{parse, ConsoleType, RomHeader, Timing};
+pub use mapper::{mapper_name, supported_mappers};
 pub use memory::NesMemoryMap;
+pub use mmc1::Mmc1Cartridge;
+pub use mmc3::Mmc3Cartridge;
 pub use nes::Nes;
+pub use nestest::{compare_nestest, start_nestest, NestestMismatch};
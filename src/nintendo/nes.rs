@@ -1,28 +1,119 @@
-use crate::Processor;
+use crate::snapshot::{self, crc32, SaveState, SnapshotKind, StateReader, StateWriter};
+use crate::{Error, Processor, Step, TraceLogger};
 
-use super::{cartridge::Cartridge, ines, ppu::Ppu, NesMemoryMap};
+use super::{cartridge::Cartridge, ines, ppu::Ppu, CodeDataLog, NesMemoryMap, RomHeader};
 
 pub struct Nes {
+    pub header: RomHeader,
     pub cartridge: *mut dyn Cartridge,
     pub cpu: Processor<NesMemoryMap>,
-    pub ppu: Ppu,
+    // Boxed so that the CPU's memory map can keep pointing at it
+    pub ppu: Box<Ppu>,
+    // Snapshots remember which ROM they were taken with
+    rom_checksum: u32,
+    cdl: Option<CodeDataLog>,
 }
 
 impl Nes {
-    pub fn new(rom: &[u8]) -> Self {
-        let cartridge = ines::parse(rom);
+    pub fn new(rom: &[u8]) -> Result<Self, Error> {
+        let header = RomHeader::parse(rom)?;
+        let cartridge = ines::parse(rom)?;
         let cartridge_ptr = Box::into_raw(cartridge);
 
-        let ppu = Ppu::new(cartridge_ptr);
+        let mut ppu = Box::new(Ppu::new(cartridge_ptr));
 
-        let memory_map = NesMemoryMap::new(cartridge_ptr);
+        let memory_map = NesMemoryMap::new(cartridge_ptr, &mut *ppu);
         let cpu = Processor::with_memory(memory_map);
 
-        Self {
+        Ok(Self {
+            header,
             cartridge: cartridge_ptr,
             ppu,
             cpu,
+            rom_checksum: crc32(rom),
+            cdl: None,
+        })
+    }
+
+    // Runs one CPU step a cycle at a time, keeping the PPU and cartridge in
+    // time with it: three dots and one cartridge clock to a CPU cycle. The
+    // NMI and IRQ lines follow the PPU and cartridge after every cycle.
+    pub fn step(&mut self) -> Result<Step, Error> {
+        // The CPU reaches the PPU through its memory map while this runs
+        let (cartridge, ppu): (_, *mut Ppu) = (self.cartridge, &mut *self.ppu);
+        let step = self.cpu.step_cycles(|cpu| {
+            let ppu = unsafe { &mut *ppu };
+            ppu.advance(3);
+            cpu.set_nmi(ppu.nmi());
+            let cartridge = unsafe { &mut *cartridge };
+            cartridge.clock();
+            cpu.set_irq(cartridge.irq());
+        })?;
+
+        if let Some(cdl) = &mut self.cdl {
+            let cartridge = unsafe { &*self.cartridge };
+            cdl.record_step(cartridge, &step, self.cpu.bus_accesses(), self.cpu.core.pc);
+            for addr in self.ppu.memory.take_chr_fetches() {
+                cdl.record_chr(cartridge, addr);
+            }
         }
+        Ok(step)
+    }
+
+    // Starts a code/data log from nothing, which step() adds to
+    pub fn enable_cdl(&mut self) {
+        let cartridge = unsafe { &*self.cartridge };
+        self.cdl = Some(CodeDataLog::new(
+            cartridge.prg_rom_size(),
+            cartridge.chr_rom_size(),
+        ));
+        self.ppu.memory.record_chr_fetches(true);
+    }
+
+    pub fn disable_cdl(&mut self) -> Option<CodeDataLog> {
+        self.ppu.memory.record_chr_fetches(false);
+        self.cdl.take()
+    }
+
+    pub fn cdl(&self) -> Option<&CodeDataLog> {
+        self.cdl.as_ref()
+    }
+
+    // Logs the next instruction with the PPU's position
+    pub fn trace<W: std::io::Write>(&self, logger: &mut TraceLogger<W>) -> std::io::Result<()> {
+        logger.log(&self.cpu, Some(self.ppu.position()))
+    }
+
+    pub fn save_state(&self) -> Vec<u8> {
+        snapshot::write_snapshot(SnapshotKind::Nes, self)
+    }
+
+    // Only snapshots taken with the same ROM can be loaded. Nothing changes
+    // if the snapshot is rejected.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), Error> {
+        snapshot::read_snapshot(SnapshotKind::Nes, self, data)?;
+        self.cpu.clear_history();
+        Ok(())
+    }
+}
+
+impl SaveState for Nes {
+    fn save(&self, writer: &mut StateWriter) {
+        writer.u32(self.rom_checksum);
+        self.cpu.save(writer);
+        self.ppu.save(writer);
+        unsafe { (*self.cartridge).save_state(writer) };
+    }
+
+    fn load(&mut self, reader: &mut StateReader) -> Result<(), Error> {
+        if reader.u32()? != self.rom_checksum {
+            return Err(Error::InvalidSnapshot {
+                reason: "snapshot is for a different ROM",
+            });
+        }
+        self.cpu.load(reader)?;
+        self.ppu.load(reader)?;
+        unsafe { (*self.cartridge).load_state(reader) }
     }
 }
 
@@ -31,3 +122,59 @@ impl Drop for Nes {
         unsafe { drop(Box::from_raw(self.cartridge)) };
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Memory;
+
+    // One PRG bank holding a loop that counts in X and stores it in RAM
+    fn rom(marker: u8) -> Vec<u8> {
+        let mut rom = vec![0x4e, 0x45, 0x53, 0x1a, 1, 1];
+        rom.resize(16 + 0x4000 + 0x2000, 0);
+        let program = [0xe8, 0x86, 0x10, 0x4c, 0x00, 0x80];
+        rom[16..16 + program.len()].copy_from_slice(&program);
+        rom[16 + 0x3ffc..16 + 0x3ffe].copy_from_slice(&[0x00, 0x80]);
+        rom[16 + 0x4000] = marker;
+        rom
+    }
+
+    fn run(nes: &mut Nes, instructions: usize) {
+        for _ in 0..instructions {
+            nes.cpu.step().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_save_state() {
+        let mut nes = Nes::new(&rom(0)).unwrap();
+        nes.cpu.reset();
+        run(&mut nes, 10);
+        nes.ppu.oam[3] = 0x77;
+        nes.ppu.memory.write(0x2005, 0x12);
+        let snapshot = nes.save_state();
+
+        run(&mut nes, 9);
+        let (core, cycles) = (nes.cpu.core, nes.cpu.cycles);
+        nes.ppu.oam[3] = 0;
+        nes.ppu.memory.write(0x2005, 0);
+
+        nes.load_state(&snapshot).unwrap();
+        assert_eq!(nes.ppu.oam[3], 0x77);
+        assert_eq!(nes.ppu.memory.read(0x2005), 0x12);
+        assert_eq!(nes.cpu.memory.read(0x10), 3);
+        run(&mut nes, 9);
+        assert_eq!(nes.cpu.core, core);
+        assert_eq!(nes.cpu.cycles, cycles);
+
+        // A snapshot of one game can't be loaded into another
+        let mut other = Nes::new(&rom(1)).unwrap();
+        assert_eq!(
+            other.load_state(&snapshot),
+            Err(Error::InvalidSnapshot {
+                reason: "snapshot is for a different ROM"
+            })
+        );
+        assert!(nes.cpu.load_state(&snapshot).is_err());
+    }
+}
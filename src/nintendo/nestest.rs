@@ -0,0 +1,218 @@
+use std::fmt::Display;
+
+use super::Nes;
+use crate::{Error, TraceFormat, TraceLogger};
+
+// How many matching lines to show before the first difference
+const CONTEXT_LINES: usize = 5;
+
+// The registers nestest.log shows between the disassembly and the PPU
+const REGISTERS: [&str; 5] = ["A", "X", "Y", "P", "SP"];
+
+// nestest runs all of its tests without a PPU when started at $C000, as if
+// from a reset that took 7 cycles
+pub fn start_nestest(nes: &mut Nes) {
+    nes.cpu.core.pc = 0xc000;
+    nes.cpu.core.f.i = true;
+    nes.cpu.cycles = 7;
+    nes.ppu.scanline = 0;
+    nes.ppu.dot = 21;
+}
+
+// Splits a trace line into named fields, so that spacing doesn't matter and
+// a difference can be reported by name
+fn fields(line: &str) -> Vec<(&'static str, String)> {
+    let mut fields = Vec::new();
+    let column = |range: std::ops::Range<usize>| line.get(range).unwrap_or("").trim().to_string();
+    let registers = line.find(" A:").map_or(line.len(), |start| start + 1);
+    let ppu = line.find("PPU:").unwrap_or(line.len());
+    let cycles = line.find("CYC:").unwrap_or(line.len());
+
+    fields.push(("PC", column(0..4)));
+    fields.push(("bytes", column(6..15)));
+    fields.push(("disassembly", column(15..registers)));
+    for register in line[registers..ppu.max(registers)].split_whitespace() {
+        if let Some((name, value)) = register.split_once(':') {
+            if let Some(name) = REGISTERS.iter().find(|known| **known == name) {
+                fields.push((name, value.to_string()));
+            }
+        }
+    }
+    let ppu_dot: String = line
+        .get(ppu + 4..cycles)
+        .unwrap_or("")
+        .split_whitespace()
+        .collect();
+    fields.push(("PPU", ppu_dot));
+    fields.push((
+        "CYC",
+        line.get(cycles + 4..).unwrap_or("").trim().to_string(),
+    ));
+    fields
+}
+
+// The first line where our trace and the reference log disagree
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NestestMismatch {
+    // Counting from 1
+    pub line: usize,
+    pub fields: Vec<&'static str>,
+    pub expected: String,
+    pub actual: String,
+    // The lines just before, which matched
+    pub context: Vec<String>,
+}
+
+// Side by side, reference log on the left, with the line that differs marked
+// and its differing fields listed underneath
+impl Display for NestestMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "line {} differs in {}",
+            self.line,
+            self.fields.join(", ")
+        )?;
+        let width = self
+            .context
+            .iter()
+            .chain([&self.expected])
+            .map(|line| line.len())
+            .max()
+            .unwrap_or(0);
+        writeln!(f, "        {:width$} | emulator", "nestest.log")?;
+        let first = self.line - self.context.len();
+        for (i, line) in self.context.iter().enumerate() {
+            writeln!(f, "  {:5} {:width$} | {}", first + i, line, line)?;
+        }
+        writeln!(
+            f,
+            "> {:5} {:width$} | {}",
+            self.line, self.expected, self.actual
+        )?;
+
+        let expected = fields(&self.expected);
+        let actual = fields(&self.actual);
+        for name in &self.fields {
+            let value = |fields: &[(&str, String)]| {
+                fields
+                    .iter()
+                    .find(|(field, _)| field == name)
+                    .map_or(String::new(), |(_, value)| value.clone())
+            };
+            writeln!(
+                f,
+                "  {}: expected \"{}\", got \"{}\"",
+                name,
+                value(&expected),
+                value(&actual)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+// Runs the CPU alongside the reference log until a line differs or the log
+// ends. The Nes should already be where the log starts.
+pub fn compare_nestest(nes: &mut Nes, log: &str) -> Result<Option<NestestMismatch>, Error> {
+    let logger = TraceLogger::new(std::io::sink(), TraceFormat::Nestest);
+    let mut context = Vec::new();
+    for (i, expected) in log.lines().map(str::trim_end).enumerate() {
+        if expected.is_empty() {
+            continue;
+        }
+        let actual = logger.line(&nes.cpu, Some(nes.ppu.position()));
+
+        let expected_fields = fields(expected);
+        let actual_fields = fields(&actual);
+        let differing: Vec<_> = expected_fields
+            .iter()
+            .filter(|field| !actual_fields.contains(field))
+            .map(|(name, _)| *name)
+            .collect();
+        if !differing.is_empty() {
+            return Ok(Some(NestestMismatch {
+                line: i + 1,
+                fields: differing,
+                expected: expected.to_string(),
+                actual,
+                context,
+            }));
+        }
+
+        if context.len() == CONTEXT_LINES {
+            context.remove(0);
+        }
+        context.push(actual);
+        nes.step()?;
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A 16K NROM with a program at $C000
+    fn rom(program: &[u8]) -> Vec<u8> {
+        let mut rom = vec![0x4e, 0x45, 0x53, 0x1a, 1, 1];
+        rom.resize(16 + 0x4000 + 0x2000, 0);
+        rom[16..16 + program.len()].copy_from_slice(program);
+        rom
+    }
+
+    const LOG: &str = "\
+C000  A2 05     LDX #$05                        A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7
+C002  86 10     STX $10 = 00                    A:00 X:05 Y:00 P:24 SP:FD PPU:  0, 27 CYC:9
+C004  E8        INX                             A:00 X:05 Y:00 P:24 SP:FD PPU:  0, 36 CYC:12
+C005  4C 00 C0  JMP $C000                       A:00 X:06 Y:00 P:24 SP:FD PPU:  0, 42 CYC:14
+";
+
+    #[test]
+    fn test_compare() {
+        // LDX #$05; STX $10; INX; JMP $C000
+        let program = [0xa2, 0x05, 0x86, 0x10, 0xe8, 0x4c, 0x00, 0xc0];
+        let mut nes = Nes::new(&rom(&program)).unwrap();
+        start_nestest(&mut nes);
+        assert_eq!(compare_nestest(&mut nes, LOG), Ok(None));
+
+        // Spacing doesn't matter, but the P and CYC values do
+        let log = LOG.replace("INX      ", "INX").replace(
+            "X:05 Y:00 P:24 SP:FD PPU:  0, 36 CYC:12",
+            "X:05 Y:00 P:A4 SP:FD PPU:  0, 36 CYC:13",
+        );
+        let mut nes = Nes::new(&rom(&program)).unwrap();
+        start_nestest(&mut nes);
+        let mismatch = compare_nestest(&mut nes, &log).unwrap().unwrap();
+        assert_eq!(mismatch.line, 3);
+        assert_eq!(mismatch.fields, vec!["P", "CYC"]);
+        assert_eq!(mismatch.context.len(), 2);
+        assert!(mismatch.actual.ends_with("P:24 SP:FD PPU:  0, 36 CYC:12"));
+
+        let report = mismatch.to_string();
+        let lines: Vec<_> = report.lines().collect();
+        assert_eq!(lines[0], "line 3 differs in P, CYC");
+        assert!(lines[4].starts_with(">     3 C004  E8        INX"));
+        assert!(lines[4].ends_with("| C004  E8        INX                             A:00 X:05 Y:00 P:24 SP:FD PPU:  0, 36 CYC:12"));
+        assert_eq!(lines[5], "  P: expected \"A4\", got \"24\"");
+    }
+
+    // Put nestest.nes and nestest.log in tests/roms and run this with
+    // --ignored
+    #[test]
+    #[ignore = "needs tests/roms/nestest.nes and nestest.log"]
+    fn test_nestest() {
+        let dir = format!("{}/tests/roms", env!("CARGO_MANIFEST_DIR"));
+        let read = |name| {
+            let path = format!("{}/{}", dir, name);
+            std::fs::read(&path).unwrap_or_else(|err| panic!("can't read {}: {}", path, err))
+        };
+        let rom = read("nestest.nes");
+        let log = String::from_utf8(read("nestest.log")).unwrap();
+        let mut nes = Nes::new(&rom).unwrap();
+        start_nestest(&mut nes);
+        if let Some(mismatch) = compare_nestest(&mut nes, &log).unwrap() {
+            panic!("\n{}", mismatch);
+        }
+    }
+}
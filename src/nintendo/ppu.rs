@@ -1,11 +1,33 @@
 use super::cartridge::Cartridge;
-use crate::{Memory, MirroredMemory, RandomAccessMemory};
+use crate::snapshot::{SaveState, StateReader, StateWriter};
+use crate::{Error, Memory, MirroredMemory, PpuPosition, RandomAccessMemory};
+
+const DOTS_PER_SCANLINE: u16 = 341;
+const SCANLINES_PER_FRAME: u16 = 262;
+const VISIBLE_SCANLINES: u16 = 240;
+const VBLANK_SCANLINE: u16 = 241;
+const PRE_RENDER_SCANLINE: u16 = 261;
+
+// Bits of $2000
+const CTRL_INCREMENT_32: u8 = 0x04;
+const CTRL_SPRITE_TABLE: u8 = 0x08;
+const CTRL_BACKGROUND_TABLE: u8 = 0x10;
+const CTRL_TALL_SPRITES: u8 = 0x20;
+const CTRL_NMI: u8 = 0x80;
+// Showing either the background or sprites in $2001 turns rendering on
+const MASK_RENDERING: u8 = 0x18;
+// Bits of $2002: vblank, sprite 0 hit and sprite overflow
+const STATUS_VBLANK: u8 = 0x80;
+const STATUS_FLAGS: u8 = 0xe0;
 
 #[derive(Debug, Clone)]
 pub struct PpuMemory {
     pub ram: RandomAccessMemory,
     pub cartridge: *mut dyn Cartridge,
     pub palette_ram: MirroredMemory<RandomAccessMemory>,
+    // Pattern table addresses fetched since they were last taken, while the
+    // code/data logger wants them
+    chr_fetches: Option<Vec<u16>>,
 }
 
 impl PpuMemory {
@@ -14,32 +36,74 @@ impl PpuMemory {
             ram: RandomAccessMemory::new(0x1000),
             cartridge,
             palette_ram: MirroredMemory::new(RandomAccessMemory::new(0x0020), 0x001f, 0x0100),
+            chr_fetches: None,
+        }
+    }
+
+    pub(crate) fn record_chr_fetches(&mut self, record: bool) {
+        self.chr_fetches = record.then(Vec::new);
+    }
+
+    pub(crate) fn take_chr_fetches(&mut self) -> Vec<u16> {
+        self.chr_fetches.as_mut().map_or(Vec::new(), std::mem::take)
+    }
+
+    pub fn chr(&self) -> &dyn Memory {
+        unsafe { (*self.cartridge).chr() }
+    }
+
+    pub fn chr_mut(&mut self) -> &mut dyn Memory {
+        unsafe { (*self.cartridge).chr_mut() }
+    }
+
+    // $3000-$3EFF mirrors the nametables, and the cartridge decides which
+    // of them share RAM
+    fn nametable_addr(&self, addr: u16) -> u16 {
+        unsafe { (*self.cartridge).mirroring() }.nametable_addr(addr & 0x2fff)
+    }
+
+    // Accesses the PPU makes itself, while rendering or through $2007. The
+    // cartridge sees their addresses go by, as mappers like MMC3 watch the
+    // bus, and the code/data logger sees pattern table fetches; plain reads
+    // and writes, such as a debugger's, go unnoticed.
+    pub fn fetch(&mut self, addr: u16) -> u8 {
+        let addr = addr & 0x3fff;
+        self.put_on_bus(addr);
+        if let (Some(fetches), 0x0000..=0x1fff) = (&mut self.chr_fetches, addr) {
+            fetches.push(addr);
         }
+        self.read(addr)
     }
 
-    pub fn chr(&self) -> &mut dyn Memory {
-        unsafe { (&mut *self.cartridge).chr_mut() }
+    pub fn store(&mut self, addr: u16, data: u8) {
+        let addr = addr & 0x3fff;
+        self.put_on_bus(addr);
+        self.write(addr, data)
+    }
+
+    // Palette RAM is inside the PPU, so the cartridge only sees the rest
+    fn put_on_bus(&mut self, addr: u16) {
+        if addr < 0x3f00 {
+            unsafe { (*self.cartridge).ppu_address(addr) }
+        }
     }
 }
 
 impl Memory for PpuMemory {
+    // The PPU's address bus is 14 bits wide, so $4000 and up wrap around
     fn read(&self, addr: u16) -> u8 {
-        match addr {
-            0x0000..=0x1fff => self.chr().read(addr),
-            0x2000..=0x2fff => self.ram.read(addr & 0x1fff),
-            0x3000..=0x3eff => unimplemented!(),
-            0x3f00..=0x3fff => self.palette_ram.read(addr & 0x00ff),
-            _ => panic!("Invalid PPU address"),
+        match addr & 0x3fff {
+            addr @ 0x0000..=0x1fff => self.chr().read(addr),
+            addr @ 0x2000..=0x3eff => self.ram.read(self.nametable_addr(addr)),
+            addr => self.palette_ram.read(addr & 0x00ff),
         }
     }
 
     fn write(&mut self, addr: u16, data: u8) {
-        match addr {
-            0x0000..=0x1fff => self.chr().write(addr, data),
-            0x2000..=0x2fff => self.ram.write(addr & 0x1fff, data),
-            0x3000..=0x3eff => unimplemented!(),
-            0x3f00..=0x3fff => self.palette_ram.write(addr & 0x00ff, data),
-            _ => panic!("Invalid PPU address"),
+        match addr & 0x3fff {
+            addr @ 0x0000..=0x1fff => self.chr_mut().write(addr, data),
+            addr @ 0x2000..=0x3eff => self.ram.write(self.nametable_addr(addr), data),
+            addr => self.palette_ram.write(addr & 0x00ff, data),
         }
     }
 
@@ -52,6 +116,29 @@ impl Memory for PpuMemory {
 pub struct Ppu {
     pub memory: PpuMemory,
     pub oam: [u8; 0x100],
+    // 0-239 are visible, 261 is the pre-render line
+    pub scanline: u16,
+    pub dot: u16,
+    pub frame: u64,
+    pub ctrl: u8,
+    pub mask: u8,
+    pub status: u8,
+    pub oam_addr: u8,
+    // The current and temporary VRAM addresses, fine X scroll and the write
+    // toggle shared by $2005 and $2006, named as on the nesdev wiki
+    pub v: u16,
+    pub t: u16,
+    pub x: u8,
+    pub w: bool,
+    // What the last $2007 read fetched, which the next one returns
+    read_buffer: u8,
+    // The last value put on the register bus, which write-only registers
+    // read back as
+    latch: u8,
+    // The nametable byte whose pattern is being fetched, and where the
+    // patterns of the sprites on the next line are
+    tile: u8,
+    sprite_patterns: [u16; 8],
 }
 
 impl Ppu {
@@ -59,6 +146,558 @@ impl Ppu {
         Self {
             memory: PpuMemory::new(cartridge),
             oam: [0; 0x100],
+            scanline: 0,
+            dot: 0,
+            frame: 0,
+            ctrl: 0,
+            mask: 0,
+            status: 0,
+            oam_addr: 0,
+            v: 0,
+            t: 0,
+            x: 0,
+            w: false,
+            read_buffer: 0,
+            latch: 0,
+            tile: 0,
+            sprite_patterns: [0; 8],
+        }
+    }
+
+    // Moves the beam on a dot at a time, making the memory fetches that
+    // rendering would. Nothing is drawn, but mappers watching the bus and the
+    // code/data logger see them.
+    pub fn advance(&mut self, dots: usize) {
+        for _ in 0..dots {
+            self.tick();
+        }
+    }
+
+    fn tick(&mut self) {
+        if self.rendering()
+            && (self.scanline < VISIBLE_SCANLINES || self.scanline == PRE_RENDER_SCANLINE)
+        {
+            self.render_fetches();
+        }
+        match (self.scanline, self.dot) {
+            (VBLANK_SCANLINE, 1) => self.status |= STATUS_VBLANK,
+            (PRE_RENDER_SCANLINE, 1) => self.status &= !STATUS_FLAGS,
+            _ => {}
+        }
+
+        self.dot += 1;
+        // Odd frames skip the last dot of the pre-render line while rendering
+        if self.scanline == PRE_RENDER_SCANLINE
+            && self.dot == DOTS_PER_SCANLINE - 1
+            && self.frame % 2 == 1
+            && self.rendering()
+        {
+            self.dot = DOTS_PER_SCANLINE;
+        }
+        if self.dot == DOTS_PER_SCANLINE {
+            self.dot = 0;
+            self.scanline += 1;
+            if self.scanline == SCANLINES_PER_FRAME {
+                self.scanline = 0;
+                self.frame += 1;
+            }
+        }
+    }
+
+    pub fn rendering(&self) -> bool {
+        self.mask & MASK_RENDERING != 0
+    }
+
+    // The PPU holds the NMI line low for as long as it is in vblank with NMIs
+    // enabled
+    pub fn nmi(&self) -> bool {
+        self.status & STATUS_VBLANK != 0 && self.ctrl & CTRL_NMI != 0
+    }
+
+    pub fn position(&self) -> PpuPosition {
+        PpuPosition {
+            scanline: self.scanline,
+            dot: self.dot,
+            frame: self.frame,
+        }
+    }
+
+    // A read of one of the eight registers at $2000-$2007 by the CPU, which
+    // can clear vblank, flip the write toggle and move the VRAM address on
+    pub fn read_register(&mut self, register: u16) -> u8 {
+        let data = match register & 0x0007 {
+            2 => {
+                let data = self.register(register);
+                self.status &= !STATUS_VBLANK;
+                self.w = false;
+                data
+            }
+            7 => {
+                let addr = self.v & 0x3fff;
+                let data = if addr >= 0x3f00 {
+                    // Palette reads skip the buffer, which is filled from the
+                    // nametable underneath instead
+                    self.read_buffer = self.memory.fetch(addr - 0x1000);
+                    self.memory.read(addr)
+                } else {
+                    std::mem::replace(&mut self.read_buffer, self.memory.fetch(addr))
+                };
+                self.increment_v();
+                data
+            }
+            _ => self.register(register),
+        };
+        self.latch = data;
+        data
+    }
+
+    // What read_register would return, without any of its side effects
+    pub fn register(&self, register: u16) -> u8 {
+        match register & 0x0007 {
+            2 => self.status | self.latch & 0x1f,
+            4 => self.oam[self.oam_addr as usize],
+            7 if self.v & 0x3fff >= 0x3f00 => self.memory.read(self.v),
+            7 => self.read_buffer,
+            _ => self.latch,
+        }
+    }
+
+    pub fn write_register(&mut self, register: u16, data: u8) {
+        self.latch = data;
+        match register & 0x0007 {
+            0 => {
+                self.ctrl = data;
+                self.t = (self.t & !0x0c00) | (data as u16 & 0x03) << 10;
+            }
+            1 => self.mask = data,
+            3 => self.oam_addr = data,
+            4 => {
+                self.oam[self.oam_addr as usize] = data;
+                self.oam_addr = self.oam_addr.wrapping_add(1);
+            }
+            5 => {
+                if self.w {
+                    self.t =
+                        (self.t & !0x73e0) | (data as u16 & 0x07) << 12 | (data as u16 & 0xf8) << 2;
+                } else {
+                    self.t = (self.t & !0x001f) | (data >> 3) as u16;
+                    self.x = data & 0x07;
+                }
+                self.w = !self.w;
+            }
+            6 => {
+                if self.w {
+                    self.t = (self.t & 0xff00) | data as u16;
+                    self.v = self.t;
+                    // The new address goes straight out on the bus
+                    self.memory.put_on_bus(self.v & 0x3fff);
+                } else {
+                    self.t = (self.t & 0x00ff) | (data as u16 & 0x3f) << 8;
+                }
+                self.w = !self.w;
+            }
+            7 => {
+                self.memory.store(self.v, data);
+                self.increment_v();
+            }
+            // $2002 is read only
+            _ => {}
+        }
+    }
+
+    fn increment_v(&mut self) {
+        let step = if self.ctrl & CTRL_INCREMENT_32 != 0 {
+            32
+        } else {
+            1
+        };
+        self.v = (self.v + step) & 0x7fff;
+    }
+
+    // The fetches of one dot of a visible or pre-render line: two tiles of
+    // background for the next line at the end, sprites for the next line in
+    // the middle, and the rest of this line's background around them. Each
+    // takes two dots, and we make it on the first.
+    fn render_fetches(&mut self) {
+        match self.dot {
+            1..=256 | 321..=336 => {
+                self.background_fetch((self.dot - 1) % 8);
+                if self.dot.is_multiple_of(8) {
+                    self.increment_coarse_x();
+                }
+                if self.dot == 256 {
+                    self.increment_y();
+                }
+            }
+            257..=320 => {
+                if self.dot == 257 {
+                    self.v = (self.v & !0x041f) | (self.t & 0x041f);
+                    self.evaluate_sprites();
+                }
+                if self.scanline == PRE_RENDER_SCANLINE && (280..=304).contains(&self.dot) {
+                    self.v = (self.v & !0x7be0) | (self.t & 0x7be0);
+                }
+                let offset = self.dot - 257;
+                self.sprite_fetch((offset / 8) as usize, offset % 8);
+            }
+            // Two nametable fetches that nothing uses
+            337 | 339 => {
+                self.memory.fetch(0x2000 | (self.v & 0x0fff));
+            }
+            _ => {}
+        }
+    }
+
+    fn background_fetch(&mut self, phase: u16) {
+        match phase {
+            0 => self.tile = self.memory.fetch(0x2000 | (self.v & 0x0fff)),
+            2 => {
+                let v = self.v;
+                self.memory
+                    .fetch(0x23c0 | (v & 0x0c00) | (v >> 4) & 0x38 | (v >> 2) & 0x07);
+            }
+            4 | 6 => {
+                let table = if self.ctrl & CTRL_BACKGROUND_TABLE != 0 {
+                    0x1000
+                } else {
+                    0
+                };
+                let plane = if phase == 6 { 8 } else { 0 };
+                let fine_y = self.v >> 12 & 0x07;
+                self.memory
+                    .fetch(table | (self.tile as u16) << 4 | plane | fine_y);
+            }
+            _ => {}
+        }
+    }
+
+    // Sprites fetch from the nametable too, though nothing uses what comes
+    // back
+    fn sprite_fetch(&mut self, slot: usize, phase: u16) {
+        match phase {
+            0 | 2 => {
+                self.memory.fetch(0x2000 | (self.v & 0x0fff));
+            }
+            4 => {
+                self.memory.fetch(self.sprite_patterns[slot]);
+            }
+            6 => {
+                self.memory.fetch(self.sprite_patterns[slot] + 8);
+            }
+            _ => {}
+        }
+    }
+
+    // Finds the first eight sprites on the next line. Empty slots fetch tile
+    // $FF, and nothing is found on the pre-render line.
+    fn evaluate_sprites(&mut self) {
+        let height = if self.ctrl & CTRL_TALL_SPRITES != 0 {
+            16
+        } else {
+            8
+        };
+        let mut found = 0;
+        if self.scanline < VISIBLE_SCANLINES {
+            for sprite in self.oam.chunks(4) {
+                let row = self.scanline.wrapping_sub(sprite[0] as u16);
+                if row < height && found < self.sprite_patterns.len() {
+                    self.sprite_patterns[found] = self.sprite_pattern(sprite[1], sprite[2], row);
+                    found += 1;
+                }
+            }
+        }
+        for slot in found..self.sprite_patterns.len() {
+            self.sprite_patterns[slot] = self.sprite_pattern(0xff, 0, 0);
+        }
+    }
+
+    // Where a row of a sprite is in the pattern tables. Tall sprites pick
+    // their table with the low bit of the tile number, and their bottom half
+    // is the next tile.
+    fn sprite_pattern(&self, tile: u8, attributes: u8, row: u16) -> u16 {
+        let (table, tile, height) = if self.ctrl & CTRL_TALL_SPRITES != 0 {
+            ((tile as u16 & 0x01) << 12, tile as u16 & 0xfe, 16)
+        } else {
+            let table = if self.ctrl & CTRL_SPRITE_TABLE != 0 {
+                0x1000
+            } else {
+                0
+            };
+            (table, tile as u16, 8)
+        };
+        // Flipped vertically
+        let row = if attributes & 0x80 != 0 {
+            height - 1 - row
+        } else {
+            row
+        };
+        table | (tile + row / 8) << 4 | (row % 8)
+    }
+
+    fn increment_coarse_x(&mut self) {
+        if self.v & 0x001f == 31 {
+            self.v = (self.v & !0x001f) ^ 0x0400;
+        } else {
+            self.v += 1;
+        }
+    }
+
+    fn increment_y(&mut self) {
+        if self.v & 0x7000 != 0x7000 {
+            self.v += 0x1000;
+            return;
+        }
+        self.v &= !0x7000;
+        let coarse_y = match (self.v & 0x03e0) >> 5 {
+            29 => {
+                self.v ^= 0x0800;
+                0
+            }
+            // Rows 30 and 31 are attributes, and wrap without switching
+            // nametable
+            31 => 0,
+            coarse_y => coarse_y + 1,
+        };
+        self.v = (self.v & !0x03e0) | coarse_y << 5;
+    }
+}
+
+impl SaveState for PpuMemory {
+    fn save(&self, writer: &mut StateWriter) {
+        self.ram.save(writer);
+        self.palette_ram.save(writer);
+    }
+
+    fn load(&mut self, reader: &mut StateReader) -> Result<(), Error> {
+        self.ram.load(reader)?;
+        self.palette_ram.load(reader)
+    }
+}
+
+impl SaveState for Ppu {
+    fn save(&self, writer: &mut StateWriter) {
+        self.memory.save(writer);
+        writer.bytes(&self.oam);
+        writer.u16(self.scanline);
+        writer.u16(self.dot);
+        writer.u64(self.frame);
+        for register in [self.ctrl, self.mask, self.status, self.oam_addr, self.x] {
+            writer.u8(register);
+        }
+        writer.u16(self.v);
+        writer.u16(self.t);
+        writer.bool(self.w);
+        writer.u8(self.read_buffer);
+        writer.u8(self.latch);
+        writer.u8(self.tile);
+        for pattern in self.sprite_patterns {
+            writer.u16(pattern);
+        }
+    }
+
+    fn load(&mut self, reader: &mut StateReader) -> Result<(), Error> {
+        self.memory.load(reader)?;
+        reader.bytes_into(&mut self.oam)?;
+        let (scanline, dot) = (reader.u16()?, reader.u16()?);
+        if scanline >= SCANLINES_PER_FRAME || dot >= DOTS_PER_SCANLINE {
+            return Err(Error::InvalidSnapshot {
+                reason: "PPU position is out of range",
+            });
+        }
+        self.scanline = scanline;
+        self.dot = dot;
+        self.frame = reader.u64()?;
+        for register in [
+            &mut self.ctrl,
+            &mut self.mask,
+            &mut self.status,
+            &mut self.oam_addr,
+            &mut self.x,
+        ] {
+            *register = reader.u8()?;
         }
+        // Both addresses are 15 bits
+        self.v = reader.u16()? & 0x7fff;
+        self.t = reader.u16()? & 0x7fff;
+        self.w = reader.bool()?;
+        self.read_buffer = reader.u8()?;
+        self.latch = reader.u8()?;
+        self.tile = reader.u8()?;
+        for pattern in &mut self.sprite_patterns {
+            *pattern = reader.u16()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nintendo::cartridge::{Mirroring, NROMCartridge, NullCartridge};
+    use crate::nintendo::RomHeader;
+
+    #[test]
+    fn test_advance() {
+        // Timing never touches the cartridge
+        let mut cartridge = NullCartridge::default();
+        let mut ppu = Ppu::new(&mut cartridge);
+        ppu.advance(21);
+        assert_eq!((ppu.scanline, ppu.dot, ppu.frame), (0, 21, 0));
+        ppu.advance(341 * 261 - 21);
+        assert_eq!((ppu.scanline, ppu.dot, ppu.frame), (261, 0, 0));
+        ppu.advance(341 + 5);
+        assert_eq!(
+            ppu.position(),
+            PpuPosition {
+                scanline: 0,
+                dot: 5,
+                frame: 1
+            }
+        );
+    }
+
+    #[test]
+    fn test_vblank() {
+        let mut cartridge = NullCartridge::default();
+        let mut ppu = Ppu::new(&mut cartridge);
+        ppu.write_register(0x2000, 0x80);
+        ppu.advance(341 * 241 + 1);
+        assert!(!ppu.nmi());
+        ppu.advance(1);
+        assert!(ppu.nmi());
+        assert_eq!(ppu.register(0x2002), 0x80);
+
+        // Reading the status ends it early, and clears the write toggle
+        ppu.write_register(0x2005, 0);
+        assert_eq!(ppu.read_register(0x2002), 0x80);
+        assert!(!ppu.nmi());
+        assert!(!ppu.w);
+        assert_eq!(ppu.read_register(0x2002), 0x00);
+
+        // The pre-render line ends it otherwise
+        ppu.advance(341 * 20 - 2);
+        ppu.status |= STATUS_VBLANK;
+        ppu.advance(2);
+        assert_eq!((ppu.scanline, ppu.dot), (261, 2));
+        assert!(!ppu.nmi());
+
+        // Odd frames are a dot short while rendering
+        ppu.write_register(0x2001, 0x08);
+        ppu.advance(339);
+        assert_eq!((ppu.scanline, ppu.dot, ppu.frame), (0, 0, 1));
+        ppu.advance(341 * 262 - 1);
+        assert_eq!((ppu.scanline, ppu.dot, ppu.frame), (0, 0, 2));
+        ppu.advance(341 * 262);
+        assert_eq!((ppu.scanline, ppu.dot, ppu.frame), (0, 0, 3));
+    }
+
+    #[test]
+    fn test_registers() {
+        let header = RomHeader::parse(b"NES\x1a\x01\x01\x01\0\0\0\0\0\0\0\0\0").unwrap();
+        let mut chr = vec![0; 0x2000];
+        chr[0x1234] = 0x56;
+        let mut cartridge = NROMCartridge::new(&header, &[0; 0x4000], &chr).unwrap();
+        let mut ppu = Ppu::new(&mut cartridge);
+
+        // Reads through $2007 come a read late
+        ppu.write_register(0x2006, 0x12);
+        ppu.write_register(0x2006, 0x34);
+        assert_eq!(ppu.v, 0x1234);
+        ppu.read_register(0x2007);
+        assert_eq!(ppu.register(0x2007), 0x56);
+        assert_eq!(ppu.read_register(0x2007), 0x56);
+        assert_eq!(ppu.v, 0x1236);
+
+        // Writes go straight in, 32 apart when going down the nametable
+        ppu.write_register(0x2000, 0x04);
+        ppu.write_register(0x2006, 0x20);
+        ppu.write_register(0x2006, 0x00);
+        ppu.write_register(0x2007, 1);
+        ppu.write_register(0x2007, 2);
+        assert_eq!(ppu.memory.read(0x2000), 1);
+        assert_eq!(ppu.memory.read(0x2020), 2);
+        assert_eq!(ppu.v, 0x2040);
+
+        // Palette reads don't wait, and fill the buffer from underneath
+        ppu.memory.write(0x2f00, 3);
+        ppu.write_register(0x2000, 0x00);
+        ppu.write_register(0x2006, 0x3f);
+        ppu.write_register(0x2006, 0x00);
+        ppu.write_register(0x2007, 0x0f);
+        ppu.write_register(0x2006, 0x3f);
+        ppu.write_register(0x2006, 0x00);
+        assert_eq!(ppu.read_register(0x2007), 0x0f);
+        assert_eq!(ppu.read_buffer, 3);
+
+        // Scrolling fills in t and fine X, sharing the toggle with $2006
+        ppu.write_register(0x2005, 0x7d);
+        ppu.write_register(0x2005, 0x5e);
+        assert_eq!((ppu.t, ppu.x), (0x6d6f, 0x05));
+        ppu.write_register(0x2003, 0xff);
+        ppu.write_register(0x2004, 0x99);
+        assert_eq!((ppu.oam[0xff], ppu.oam_addr), (0x99, 0x00));
+        // Write-only registers read back the last value written
+        assert_eq!(ppu.read_register(0x2001), 0x99);
+    }
+
+    #[test]
+    fn test_rendering_fetches() {
+        let header = RomHeader::parse(b"NES\x1a\x01\x01\x01\0\0\0\0\0\0\0\0\0").unwrap();
+        let mut cartridge = NROMCartridge::new(&header, &[0; 0x4000], &[0; 0x2000]).unwrap();
+        let mut ppu = Ppu::new(&mut cartridge);
+        ppu.memory.write(0x2000, 0x42);
+        ppu.memory.record_chr_fetches(true);
+        // Nothing is fetched with rendering off
+        ppu.advance(341);
+        assert!(ppu.memory.take_chr_fetches().is_empty());
+
+        // Background from $1000 and sprites from $0000
+        ppu.write_register(0x2000, 0x10);
+        ppu.write_register(0x2001, 0x18);
+        ppu.advance(341 * 260);
+        ppu.memory.take_chr_fetches();
+
+        // The pre-render line finds no sprites, which fetch tile $FF, and
+        // ends with the first two tiles of the frame
+        ppu.advance(341);
+        let fetches = ppu.memory.take_chr_fetches();
+        assert_eq!(fetches.len(), 32 * 2 + 8 * 2 + 2 * 2);
+        assert!(fetches[64..80]
+            .chunks(2)
+            .all(|pattern| pattern == [0x0ff0, 0x0ff8]));
+        assert_eq!(fetches[80..], [0x1420, 0x1428, 0x1000, 0x1008]);
+
+        // Every sprite is on the top line, and the next line is a row further
+        // down
+        ppu.advance(341);
+        let fetches = ppu.memory.take_chr_fetches();
+        assert!(fetches[64..80]
+            .chunks(2)
+            .all(|pattern| pattern == [0x0000, 0x0008]));
+        assert_eq!(fetches[80..], [0x1421, 0x1429, 0x1001, 0x1009]);
+    }
+
+    #[test]
+    fn test_mirroring() {
+        // One bank of PRG ROM and CHR ROM, with vertical mirroring
+        let header = RomHeader::parse(b"NES\x1a\x01\x01\x01\0\0\0\0\0\0\0\0\0").unwrap();
+        let mut cartridge = NROMCartridge::new(&header, &[0; 0x4000], &[0; 0x2000]).unwrap();
+        let mut memory = PpuMemory::new(&mut cartridge);
+        memory.write(0x2005, 1);
+        memory.write(0x2405, 2);
+        assert_eq!(memory.read(0x2805), 1);
+        assert_eq!(memory.read(0x3c05), 2);
+        // Addresses past $3FFF wrap around
+        memory.write(0x6805, 3);
+        assert_eq!(memory.read(0x2805), 3);
+        memory.write(0xff00, 4);
+        assert_eq!(memory.read(0x3f00), 4);
+
+        cartridge.mirroring = Mirroring::Horizontal;
+        let mut memory = PpuMemory::new(&mut cartridge);
+        memory.write(0x2005, 1);
+        memory.write(0x2805, 2);
+        assert_eq!(memory.read(0x2405), 1);
+        assert_eq!(memory.read(0x2c05), 2);
     }
 }
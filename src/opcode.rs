@@ -0,0 +1,188 @@
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AddressingMode {
+    Implied,
+    Accumulator,
+    Immediate,
+    ZeroPage,
+    ZeroPageX,
+    ZeroPageY,
+    Relative,
+    Absolute,
+    AbsoluteX,
+    AbsoluteY,
+    Indirect,
+    IndexedIndirect,
+    IndirectIndexed,
+    // 65C02 ONLY
+    ZeroPageIndirect,
+    ZeroPageRelative,
+    AbsoluteIndexedIndirect,
+}
+
+// The documented facts about one opcode. Cycles don't include the extra
+// cycles for crossing a page, taking a branch, or 65C02 decimal arithmetic.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OpcodeInfo {
+    pub opcode: u8,
+    pub mnemonic: &'static str,
+    pub mode: AddressingMode,
+    pub length: u16,
+    pub cycles: usize,
+    pub page_cross_penalty: bool,
+    pub official: bool,
+    pub access: Access,
+}
+
+impl OpcodeInfo {
+    // Placeholder for building tables
+    pub(crate) const UNDEFINED: OpcodeInfo = OpcodeInfo {
+        opcode: 0,
+        mnemonic: "",
+        mode: AddressingMode::Implied,
+        length: 1,
+        cycles: 0,
+        page_cross_penalty: false,
+        official: false,
+        access: Access::None,
+    };
+}
+
+// The NMOS 6502 and 2A03
+pub const OPCODES: [OpcodeInfo; 256] =
+    crate::instructions::opcodes_6502!(instructions::opcode_table! {});
+
+// The WDC 65C02
+pub const CMOS_OPCODES: [OpcodeInfo; 256] =
+    crate::instructions::opcodes_65c02!(instructions::opcode_table! {});
+
+// How an instruction uses the memory at its effective address
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Access {
+    None,
+    Read,
+    Write,
+    ReadModifyWrite,
+}
+
+// What the decode tables know about an operation, independent of the
+// addressing mode it is used with
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Operation {
+    pub mnemonic: &'static str,
+    pub access: Access,
+    // Operations without an operand that work on A rather than nothing
+    pub accumulator: bool,
+}
+
+impl Operation {
+    pub const fn none(mnemonic: &'static str) -> Self {
+        Self {
+            mnemonic,
+            access: Access::None,
+            accumulator: false,
+        }
+    }
+
+    pub const fn accumulator(mnemonic: &'static str) -> Self {
+        Self {
+            mnemonic,
+            access: Access::None,
+            accumulator: true,
+        }
+    }
+
+    pub const fn read(mnemonic: &'static str) -> Self {
+        Self {
+            mnemonic,
+            access: Access::Read,
+            accumulator: false,
+        }
+    }
+
+    pub const fn write(mnemonic: &'static str) -> Self {
+        Self {
+            mnemonic,
+            access: Access::Write,
+            accumulator: false,
+        }
+    }
+
+    pub const fn modify(mnemonic: &'static str) -> Self {
+        Self {
+            mnemonic,
+            access: Access::ReadModifyWrite,
+            accumulator: false,
+        }
+    }
+
+    pub const fn mode(&self) -> AddressingMode {
+        if self.accumulator {
+            AddressingMode::Accumulator
+        } else {
+            AddressingMode::Implied
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::{Memory, RandomAccessMemory};
+    use crate::processor::Processor;
+    use crate::variant::Variant;
+
+    #[test]
+    fn test_opcode_info() {
+        let lda = OPCODES[0xb1];
+        assert_eq!(lda.mnemonic, "LDA");
+        assert_eq!(lda.mode, AddressingMode::IndirectIndexed);
+        assert_eq!(lda.length, 2);
+        assert_eq!(lda.cycles, 5);
+        assert!(lda.page_cross_penalty);
+        assert!(lda.official);
+        assert_eq!(lda.access, Access::Read);
+
+        let sta = OPCODES[0x9d];
+        assert!(!sta.page_cross_penalty);
+        assert_eq!(sta.access, Access::Write);
+
+        // The undocumented copy of SBC #imm
+        assert_eq!(OPCODES[0xeb].mnemonic, "SBC");
+        assert!(!OPCODES[0xeb].official);
+        assert_eq!(OPCODES[0xeb].mode, AddressingMode::Immediate);
+
+        assert_eq!(OPCODES[0x0a].mode, AddressingMode::Accumulator);
+        assert_eq!(OPCODES[0xe6].access, Access::ReadModifyWrite);
+        assert_eq!(CMOS_OPCODES[0x1a].mnemonic, "INC");
+        assert_eq!(CMOS_OPCODES[0x0f].mnemonic, "BBR0");
+        assert_eq!(CMOS_OPCODES[0x0f].mode, AddressingMode::ZeroPageRelative);
+        assert!(!CMOS_OPCODES[0x03].official);
+
+        for (opcode, info) in OPCODES.iter().enumerate() {
+            assert_eq!(info.opcode as usize, opcode);
+        }
+        assert_eq!(OPCODES.iter().filter(|info| info.official).count(), 151);
+        assert_eq!(
+            CMOS_OPCODES.iter().filter(|info| info.official).count(),
+            212
+        );
+    }
+
+    #[test]
+    fn test_table_matches_execution() {
+        for variant in [Variant::Nmos6502, Variant::Cmos65C02] {
+            for info in variant.opcodes() {
+                let mut cpu = Processor::with_variant(RandomAccessMemory::new(0x10000), variant);
+                cpu.memory.write(0x0200, info.opcode);
+                cpu.core.pc = 0x0200;
+
+                let step = cpu.step().unwrap();
+                let extra = step.branch_taken as usize + step.page_crossed as usize;
+                assert_eq!(step.mnemonic, info.mnemonic, "{:02x}", info.opcode);
+                assert_eq!(step.mode, info.mode, "{:02x}", info.opcode);
+                assert_eq!(step.length, info.length, "{:02x}", info.opcode);
+                assert_eq!(step.cycles - extra, info.cycles, "{:02x}", info.opcode);
+            }
+        }
+    }
+}
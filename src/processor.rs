@@ -1,8 +1,52 @@
 use std::fmt::Display;
 
-use crate::decode_6502;
+use crate::bus::{AccessKind, BusAccess};
+use crate::error::Error;
 use crate::flags::Flags;
+use crate::history::{Entry, History};
+use crate::instructions::Decoded;
 use crate::memory::Memory;
+use crate::opcode::AddressingMode;
+use crate::profile::{Flow, Profile};
+use crate::snapshot::{self, SaveState, SnapshotKind, StateReader, StateWriter};
+use crate::step::{Step, StepKind};
+use crate::variant::Variant;
+use crate::{decode_6502, decode_65c02};
+
+pub const NMI_VECTOR: u16 = 0xfffa;
+pub const RESET_VECTOR: u16 = 0xfffc;
+pub const IRQ_VECTOR: u16 = 0xfffe;
+
+// The Rockwell RMB/SMB/BBR/BBS instructions, one set per bit
+macro_rules! bit_instructions {
+    ($($bit:literal => ($rmb:ident, $smb:ident, $bbr:ident, $bbs:ident)),+) => {
+        $(
+            pub(crate) fn $rmb(&mut self, addr: u16) {
+                let operand = self.read(addr);
+                self.dummy_modify(addr, operand);
+                self.write(addr, operand & !(1 << $bit));
+            }
+
+            pub(crate) fn $smb(&mut self, addr: u16) {
+                let operand = self.read(addr);
+                self.dummy_modify(addr, operand);
+                self.write(addr, operand | (1 << $bit));
+            }
+
+            pub(crate) fn $bbr(&mut self, addr: u16) {
+                let operand = self.read(addr);
+                self.dummy_read(addr);
+                self.branch_if(operand & (1 << $bit) == 0, self.core.pc.wrapping_add(2));
+            }
+
+            pub(crate) fn $bbs(&mut self, addr: u16) {
+                let operand = self.read(addr);
+                self.dummy_read(addr);
+                self.branch_if(operand & (1 << $bit) != 0, self.core.pc.wrapping_add(2));
+            }
+        )+
+    };
+}
 
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Core {
@@ -31,6 +75,39 @@ pub struct Processor<T: Memory> {
     // TODO: Is there a better way to do this?
     pub jumped: bool,
     pub cycles: usize,
+    pub variant: Variant,
+    // The chip-dependent constant ORed into A by the unstable XAA and LXA
+    pub magic_constant: u8,
+    nmi_line: bool,
+    nmi_pending: bool,
+    irq_line: bool,
+    halted: bool,
+    waiting: bool,
+    // Every bus access made by the current instruction
+    bus_log: Vec<BusAccess>,
+    bus_index: usize,
+    // While tick() replays an instruction, the first accesses come from
+    // bus_log and anything past the current cycle is discarded
+    bus_replayed: usize,
+    bus_live_until: usize,
+    tick_action: Option<Action>,
+    // Filled in while an instruction runs, for step()
+    decoded: Option<Decoded>,
+    effective_addr: Option<u16>,
+    page_crossed: bool,
+    branch_taken: bool,
+    // Undo information for step_back(), when history is enabled
+    history: Option<History>,
+    profile: Option<Profile>,
+}
+
+// What the processor does with its next few cycles
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Action {
+    Halted,
+    Waiting,
+    Interrupt(u16),
+    Instruction,
 }
 
 impl Core {
@@ -52,36 +129,228 @@ impl Default for Core {
     }
 }
 
+// Just the registers. TraceLogger adds the cycle count, and the PPU position
+// when there is a PPU to ask.
 impl<T: Memory> Display for Processor<T> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let ppu_cycles = self.cycles * 3;
-        write!(
-            f,
-            "{} PPU:{:3},{:3} CYC:{}",
-            self.core,
-            ppu_cycles / 341,
-            ppu_cycles % 341,
-            self.cycles
-        )
+        write!(f, "{}", self.core)
     }
 }
 
 impl<T: Memory> Processor<T> {
     pub fn with_memory(memory: T) -> Self {
+        Self::with_variant(memory, Variant::default())
+    }
+
+    pub fn with_variant(memory: T, variant: Variant) -> Self {
         Self {
             core: Core::new(),
             memory,
             jumped: false,
             cycles: 0,
+            variant,
+            magic_constant: 0xee,
+            nmi_line: false,
+            nmi_pending: false,
+            irq_line: false,
+            halted: false,
+            waiting: false,
+            bus_log: Vec::with_capacity(8),
+            bus_index: 0,
+            bus_replayed: 0,
+            bus_live_until: usize::MAX,
+            tick_action: None,
+            decoded: None,
+            effective_addr: None,
+            page_crossed: false,
+            branch_taken: false,
+            history: None,
+            profile: None,
+        }
+    }
+
+    // Loads PC from the reset vector. Like the real chip, the stack pointer
+    // is decremented by three without anything being written.
+    pub fn reset(&mut self) {
+        self.core.sp = self.core.sp.wrapping_sub(3);
+        self.core.f.i = true;
+        self.core.pc = self.memory.read_word(RESET_VECTOR);
+        self.nmi_pending = false;
+        self.jumped = false;
+        self.halted = false;
+        self.waiting = false;
+        self.tick_action = None;
+        self.cycles += 7;
+        self.clear_history();
+        if let Some(profile) = &mut self.profile {
+            profile.restart();
+        }
+    }
+
+    // NMI is edge-triggered: only the transition to asserted is latched.
+    pub fn set_nmi(&mut self, asserted: bool) {
+        if asserted && !self.nmi_line {
+            self.nmi_pending = true;
+        }
+        self.nmi_line = asserted;
+    }
+
+    // IRQ is level-triggered and is serviced for as long as it is asserted
+    // and the I flag is clear.
+    pub fn set_irq(&mut self, asserted: bool) {
+        self.irq_line = asserted;
+    }
+
+    pub fn nmi_pending(&self) -> bool {
+        self.nmi_pending
+    }
+
+    pub fn irq_asserted(&self) -> bool {
+        self.irq_line
+    }
+
+    // Set by STP or a JAM opcode; only a reset will start the processor again
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    // Set by WAI until an interrupt line is asserted
+    pub fn is_waiting(&self) -> bool {
+        self.waiting
+    }
+
+    // The bus accesses made by the last instruction, or by the instruction
+    // that tick() is part way through
+    pub fn bus_accesses(&self) -> &[BusAccess] {
+        &self.bus_log
+    }
+
+    // True when tick() has started an instruction that it hasn't finished
+    pub fn mid_instruction(&self) -> bool {
+        self.tick_action.is_some()
+    }
+
+    fn bus_read(&mut self, addr: u16, kind: AccessKind) -> u8 {
+        let index = self.bus_index;
+        self.bus_index += 1;
+
+        if index < self.bus_replayed {
+            self.bus_log[index].value
+        } else if index < self.bus_live_until {
+            let value = self.memory.read_bus(addr);
+            self.bus_log.push(BusAccess { addr, value, kind });
+            value
+        } else {
+            0
+        }
+    }
+
+    fn bus_write(&mut self, addr: u16, value: u8, kind: AccessKind) {
+        let index = self.bus_index;
+        self.bus_index += 1;
+
+        if index >= self.bus_replayed && index < self.bus_live_until {
+            if let Some(history) = &mut self.history {
+                history.record_write(addr, self.memory.peek(addr));
+            }
+            // The log holds every earlier access of this instruction, so its
+            // last entry is the cycle before this one
+            let consecutive = self.bus_log.last().is_some_and(|last| last.kind.is_write());
+            match consecutive {
+                true => self.memory.write_consecutive(addr, value),
+                false => self.memory.write(addr, value),
+            }
+            self.bus_log.push(BusAccess { addr, value, kind });
+        }
+    }
+
+    fn read(&mut self, addr: u16) -> u8 {
+        self.bus_read(addr, AccessKind::Read)
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        self.bus_write(addr, value, AccessKind::Write)
+    }
+
+    fn fetch(&mut self, addr: u16) -> u8 {
+        self.bus_read(addr, AccessKind::OperandFetch)
+    }
+
+    fn dummy_read(&mut self, addr: u16) {
+        self.bus_read(addr, AccessKind::DummyRead);
+    }
+
+    // The cycle between the read and the write of a read-modify-write
+    // instruction: NMOS parts write the old value back, while the 65C02 reads
+    // the address again
+    fn dummy_modify(&mut self, addr: u16, value: u8) {
+        if self.variant.is_cmos() {
+            self.dummy_read(addr);
+        } else {
+            self.bus_write(addr, value, AccessKind::DummyWrite);
+        }
+    }
+
+    fn read_word(&mut self, addr: u16) -> u16 {
+        u16::from_le_bytes([self.read(addr), self.read(addr.wrapping_add(1))])
+    }
+
+    fn interrupt(&mut self, vector: u16) {
+        // The opcode fetch and the following read are thrown away
+        self.dummy_read(self.core.pc);
+        self.dummy_read(self.core.pc);
+        self.push_word(self.core.pc);
+        // Hardware interrupts push the flags with the B bit clear
+        self.push(self.core.f.get_byte());
+        self.core.f.i = true;
+        if self.variant.is_cmos() {
+            self.core.f.d = false;
+        }
+        self.core.pc = self.read_word(vector);
+        self.cycles += 7;
+    }
+
+    fn next_action(&mut self) -> Action {
+        if self.halted {
+            return Action::Halted;
+        }
+
+        if self.waiting {
+            if !self.nmi_pending && !self.irq_line {
+                return Action::Waiting;
+            }
+            // An IRQ wakes the processor even when it is masked
+            self.waiting = false;
+        }
+
+        if self.nmi_pending {
+            self.nmi_pending = false;
+            Action::Interrupt(NMI_VECTOR)
+        } else if self.irq_line && !self.core.f.i {
+            Action::Interrupt(IRQ_VECTOR)
+        } else {
+            Action::Instruction
         }
     }
 
-    fn branch(&mut self, addr: u16) {
-        let offset = self.memory.read_signed(addr);
-        let base = self.core.pc.wrapping_add(2);
+    // addr points at the offset byte, which is always the last byte of the
+    // instruction
+    fn branch_if(&mut self, condition: bool, addr: u16) {
+        let offset = self.fetch(addr) as i8;
+        if !condition {
+            return;
+        }
+
+        let base = addr.wrapping_add(1);
         let new_address = base.wrapping_add(offset as u16);
 
+        // The next opcode is read while the new PC is worked out, then the
+        // wrong page is read while the high byte is fixed up
+        self.dummy_read(base);
+        self.branch_taken = true;
         if base & 0xff00 != new_address & 0xff00 {
+            self.dummy_read((base & 0xff00) | (new_address & 0x00ff));
+            self.page_crossed = true;
             self.cycles += 1;
         }
 
@@ -92,11 +361,11 @@ impl<T: Memory> Processor<T> {
 
     fn pull(&mut self) -> u8 {
         self.core.sp = self.core.sp.wrapping_add(1);
-        self.memory.read(self.stack_addr())
+        self.read(self.stack_addr())
     }
 
     fn push(&mut self, value: u8) {
-        self.memory.write(self.stack_addr(), value);
+        self.write(self.stack_addr(), value);
         self.core.sp = self.core.sp.wrapping_sub(1);
     }
 
@@ -109,118 +378,338 @@ impl<T: Memory> Processor<T> {
         0x100 | (self.core.sp as u16)
     }
 
+    fn decimal_mode(&self) -> bool {
+        self.core.f.d && self.variant.has_decimal_mode()
+    }
+
+    fn add_with_carry(&mut self, operand: u8) {
+        let old_a = self.core.a;
+        let carry = self.core.f.c as u8;
+
+        let sum = (old_a as u16) + (operand as u16) + (carry as u16);
+        self.core.a = (sum & 0xff) as u8;
+        self.core.f.c = sum > 0xff;
+
+        self.core.f.set_z(self.core.a);
+        self.core.f.set_n(self.core.a);
+        self.core.f.v = (old_a ^ operand) & 0x80 == 0 && (old_a ^ self.core.a) & 0x80 != 0;
+
+        if self.decimal_mode() {
+            self.decimal_add(old_a, operand, carry);
+        }
+    }
+
+    // Decimal addition, following Bruce Clark's "Decimal Mode" tutorial.
+    // On NMOS parts Z keeps its binary value, while N and V come from the
+    // intermediate result before the high nybble is adjusted. The 65C02 sets
+    // N and Z from the final result.
+    fn decimal_add(&mut self, old_a: u8, operand: u8, carry: u8) {
+        let mut low = (old_a & 0x0f) as i16 + (operand & 0x0f) as i16 + carry as i16;
+        if low >= 0x0a {
+            low = ((low + 0x06) & 0x0f) + 0x10;
+        }
+
+        let signed = (old_a & 0xf0) as i8 as i16 + (operand & 0xf0) as i8 as i16 + low;
+        self.core.f.n = signed & 0x80 != 0;
+        self.core.f.v = !(-128..=127).contains(&signed);
+
+        let mut sum = (old_a & 0xf0) as i16 + (operand & 0xf0) as i16 + low;
+        if sum >= 0xa0 {
+            sum += 0x60;
+        }
+        self.core.a = (sum & 0xff) as u8;
+        self.core.f.c = sum >= 0x100;
+
+        if self.variant.is_cmos() {
+            self.core.f.set_z(self.core.a);
+            self.core.f.set_n(self.core.a);
+        }
+    }
+
+    // SHA, SHX, SHY and TAS store the value ANDed with the high byte of the
+    // base address plus one. If indexing crossed a page, the stored value
+    // also replaces the high byte of the address.
+    fn unstable_store(&mut self, addr: u16, index: u8, value: u8) {
+        let base = addr.wrapping_sub(index as u16);
+        let value = value & ((base >> 8) as u8).wrapping_add(1);
+        let addr = if base & 0xff00 != addr & 0xff00 {
+            ((value as u16) << 8) | (addr & 0x00ff)
+        } else {
+            addr
+        };
+        self.write(addr, value);
+    }
+
+    // The 65C02 takes an extra cycle to correct a decimal result
+    fn decimal_cycle(&mut self, addr: u16) {
+        if self.variant.is_cmos() && self.decimal_mode() {
+            self.dummy_read(addr);
+            self.cycles += 1;
+        }
+    }
+
+    fn subtract_with_borrow(&mut self, operand: u8) {
+        let old_a = self.core.a;
+        let carry = (!self.core.f.c) as u8;
+
+        let diff = (old_a as u16)
+            .wrapping_sub(operand as u16)
+            .wrapping_sub(carry as u16);
+        self.core.a = (diff & 0x0ff) as u8;
+        self.core.f.c = diff <= 0x0ff;
+        self.core.f.set_z(self.core.a);
+        self.core.f.set_n(self.core.a);
+        self.core.f.v = (old_a ^ operand) & 0x80 != 0 && (old_a ^ self.core.a) & 0x80 != 0;
+
+        if self.decimal_mode() {
+            self.decimal_subtract(old_a, operand, carry);
+        }
+    }
+
+    // NMOS decimal subtraction only corrects the accumulator; every flag
+    // keeps its binary value. The 65C02 corrects the result differently and
+    // sets N and Z from it.
+    fn decimal_subtract(&mut self, old_a: u8, operand: u8, borrow: u8) {
+        if self.variant.is_cmos() {
+            let low = (old_a & 0x0f) as i16 - (operand & 0x0f) as i16 - borrow as i16;
+            let mut diff = old_a as i16 - operand as i16 - borrow as i16;
+            if diff < 0 {
+                diff -= 0x60;
+            }
+            if low < 0 {
+                diff -= 0x06;
+            }
+            self.core.a = (diff & 0xff) as u8;
+            self.core.f.set_z(self.core.a);
+            self.core.f.set_n(self.core.a);
+            return;
+        }
+
+        let mut low = (old_a & 0x0f) as i16 - (operand & 0x0f) as i16 - borrow as i16;
+        if low < 0 {
+            low = ((low - 0x06) & 0x0f) - 0x10;
+        }
+
+        let mut diff = (old_a & 0xf0) as i16 - (operand & 0xf0) as i16 + low;
+        if diff < 0 {
+            diff -= 0x60;
+        }
+        self.core.a = (diff & 0xff) as u8;
+    }
+
     // ADDRESSING MODES:
+    // Instructions without an operand still read the byte after the opcode,
+    // except for the single cycle NOPs on the 65C02
+    pub(crate) fn implied(&mut self, cycles: usize) {
+        if cycles > 1 {
+            self.dummy_read(self.core.pc.wrapping_add(1));
+        }
+    }
+
+    pub(crate) fn operand_address(&mut self, addr: &u16) {
+        self.effective_addr = Some(*addr);
+    }
+
     pub(crate) fn immediate(&self) -> u16 {
-        self.core.pc + 1
+        self.core.pc.wrapping_add(1)
+    }
+
+    // Branches are given the address of their offset byte
+    pub(crate) fn relative(&self) -> u16 {
+        self.immediate()
     }
 
-    pub(crate) fn immediate_operand(&self) -> u8 {
-        self.memory.read(self.immediate())
+    pub(crate) fn immediate_operand(&mut self) -> u8 {
+        self.fetch(self.immediate())
     }
 
-    pub(crate) fn zero_page(&self) -> u16 {
+    pub(crate) fn zero_page(&mut self) -> u16 {
         self.immediate_operand() as u16
     }
 
-    pub(crate) fn zero_page_x(&self) -> u16 {
-        self.immediate_operand().wrapping_add(self.core.x) as u16
+    pub(crate) fn zero_page_x(&mut self) -> u16 {
+        let base = self.immediate_operand();
+        self.dummy_read(base as u16);
+        base.wrapping_add(self.core.x) as u16
     }
 
-    pub(crate) fn zero_page_y(&self) -> u16 {
-        self.immediate_operand().wrapping_add(self.core.y) as u16
+    pub(crate) fn zero_page_y(&mut self) -> u16 {
+        let base = self.immediate_operand();
+        self.dummy_read(base as u16);
+        base.wrapping_add(self.core.y) as u16
     }
 
-    pub(crate) fn absolute(&self) -> u16 {
-        self.memory.read_word(self.immediate())
+    pub(crate) fn absolute(&mut self) -> u16 {
+        let immediate = self.immediate();
+        u16::from_le_bytes([self.fetch(immediate), self.fetch(immediate.wrapping_add(1))])
     }
 
-    pub(crate) fn absolute_x(&mut self) -> u16 {
-        let base = self.absolute();
-        let addr = base.wrapping_add(self.core.x as u16);
+    // Indexing reads from the address before the carry into the high byte is
+    // fixed up. Reads only pay for that when the page was crossed, writes
+    // always do.
+    fn indexed(&mut self, base: u16, index: u8, always_fix_up: bool) -> u16 {
+        let addr = base.wrapping_add(index as u16);
+        let crossed = base & 0xff00 != addr & 0xff00;
+        self.page_crossed = crossed;
 
-        if base & 0xff00 != addr & 0xff00 {
+        if crossed || always_fix_up {
+            let unfixed = (base & 0xff00) | (addr & 0x00ff);
+            if self.variant.is_cmos() {
+                // The 65C02 reads the last instruction byte again instead
+                self.dummy_read(self.core.pc.wrapping_add(2));
+            } else {
+                self.dummy_read(unfixed);
+            }
+        }
+        if crossed && !always_fix_up {
             self.cycles += 1
         }
 
         addr
     }
 
-    pub(crate) fn absolute_x_for_store(&self) -> u16 {
-        self.absolute().wrapping_add(self.core.x as u16)
+    pub(crate) fn absolute_x(&mut self) -> u16 {
+        let base = self.absolute();
+        self.indexed(base, self.core.x, false)
     }
 
-    pub(crate) fn absolute_y(&mut self) -> u16 {
+    pub(crate) fn absolute_x_for_store(&mut self) -> u16 {
         let base = self.absolute();
-        let addr = base.wrapping_add(self.core.y as u16);
-
-        if base & 0xff00 != addr & 0xff00 {
-            self.cycles += 1
-        }
+        self.indexed(base, self.core.x, true)
+    }
 
-        addr
+    pub(crate) fn absolute_y(&mut self) -> u16 {
+        let base = self.absolute();
+        self.indexed(base, self.core.y, false)
     }
 
-    pub(crate) fn absolute_y_for_store(&self) -> u16 {
-        self.absolute().wrapping_add(self.core.y as u16)
+    pub(crate) fn absolute_y_for_store(&mut self) -> u16 {
+        let base = self.absolute();
+        self.indexed(base, self.core.y, true)
     }
 
-    pub(crate) fn indirect(&self) -> u16 {
-        self.wrapping_read(self.absolute())
+    pub(crate) fn indirect(&mut self) -> u16 {
+        let indirect_addr = self.absolute();
+        self.wrapping_read(indirect_addr)
     }
 
-    fn wrapping_read(&self, indirect_addr: u16) -> u16 {
+    fn wrapping_read(&mut self, indirect_addr: u16) -> u16 {
         // If the base address is 0x??ff, we don't read the word correctly
         if indirect_addr & 0x00ff == 0x00ff {
-            u16::from_le_bytes([
-                self.memory.read(indirect_addr),
-                self.memory.read(indirect_addr & 0xff00),
-            ])
+            u16::from_le_bytes([self.read(indirect_addr), self.read(indirect_addr & 0xff00)])
         } else {
-            self.memory.read_word(indirect_addr)
+            self.read_word(indirect_addr)
         }
     }
 
-    pub(crate) fn indexed_indirect(&self) -> u16 {
-        self.wrapping_read(self.zero_page_x())
+    pub(crate) fn indexed_indirect(&mut self) -> u16 {
+        let indirect_addr = self.zero_page_x();
+        self.wrapping_read(indirect_addr)
     }
 
     pub(crate) fn indirect_indexed(&mut self) -> u16 {
-        let base = self.wrapping_read(self.zero_page());
-        let addr = base.wrapping_add(self.core.y as u16);
+        let indirect_addr = self.zero_page();
+        let base = self.wrapping_read(indirect_addr);
+        self.indexed(base, self.core.y, false)
+    }
 
-        if base & 0xff00 != addr & 0xff00 {
-            self.cycles += 1
-        }
+    pub(crate) fn indirect_indexed_for_store(&mut self) -> u16 {
+        let indirect_addr = self.zero_page();
+        let base = self.wrapping_read(indirect_addr);
+        self.indexed(base, self.core.y, true)
+    }
 
-        addr
+    // 65C02 ONLY
+    pub(crate) fn zero_page_indirect(&mut self) -> u16 {
+        let indirect_addr = self.zero_page();
+        self.wrapping_read(indirect_addr)
     }
 
-    pub(crate) fn indirect_indexed_for_store(&mut self) -> u16 {
-        self.wrapping_read(self.zero_page())
-            .wrapping_add(self.core.y as u16)
+    // 65C02 ONLY: the zero page operand of BBR/BBS, followed by the offset
+    pub(crate) fn zero_page_relative(&mut self) -> u16 {
+        self.zero_page()
+    }
+
+    // 65C02 ONLY: JMP (abs) without the page wrapping bug
+    pub(crate) fn absolute_indirect(&mut self) -> u16 {
+        let indirect_addr = self.absolute();
+        self.dummy_read(self.core.pc.wrapping_add(2));
+        self.read_word(indirect_addr)
+    }
+
+    // 65C02 ONLY
+    pub(crate) fn absolute_indexed_indirect(&mut self) -> u16 {
+        let indirect_addr = self.absolute().wrapping_add(self.core.x as u16);
+        self.dummy_read(self.core.pc.wrapping_add(2));
+        self.read_word(indirect_addr)
+    }
+
+    // JSR fetches the high byte of its operand after pushing the return
+    // address, so the push happens as part of the addressing
+    pub(crate) fn absolute_for_jsr(&mut self) -> u16 {
+        let immediate = self.immediate();
+        let low = self.fetch(immediate);
+        self.dummy_read(self.stack_addr());
+        self.push_word(immediate.wrapping_add(1));
+        let high = self.fetch(immediate.wrapping_add(1));
+        u16::from_le_bytes([low, high])
     }
 
     // OPCODES
     pub(crate) fn adc(&mut self, addr: u16) {
-        let old_a = self.core.a;
-        let carry = self.core.f.c as u8;
-        let operand = self.memory.read(addr);
+        let operand = self.read(addr);
+        self.add_with_carry(operand);
+        self.decimal_cycle(addr);
+    }
 
-        let sum = (old_a as u16) + (operand as u16) + (carry as u16);
-        self.core.a = (sum & 0xff) as u8;
-        self.core.f.c = sum > 0xff;
+    // UNDOCUMENTED OPCODE
+    pub(crate) fn alr(&mut self, addr: u16) {
+        self.core.a &= self.read(addr);
+        self.lsra();
+    }
 
+    // UNDOCUMENTED OPCODE
+    pub(crate) fn anc(&mut self, addr: u16) {
+        self.core.a &= self.read(addr);
         self.core.f.set_z(self.core.a);
         self.core.f.set_n(self.core.a);
-        self.core.f.v = (old_a ^ operand) & 0x80 == 0 && (old_a ^ self.core.a) & 0x80 != 0;
+        self.core.f.c = self.core.f.n;
     }
 
     pub(crate) fn and(&mut self, addr: u16) {
-        self.core.a &= self.memory.read(addr);
+        self.core.a &= self.read(addr);
         self.core.f.set_z(self.core.a);
         self.core.f.set_n(self.core.a);
     }
 
+    // UNDOCUMENTED OPCODE
+    pub(crate) fn arr(&mut self, addr: u16) {
+        let anded = self.core.a & self.read(addr);
+        let carry = self.core.f.c as u8;
+        let mut result = (anded >> 1) | (carry << 7);
+
+        self.core.f.set_z(result);
+        self.core.f.set_n(result);
+
+        if self.decimal_mode() {
+            // Each nybble is fixed up like a decimal addition, using the value
+            // before the rotate
+            self.core.f.v = (anded ^ result) & 0x40 != 0;
+            if (anded & 0x0f) + (anded & 0x01) > 0x05 {
+                result = (result & 0xf0) | (result.wrapping_add(0x06) & 0x0f);
+            }
+            self.core.f.c = (anded as u16 & 0xf0) + (anded as u16 & 0x10) > 0x50;
+            if self.core.f.c {
+                result = result.wrapping_add(0x60);
+            }
+        } else {
+            self.core.f.c = result & 0x40 != 0;
+            self.core.f.v = ((result >> 6) ^ (result >> 5)) & 0x01 != 0;
+        }
+
+        self.core.a = result;
+    }
+
     pub(crate) fn asla(&mut self) {
         self.core.f.c = self.core.a & 0x80 != 0;
         self.core.a <<= 1;
@@ -229,34 +718,39 @@ impl<T: Memory> Processor<T> {
     }
 
     pub(crate) fn asl(&mut self, addr: u16) {
-        let mut operand = self.memory.read(addr);
+        let mut operand = self.read(addr);
+        self.dummy_modify(addr, operand);
         self.core.f.c = operand & 0x80 != 0;
         operand <<= 1;
-        self.memory.write(addr, operand);
+        self.write(addr, operand);
         self.core.f.set_z(operand);
         self.core.f.set_n(operand);
     }
 
+    // UNDOCUMENTED OPCODE
+    pub(crate) fn axs(&mut self, addr: u16) {
+        let operand = self.read(addr);
+        let anded = self.core.a & self.core.x;
+        self.core.x = anded.wrapping_sub(operand);
+        self.core.f.set_z(self.core.x);
+        self.core.f.set_n(self.core.x);
+        self.core.f.c = anded >= operand;
+    }
+
     pub(crate) fn bcc(&mut self, addr: u16) {
-        if !self.core.f.c {
-            self.branch(addr);
-        }
+        self.branch_if(!self.core.f.c, addr);
     }
 
     pub(crate) fn bcs(&mut self, addr: u16) {
-        if self.core.f.c {
-            self.branch(addr);
-        }
+        self.branch_if(self.core.f.c, addr);
     }
 
     pub(crate) fn beq(&mut self, addr: u16) {
-        if self.core.f.z {
-            self.branch(addr);
-        }
+        self.branch_if(self.core.f.z, addr);
     }
 
     pub(crate) fn bit(&mut self, addr: u16) {
-        let operand = self.memory.read(addr);
+        let operand = self.read(addr);
         let anded = self.core.a & operand;
 
         self.core.f.z = anded == 0;
@@ -264,42 +758,46 @@ impl<T: Memory> Processor<T> {
         self.core.f.n = operand & 0x80 != 0;
     }
 
+    // 65C02 OPCODE: only Z is affected in immediate mode
+    pub(crate) fn bit_imm(&mut self, addr: u16) {
+        self.core.f.z = self.core.a & self.read(addr) == 0;
+    }
+
     pub(crate) fn bmi(&mut self, addr: u16) {
-        if self.core.f.n {
-            self.branch(addr);
-        }
+        self.branch_if(self.core.f.n, addr);
     }
 
     pub(crate) fn bne(&mut self, addr: u16) {
-        if !self.core.f.z {
-            self.branch(addr);
-        }
+        self.branch_if(!self.core.f.z, addr);
     }
 
     pub(crate) fn bpl(&mut self, addr: u16) {
-        if !self.core.f.n {
-            self.branch(addr);
-        }
+        self.branch_if(!self.core.f.n, addr);
+    }
+
+    // 65C02 OPCODE
+    pub(crate) fn bra(&mut self, addr: u16) {
+        self.branch_if(true, addr);
     }
 
     pub(crate) fn brk(&mut self) {
-        let ret = self.core.pc + 2;
+        let ret = self.core.pc.wrapping_add(2);
         self.push_word(ret);
         self.php();
         self.core.f.i = true;
-        self.jmp(self.memory.read_word(0xfffe));
+        if self.variant.is_cmos() {
+            self.core.f.d = false;
+        }
+        let target = self.read_word(IRQ_VECTOR);
+        self.jmp(target);
     }
 
     pub(crate) fn bvc(&mut self, addr: u16) {
-        if !self.core.f.v {
-            self.branch(addr);
-        }
+        self.branch_if(!self.core.f.v, addr);
     }
 
     pub(crate) fn bvs(&mut self, addr: u16) {
-        if self.core.f.v {
-            self.branch(addr);
-        }
+        self.branch_if(self.core.f.v, addr);
     }
 
     pub(crate) fn clc(&mut self) {
@@ -319,7 +817,7 @@ impl<T: Memory> Processor<T> {
     }
 
     pub(crate) fn cmp(&mut self, addr: u16) {
-        let operand = self.memory.read(addr);
+        let operand = self.read(addr);
         let diff = (self.core.a as i16) - (operand as i16);
         let result = (diff & 0xff) as u8;
         self.core.f.set_n(result);
@@ -328,7 +826,7 @@ impl<T: Memory> Processor<T> {
     }
 
     pub(crate) fn cpx(&mut self, addr: u16) {
-        let operand = self.memory.read(addr);
+        let operand = self.read(addr);
         let diff = (self.core.x as i16) - (operand as i16);
         let result = (diff & 0xff) as u8;
         self.core.f.set_n(result);
@@ -337,7 +835,7 @@ impl<T: Memory> Processor<T> {
     }
 
     pub(crate) fn cpy(&mut self, addr: u16) {
-        let operand = self.memory.read(addr);
+        let operand = self.read(addr);
         let diff = (self.core.y as i16) - (operand as i16);
         let result = (diff & 0xff) as u8;
         self.core.f.set_n(result);
@@ -347,9 +845,10 @@ impl<T: Memory> Processor<T> {
 
     // UNDOCUMENTED OPCODE
     pub(crate) fn dcp(&mut self, addr: u16) {
-        let operand = self.memory.read(addr);
+        let operand = self.read(addr);
+        self.dummy_modify(addr, operand);
         let result = operand.wrapping_sub(1);
-        self.memory.write(addr, result);
+        self.write(addr, result);
         let diff = (self.core.a as i16) - (result as i16);
         let result = (diff & 0xff) as u8;
         self.core.f.set_n(result);
@@ -357,10 +856,18 @@ impl<T: Memory> Processor<T> {
         self.core.f.c = diff >= 0;
     }
 
+    // 65C02 OPCODE
+    pub(crate) fn deca(&mut self) {
+        self.core.a = self.core.a.wrapping_sub(1);
+        self.core.f.set_z(self.core.a);
+        self.core.f.set_n(self.core.a);
+    }
+
     pub(crate) fn dec(&mut self, addr: u16) {
-        let mut operand = self.memory.read(addr);
+        let mut operand = self.read(addr);
+        self.dummy_modify(addr, operand);
         operand = operand.wrapping_sub(1);
-        self.memory.write(addr, operand);
+        self.write(addr, operand);
         self.core.f.set_z(operand);
         self.core.f.set_n(operand);
     }
@@ -378,19 +885,27 @@ impl<T: Memory> Processor<T> {
     }
 
     pub(crate) fn eor(&mut self, addr: u16) {
-        self.core.a ^= self.memory.read(addr);
+        self.core.a ^= self.read(addr);
         self.core.f.set_z(self.core.a);
         self.core.f.set_n(self.core.a);
     }
 
     pub(crate) fn inc(&mut self, addr: u16) {
-        let mut operand = self.memory.read(addr);
+        let mut operand = self.read(addr);
+        self.dummy_modify(addr, operand);
         operand = operand.wrapping_add(1);
-        self.memory.write(addr, operand);
+        self.write(addr, operand);
         self.core.f.set_z(operand);
         self.core.f.set_n(operand);
     }
 
+    // 65C02 OPCODE
+    pub(crate) fn inca(&mut self) {
+        self.core.a = self.core.a.wrapping_add(1);
+        self.core.f.set_z(self.core.a);
+        self.core.f.set_n(self.core.a);
+    }
+
     pub(crate) fn inx(&mut self) {
         self.core.x = self.core.x.wrapping_add(1);
         self.core.f.set_z(self.core.x);
@@ -405,20 +920,17 @@ impl<T: Memory> Processor<T> {
 
     // UNDOCUMENTED OPCODE
     pub(crate) fn isc(&mut self, addr: u16) {
-        let operand = self.memory.read(addr);
+        let operand = self.read(addr);
+        self.dummy_modify(addr, operand);
         let result = operand.wrapping_add(1);
-        self.memory.write(addr, result);
-        let old_a = self.core.a;
-        let carry = (!self.core.f.c) as u8;
+        self.write(addr, result);
+        self.subtract_with_borrow(result);
+    }
 
-        let diff = (old_a as u16)
-            .wrapping_sub(result as u16)
-            .wrapping_sub(carry as u16);
-        self.core.a = (diff & 0x0ff) as u8;
-        self.core.f.c = diff <= 0x0ff;
-        self.core.f.set_z(self.core.a);
-        self.core.f.set_n(self.core.a);
-        self.core.f.v = (old_a ^ operand) & 0x80 != 0 && (old_a ^ self.core.a) & 0x80 != 0;
+    // UNDOCUMENTED OPCODE: locks up the processor until it is reset
+    pub(crate) fn jam(&mut self) {
+        self.halted = true;
+        self.jumped = true;
     }
 
     pub(crate) fn jmp(&mut self, addr: u16) {
@@ -426,15 +938,24 @@ impl<T: Memory> Processor<T> {
         self.jumped = true;
     }
 
+    // The return address has already been pushed by absolute_for_jsr
     pub(crate) fn jsr(&mut self, addr: u16) {
-        let ret = self.core.pc + 2;
-        self.push_word(ret);
         self.jmp(addr);
     }
 
+    // UNDOCUMENTED OPCODE
+    pub(crate) fn las(&mut self, addr: u16) {
+        let value = self.read(addr) & self.core.sp;
+        self.core.a = value;
+        self.core.x = value;
+        self.core.sp = value;
+        self.core.f.set_z(value);
+        self.core.f.set_n(value);
+    }
+
     // UNDOCUMENTED OPCODE
     pub(crate) fn lax(&mut self, addr: u16) {
-        let value = self.memory.read(addr);
+        let value = self.read(addr);
         self.core.a = value;
         self.core.x = value;
         self.core.f.set_z(value);
@@ -442,28 +963,29 @@ impl<T: Memory> Processor<T> {
     }
 
     pub(crate) fn lda(&mut self, addr: u16) {
-        self.core.a = self.memory.read(addr);
+        self.core.a = self.read(addr);
         self.core.f.set_z(self.core.a);
         self.core.f.set_n(self.core.a);
     }
 
     pub(crate) fn ldx(&mut self, addr: u16) {
-        self.core.x = self.memory.read(addr);
+        self.core.x = self.read(addr);
         self.core.f.set_z(self.core.x);
         self.core.f.set_n(self.core.x);
     }
 
     pub(crate) fn ldy(&mut self, addr: u16) {
-        self.core.y = self.memory.read(addr);
+        self.core.y = self.read(addr);
         self.core.f.set_z(self.core.y);
         self.core.f.set_n(self.core.y);
     }
 
     pub(crate) fn lsr(&mut self, addr: u16) {
-        let mut operand = self.memory.read(addr);
+        let mut operand = self.read(addr);
+        self.dummy_modify(addr, operand);
         self.core.f.c = operand & 0x01 != 0;
         operand >>= 1;
-        self.memory.write(addr, operand);
+        self.write(addr, operand);
         self.core.f.set_z(operand);
         self.core.f.set_n(operand);
     }
@@ -475,16 +997,33 @@ impl<T: Memory> Processor<T> {
         self.core.f.set_n(self.core.a);
     }
 
+    // UNSTABLE UNDOCUMENTED OPCODE
+    pub(crate) fn lxa(&mut self, addr: u16) {
+        let value = (self.core.a | self.magic_constant) & self.read(addr);
+        self.core.a = value;
+        self.core.x = value;
+        self.core.f.set_z(value);
+        self.core.f.set_n(value);
+    }
+
     pub(crate) fn nop(&mut self) {
         // do nothing
     }
 
-    pub(crate) fn nop_addr(&mut self, _addr: u16) {
-        // do nothing
+    pub(crate) fn nop_addr(&mut self, addr: u16) {
+        self.read(addr);
+    }
+
+    // 65C02 OPCODE: the undefined $5C spends its extra cycles reading from
+    // the top page
+    pub(crate) fn nop_long(&mut self, addr: u16) {
+        for _ in 0..5 {
+            self.dummy_read(0xff00 | (addr & 0x00ff));
+        }
     }
 
     pub(crate) fn ora(&mut self, addr: u16) {
-        self.core.a |= self.memory.read(addr);
+        self.core.a |= self.read(addr);
         self.core.f.set_z(self.core.a);
         self.core.f.set_n(self.core.a);
     }
@@ -498,25 +1037,54 @@ impl<T: Memory> Processor<T> {
         self.push(self.core.f.get_byte() | 0x30);
     }
 
+    // 65C02 OPCODE
+    pub(crate) fn phx(&mut self) {
+        self.push(self.core.x);
+    }
+
+    // 65C02 OPCODE
+    pub(crate) fn phy(&mut self) {
+        self.push(self.core.y);
+    }
+
     pub(crate) fn pla(&mut self) {
+        self.dummy_read(self.stack_addr());
         self.core.a = self.pull();
         self.core.f.set_z(self.core.a);
         self.core.f.set_n(self.core.a);
     }
 
     pub(crate) fn plp(&mut self) {
+        self.dummy_read(self.stack_addr());
         let byte = self.pull();
         self.core.f.set_byte(byte);
     }
 
+    // 65C02 OPCODE
+    pub(crate) fn plx(&mut self) {
+        self.dummy_read(self.stack_addr());
+        self.core.x = self.pull();
+        self.core.f.set_z(self.core.x);
+        self.core.f.set_n(self.core.x);
+    }
+
+    // 65C02 OPCODE
+    pub(crate) fn ply(&mut self) {
+        self.dummy_read(self.stack_addr());
+        self.core.y = self.pull();
+        self.core.f.set_z(self.core.y);
+        self.core.f.set_n(self.core.y);
+    }
+
     // UNDOCUMENTED OPCODE
     pub(crate) fn rla(&mut self, addr: u16) {
         let carry = self.core.f.c as u8;
-        let operand = self.memory.read(addr);
+        let operand = self.read(addr);
+        self.dummy_modify(addr, operand);
 
         self.core.f.c = operand & 0x80 != 0;
         let intermediate = (operand << 1) | carry;
-        self.memory.write(addr, intermediate);
+        self.write(addr, intermediate);
 
         self.core.a &= intermediate;
         self.core.f.set_z(self.core.a);
@@ -525,12 +1093,13 @@ impl<T: Memory> Processor<T> {
 
     pub(crate) fn rol(&mut self, addr: u16) {
         let carry = self.core.f.c as u8;
-        let mut operand = self.memory.read(addr);
+        let mut operand = self.read(addr);
+        self.dummy_modify(addr, operand);
 
         self.core.f.c = operand & 0x80 != 0;
         operand = (operand << 1) | carry;
 
-        self.memory.write(addr, operand);
+        self.write(addr, operand);
         self.core.f.set_z(operand);
         self.core.f.set_n(operand);
     }
@@ -547,12 +1116,13 @@ impl<T: Memory> Processor<T> {
 
     pub(crate) fn ror(&mut self, addr: u16) {
         let carry = (self.core.f.c as u8) << 7;
-        let mut operand = self.memory.read(addr);
+        let mut operand = self.read(addr);
+        self.dummy_modify(addr, operand);
 
         self.core.f.c = operand & 0x01 != 0;
         operand = (operand >> 1) | carry;
 
-        self.memory.write(addr, operand);
+        self.write(addr, operand);
         self.core.f.set_z(operand);
         self.core.f.set_n(operand);
     }
@@ -570,52 +1140,60 @@ impl<T: Memory> Processor<T> {
     // UNDOCUMENTED OPCODE
     pub(crate) fn rra(&mut self, addr: u16) {
         let carry = self.core.f.c as u8;
-        let operand = self.memory.read(addr);
+        let operand = self.read(addr);
+        self.dummy_modify(addr, operand);
 
         self.core.f.c = operand & 0x01 != 0;
         let intermediate = (operand >> 1) | (carry << 7);
-        self.memory.write(addr, intermediate);
-
-        let old_a = self.core.a;
-        let sum = (old_a as u16) + (intermediate as u16) + (self.core.f.c as u16);
-        self.core.a = (sum & 0xff) as u8;
-        self.core.f.c = sum > 0xff;
-        self.core.f.set_z(self.core.a);
-        self.core.f.set_n(self.core.a);
-        self.core.f.v = (old_a ^ intermediate) & 0x80 == 0 && (old_a ^ self.core.a) & 0x80 != 0;
+        self.write(addr, intermediate);
+        self.add_with_carry(intermediate);
     }
 
     pub(crate) fn rti(&mut self) {
-        self.plp();
+        self.dummy_read(self.stack_addr());
+        let byte = self.pull();
+        self.core.f.set_byte(byte);
+        let lob = self.pull();
+        let hob = self.pull();
+        self.core.pc = ((hob as u16) << 8) | (lob as u16);
         self.jumped = true;
-        self.rts();
     }
 
+    // The pulled address is one byte short of the return address, and is
+    // read while PC is incremented past it
     pub(crate) fn rts(&mut self) {
+        self.dummy_read(self.stack_addr());
         let lob = self.pull();
         let hob = self.pull();
         self.core.pc = ((hob as u16) << 8) | (lob as u16);
+        self.dummy_read(self.core.pc);
     }
 
     // UNDOCUMENTED OPCODE
     pub(crate) fn sax(&mut self, addr: u16) {
         let value = self.core.a & self.core.x;
-        self.memory.write(addr, value);
+        self.write(addr, value);
     }
 
-    pub(crate) fn sbc(&mut self, addr: u16) {
-        let old_a = self.core.a;
-        let carry = (!self.core.f.c) as u8;
-        let operand = self.memory.read(addr);
+    // UNSTABLE UNDOCUMENTED OPCODE
+    pub(crate) fn sha(&mut self, addr: u16) {
+        self.unstable_store(addr, self.core.y, self.core.a & self.core.x);
+    }
 
-        let diff = (old_a as u16)
-            .wrapping_sub(operand as u16)
-            .wrapping_sub(carry as u16);
-        self.core.a = (diff & 0x0ff) as u8;
-        self.core.f.c = diff <= 0x0ff;
-        self.core.f.set_z(self.core.a);
-        self.core.f.set_n(self.core.a);
-        self.core.f.v = (old_a ^ operand) & 0x80 != 0 && (old_a ^ self.core.a) & 0x80 != 0;
+    // UNSTABLE UNDOCUMENTED OPCODE
+    pub(crate) fn shx(&mut self, addr: u16) {
+        self.unstable_store(addr, self.core.y, self.core.x);
+    }
+
+    // UNSTABLE UNDOCUMENTED OPCODE
+    pub(crate) fn shy(&mut self, addr: u16) {
+        self.unstable_store(addr, self.core.x, self.core.y);
+    }
+
+    pub(crate) fn sbc(&mut self, addr: u16) {
+        let operand = self.read(addr);
+        self.subtract_with_borrow(operand);
+        self.decimal_cycle(addr);
     }
 
     pub(crate) fn sec(&mut self) {
@@ -632,11 +1210,12 @@ impl<T: Memory> Processor<T> {
 
     // UNDOCUMENTED OPCODE
     pub(crate) fn slo(&mut self, addr: u16) {
-        let operand = self.memory.read(addr);
+        let operand = self.read(addr);
+        self.dummy_modify(addr, operand);
 
         self.core.f.c = operand & 0x80 != 0;
         let intermediate = operand << 1;
-        self.memory.write(addr, intermediate);
+        self.write(addr, intermediate);
 
         self.core.a |= intermediate;
         self.core.f.set_z(self.core.a);
@@ -645,11 +1224,12 @@ impl<T: Memory> Processor<T> {
 
     // UNDOCUMENTED OPCODE
     pub(crate) fn sre(&mut self, addr: u16) {
-        let operand = self.memory.read(addr);
+        let operand = self.read(addr);
+        self.dummy_modify(addr, operand);
 
         self.core.f.c = operand & 0x01 != 0;
         let intermediate = operand >> 1;
-        self.memory.write(addr, intermediate);
+        self.write(addr, intermediate);
 
         self.core.a ^= intermediate;
         self.core.f.set_z(self.core.a);
@@ -657,15 +1237,32 @@ impl<T: Memory> Processor<T> {
     }
 
     pub(crate) fn sta(&mut self, addr: u16) {
-        self.memory.write(addr, self.core.a);
+        self.write(addr, self.core.a);
     }
 
     pub(crate) fn stx(&mut self, addr: u16) {
-        self.memory.write(addr, self.core.x);
+        self.write(addr, self.core.x);
     }
 
     pub(crate) fn sty(&mut self, addr: u16) {
-        self.memory.write(addr, self.core.y);
+        self.write(addr, self.core.y);
+    }
+
+    // 65C02 OPCODE
+    pub(crate) fn stp(&mut self) {
+        self.dummy_read(self.core.pc.wrapping_add(1));
+        self.halted = true;
+    }
+
+    // 65C02 OPCODE
+    pub(crate) fn stz(&mut self, addr: u16) {
+        self.write(addr, 0);
+    }
+
+    // UNSTABLE UNDOCUMENTED OPCODE
+    pub(crate) fn tas(&mut self, addr: u16) {
+        self.core.sp = self.core.a & self.core.x;
+        self.unstable_store(addr, self.core.y, self.core.sp);
     }
 
     pub(crate) fn tax(&mut self) {
@@ -680,6 +1277,22 @@ impl<T: Memory> Processor<T> {
         self.core.f.set_n(self.core.y);
     }
 
+    // 65C02 OPCODE
+    pub(crate) fn trb(&mut self, addr: u16) {
+        let operand = self.read(addr);
+        self.dummy_modify(addr, operand);
+        self.core.f.z = operand & self.core.a == 0;
+        self.write(addr, operand & !self.core.a);
+    }
+
+    // 65C02 OPCODE
+    pub(crate) fn tsb(&mut self, addr: u16) {
+        let operand = self.read(addr);
+        self.dummy_modify(addr, operand);
+        self.core.f.z = operand & self.core.a == 0;
+        self.write(addr, operand | self.core.a);
+    }
+
     pub(crate) fn tsx(&mut self) {
         self.core.x = self.core.sp;
         self.core.f.set_z(self.core.x);
@@ -702,16 +1315,473 @@ impl<T: Memory> Processor<T> {
         self.core.f.set_n(self.core.a);
     }
 
-    pub fn emulate_instruction(&mut self) {
-        let opcode = self.memory.read(self.core.pc);
+    // 65C02 OPCODE
+    pub(crate) fn wai(&mut self) {
+        self.dummy_read(self.core.pc.wrapping_add(1));
+        self.waiting = true;
+    }
+
+    // UNSTABLE UNDOCUMENTED OPCODE
+    pub(crate) fn xaa(&mut self, addr: u16) {
+        self.core.a = (self.core.a | self.magic_constant) & self.core.x & self.read(addr);
+        self.core.f.set_z(self.core.a);
+        self.core.f.set_n(self.core.a);
+    }
 
-        let (length, cycles) = decode_6502!(opcode; self);
-        self.cycles += cycles;
+    bit_instructions!(
+        0 => (rmb0, smb0, bbr0, bbs0),
+        1 => (rmb1, smb1, bbr1, bbs1),
+        2 => (rmb2, smb2, bbr2, bbs2),
+        3 => (rmb3, smb3, bbr3, bbs3),
+        4 => (rmb4, smb4, bbr4, bbs4),
+        5 => (rmb5, smb5, bbr5, bbs5),
+        6 => (rmb6, smb6, bbr6, bbs6),
+        7 => (rmb7, smb7, bbr7, bbs7)
+    );
+
+    fn execute(&mut self) -> Result<(), Error> {
+        let pc = self.core.pc;
+        let opcode = self.bus_read(pc, AccessKind::OpcodeFetch);
+        self.effective_addr = None;
+        self.page_crossed = false;
+        self.branch_taken = false;
+
+        let decoded = match self.variant {
+            Variant::Cmos65C02 => decode_65c02!(opcode; self),
+            _ => decode_6502!(opcode; self),
+        };
+        let decoded = decoded.ok_or(Error::InvalidOpcode { pc, opcode })?;
+
+        self.cycles += decoded.cycles;
         if self.jumped {
             self.jumped = false;
         } else {
-            self.core.pc += length;
+            self.core.pc = self.core.pc.wrapping_add(decoded.length);
+        }
+        self.decoded = Some(decoded);
+        Ok(())
+    }
+
+    fn perform(&mut self, action: Action) -> Result<(), Error> {
+        match action {
+            // Jammed or waiting, the processor spends a cycle on a read that
+            // gets it nowhere
+            Action::Halted | Action::Waiting => {
+                self.dummy_read(self.core.pc);
+                self.cycles += 1;
+                Ok(())
+            }
+            Action::Interrupt(vector) => {
+                self.interrupt(vector);
+                Ok(())
+            }
+            Action::Instruction => self.execute(),
+        }
+    }
+
+    // Decides what to do next and gets ready to do it
+    fn start_action(&mut self) -> Action {
+        let entry = self.history.as_ref().map(|_| Entry {
+            core: self.core,
+            cycles: self.cycles,
+            jumped: self.jumped,
+            nmi_pending: self.nmi_pending,
+            halted: self.halted,
+            waiting: self.waiting,
+            writes: Vec::new(),
+            undoable: true,
+        });
+
+        let action = self.next_action();
+        self.start_bus_log();
+        if let (Some(profile), false) = (&mut self.profile, action == Action::Halted) {
+            profile.begin(self.core.pc, self.cycles, self.core.sp);
+        }
+        // A halted processor doesn't change, so there is nothing to undo
+        if let (Some(history), Some(entry)) = (&mut self.history, entry) {
+            if action != Action::Halted {
+                history.begin(entry);
+            }
+        }
+        action
+    }
+
+    // Charges the action that just finished to the profile
+    fn end_action(&mut self, action: Action) {
+        let Some(profile) = &mut self.profile else {
+            return;
+        };
+        let mnemonic = self.decoded.map(|decoded| decoded.mnemonic);
+        let (instruction, flow) = match (action, mnemonic) {
+            (Action::Interrupt(_), _) => (false, Flow::Call),
+            (Action::Instruction, Some("JSR" | "BRK")) => (true, Flow::Call),
+            (Action::Instruction, Some("RTS" | "RTI")) => (true, Flow::Return),
+            (action, _) => (action == Action::Instruction, Flow::Other),
+        };
+        profile.end(instruction, flow, self.core.pc, self.cycles, self.core.sp);
+    }
+
+    // Starts counting instructions and cycles by address and subroutine,
+    // from nothing
+    pub fn enable_profiling(&mut self) {
+        self.profile = Some(Profile::new());
+    }
+
+    // Stops profiling and hands back what was counted
+    pub fn disable_profiling(&mut self) -> Option<Profile> {
+        self.profile.take()
+    }
+
+    pub fn profile(&self) -> Option<&Profile> {
+        self.profile.as_ref()
+    }
+
+    // Starts recording what each instruction changes, keeping the most
+    // recent `capacity` instructions. Anything already recorded is dropped.
+    pub fn enable_history(&mut self, capacity: usize) {
+        self.history = Some(History::new(capacity));
+    }
+
+    pub fn disable_history(&mut self) {
+        self.history = None;
+    }
+
+    // Forgets what has been recorded, for when the past no longer leads to
+    // the present, such as after a reset or loading a snapshot
+    pub fn clear_history(&mut self) {
+        if let Some(history) = &mut self.history {
+            history.clear();
+        }
+    }
+
+    // How many instructions step_back() can undo
+    pub fn history_len(&self) -> usize {
+        self.history.as_ref().map_or(0, History::len)
+    }
+
+    // Undoes the most recent instruction, interrupt or wait cycle, putting
+    // back the registers and every byte it wrote. An instruction that tick()
+    // is part way through is abandoned. Returns false if there is nothing
+    // left to undo, or if it wrote somewhere Memory::peek can't see, such as
+    // a device register, since writing the old value back wouldn't put the
+    // device back as it was.
+    pub fn step_back(&mut self) -> bool {
+        let Some(entry) = self.history.as_mut().and_then(History::pop) else {
+            return false;
+        };
+        for (addr, value) in entry.writes.into_iter().rev() {
+            self.memory.write(addr, value);
+        }
+        self.core = entry.core;
+        self.cycles = entry.cycles;
+        self.jumped = entry.jumped;
+        self.nmi_pending = entry.nmi_pending;
+        self.halted = entry.halted;
+        self.waiting = entry.waiting;
+        self.tick_action = None;
+        self.start_bus_log();
+        self.decoded = None;
+        true
+    }
+
+    fn start_bus_log(&mut self) {
+        self.bus_log.clear();
+        self.bus_index = 0;
+        self.bus_replayed = 0;
+        self.bus_live_until = usize::MAX;
+    }
+
+    // Pending interrupts are checked between instructions; servicing one
+    // takes the place of executing an instruction. If tick() has started an
+    // instruction, the rest of it is run instead.
+    pub fn emulate_instruction(&mut self) -> Result<(), Error> {
+        self.step().map(|_| ())
+    }
+
+    // Like emulate_instruction, but reports what happened
+    pub fn step(&mut self) -> Result<Step, Error> {
+        let addr = self.core.pc;
+        // Cycles already run by tick() still count towards this step
+        let start = match self.tick_action {
+            Some(_) => self.cycles - self.bus_log.len(),
+            None => self.cycles,
+        };
+        let action = match self.tick_action {
+            Some(action) => {
+                while !self.tick()? {}
+                action
+            }
+            None => {
+                let action = self.start_action();
+                self.perform(action)?;
+                self.end_action(action);
+                action
+            }
+        };
+        Ok(self.finish_step(addr, start, action))
+    }
+
+    // Like step, but runs a cycle at a time with tick() and calls `cycle`
+    // after each one, for hardware that has to keep in time with the
+    // processor rather than catch up after each instruction
+    pub fn step_cycles(&mut self, mut cycle: impl FnMut(&mut Self)) -> Result<Step, Error> {
+        let addr = self.core.pc;
+        let start = match self.tick_action {
+            Some(_) => self.cycles - self.bus_log.len(),
+            None => self.cycles,
+        };
+        let action = match self.tick_action {
+            Some(action) => action,
+            None => {
+                let action = self.start_action();
+                self.tick_action = Some(action);
+                action
+            }
+        };
+        loop {
+            let before = self.cycles;
+            let finished = self.tick()?;
+            for _ in before..self.cycles {
+                cycle(self);
+            }
+            if finished {
+                break;
+            }
+        }
+        Ok(self.finish_step(addr, start, action))
+    }
+
+    // Describes the action that just finished, which started at `addr` on
+    // cycle `start`
+    fn finish_step(&mut self, addr: u16, start: usize, action: Action) -> Step {
+        let cycles = self.cycles - start;
+
+        let kind = match action {
+            Action::Halted => return Step::new(StepKind::Halted, addr, cycles),
+            Action::Waiting => return Step::new(StepKind::Waiting, addr, cycles),
+            Action::Interrupt(vector) => {
+                return Step::new(StepKind::Interrupt { vector }, addr, cycles)
+            }
+            Action::Instruction => StepKind::Instruction,
+        };
+
+        let decoded = self.decoded.take().expect("an instruction was decoded");
+        let mut step = Step::new(kind, addr, cycles);
+        step.length = decoded.length;
+        for i in 0..decoded.length {
+            step.bytes[i as usize] = self.instruction_byte(addr.wrapping_add(i));
+        }
+        step.mnemonic = decoded.mnemonic;
+        step.mode = decoded.mode;
+        step.page_crossed = self.page_crossed;
+        step.branch_taken = self.branch_taken;
+
+        step.effective_addr = if decoded.mode == AddressingMode::Relative {
+            let base = addr.wrapping_add(2);
+            Some(base.wrapping_add(step.bytes[1] as i8 as u16))
+        } else {
+            self.effective_addr
+        };
+        step.value = step.effective_addr.and_then(|effective_addr| {
+            self.bus_log
+                .iter()
+                .rev()
+                .find(|access| {
+                    access.addr == effective_addr
+                        && matches!(access.kind, AccessKind::Read | AccessKind::Write)
+                })
+                .map(|access| access.value)
+        });
+
+        step
+    }
+
+    // The bytes of the instruction that just ran, as they were fetched
+    fn instruction_byte(&self, addr: u16) -> u8 {
+        self.bus_log
+            .iter()
+            .find(|access| access.addr == addr && !access.kind.is_write())
+            .map(|access| access.value)
+            .unwrap_or_else(|| self.memory.read(addr))
+    }
+
+    // Runs a single cycle and returns true when that cycle finished an
+    // instruction. Each call runs the instruction again from its start,
+    // replaying the bus accesses that were already made, and stops at the
+    // first access that hasn't happened yet so memory sees every access on
+    // the right cycle. A halted processor still spends the cycle.
+    pub fn tick(&mut self) -> Result<bool, Error> {
+        let action = match self.tick_action {
+            Some(action) => action,
+            None => {
+                let action = self.start_action();
+                self.tick_action = Some(action);
+                action
+            }
+        };
+
+        let done = self.bus_log.len();
+        let saved = (
+            self.core,
+            self.cycles,
+            self.jumped,
+            self.halted,
+            self.waiting,
+        );
+
+        self.cycles -= done;
+        self.bus_index = 0;
+        self.bus_replayed = done;
+        self.bus_live_until = done + 1;
+        let result = self.perform(action);
+        let finished = result.is_err() || self.bus_index <= done + 1;
+        self.bus_live_until = usize::MAX;
+
+        if finished {
+            self.tick_action = None;
+            result?;
+            self.end_action(action);
+            Ok(true)
+        } else {
+            (
+                self.core,
+                self.cycles,
+                self.jumped,
+                self.halted,
+                self.waiting,
+            ) = saved;
+            self.cycles += 1;
+            Ok(false)
+        }
+    }
+}
+impl<T: Memory + SaveState> Processor<T> {
+    // Captures everything needed to carry on exactly where the processor
+    // is now, even in the middle of an instruction run with tick()
+    pub fn save_state(&self) -> Vec<u8> {
+        snapshot::write_snapshot(SnapshotKind::Processor, self)
+    }
+
+    // Nothing changes if the snapshot is rejected
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), Error> {
+        snapshot::read_snapshot(SnapshotKind::Processor, self, data)?;
+        self.clear_history();
+        Ok(())
+    }
+}
+
+impl SaveState for Core {
+    fn save(&self, writer: &mut StateWriter) {
+        writer.u8(self.a);
+        writer.u8(self.x);
+        writer.u8(self.y);
+        self.f.save(writer);
+        writer.u8(self.sp);
+        writer.u16(self.pc);
+    }
+
+    fn load(&mut self, reader: &mut StateReader) -> Result<(), Error> {
+        self.a = reader.u8()?;
+        self.x = reader.u8()?;
+        self.y = reader.u8()?;
+        self.f.load(reader)?;
+        self.sp = reader.u8()?;
+        self.pc = reader.u16()?;
+        Ok(())
+    }
+}
+
+const ACCESS_KINDS: [AccessKind; 6] = [
+    AccessKind::OpcodeFetch,
+    AccessKind::OperandFetch,
+    AccessKind::Read,
+    AccessKind::Write,
+    AccessKind::DummyRead,
+    AccessKind::DummyWrite,
+];
+
+const VARIANTS: [Variant; 3] = [Variant::Ricoh2A03, Variant::Nmos6502, Variant::Cmos65C02];
+
+// No instruction or interrupt takes more bus cycles than this
+const MAX_BUS_LOG: usize = 8;
+
+impl<T: Memory + SaveState> SaveState for Processor<T> {
+    fn save(&self, writer: &mut StateWriter) {
+        self.core.save(writer);
+        writer.u64(self.cycles as u64);
+        writer.u8(VARIANTS.iter().position(|v| *v == self.variant).unwrap() as u8);
+        writer.u8(self.magic_constant);
+        writer.bool(self.jumped);
+        writer.bool(self.nmi_line);
+        writer.bool(self.nmi_pending);
+        writer.bool(self.irq_line);
+        writer.bool(self.halted);
+        writer.bool(self.waiting);
+
+        match self.tick_action {
+            None => writer.u8(0),
+            Some(Action::Halted) => writer.u8(1),
+            Some(Action::Waiting) => writer.u8(2),
+            Some(Action::Interrupt(vector)) => {
+                writer.u8(3);
+                writer.u16(vector);
+            }
+            Some(Action::Instruction) => writer.u8(4),
+        }
+        writer.u8(self.bus_log.len() as u8);
+        for access in &self.bus_log {
+            writer.u16(access.addr);
+            writer.u8(access.value);
+            writer.u8(ACCESS_KINDS.iter().position(|k| *k == access.kind).unwrap() as u8);
+        }
+
+        self.memory.save(writer);
+    }
+
+    fn load(&mut self, reader: &mut StateReader) -> Result<(), Error> {
+        let invalid = |reason| Error::InvalidSnapshot { reason };
+
+        self.core.load(reader)?;
+        self.cycles = reader.u64()? as usize;
+        self.variant = *VARIANTS
+            .get(reader.u8()? as usize)
+            .ok_or(invalid("unknown processor variant"))?;
+        self.magic_constant = reader.u8()?;
+        self.jumped = reader.bool()?;
+        self.nmi_line = reader.bool()?;
+        self.nmi_pending = reader.bool()?;
+        self.irq_line = reader.bool()?;
+        self.halted = reader.bool()?;
+        self.waiting = reader.bool()?;
+
+        self.tick_action = match reader.u8()? {
+            0 => None,
+            1 => Some(Action::Halted),
+            2 => Some(Action::Waiting),
+            3 => Some(Action::Interrupt(reader.u16()?)),
+            4 => Some(Action::Instruction),
+            _ => return Err(invalid("unknown processor action")),
+        };
+        let length = reader.u8()? as usize;
+        if length > MAX_BUS_LOG {
+            return Err(invalid("too many bus accesses"));
+        }
+        self.start_bus_log();
+        for _ in 0..length {
+            let addr = reader.u16()?;
+            let value = reader.u8()?;
+            let kind = *ACCESS_KINDS
+                .get(reader.u8()? as usize)
+                .ok_or(invalid("unknown bus access kind"))?;
+            self.bus_log.push(BusAccess { addr, value, kind });
         }
+        // Only step() looks at these, and it fills them in again
+        self.decoded = None;
+        self.effective_addr = None;
+        self.page_crossed = false;
+        self.branch_taken = false;
+
+        self.memory.load(reader)
     }
 }
 
@@ -725,6 +1795,25 @@ mod tests {
         Processor::with_memory(RandomAccessMemory::new(0x1000000))
     }
 
+    pub fn new_nmos_processor() -> Processor<RandomAccessMemory> {
+        Processor::with_variant(RandomAccessMemory::new(0x1000000), Variant::Nmos6502)
+    }
+
+    pub fn new_cmos_processor() -> Processor<RandomAccessMemory> {
+        Processor::with_variant(RandomAccessMemory::new(0x1000000), Variant::Cmos65C02)
+    }
+
+    fn load(cpu: &mut Processor<RandomAccessMemory>, addr: u16, bytes: &[u8]) {
+        for (i, byte) in bytes.iter().enumerate() {
+            cpu.memory.write(addr + i as u16, *byte);
+        }
+        cpu.core.pc = addr;
+    }
+
+    fn to_bcd(n: u8) -> u8 {
+        ((n / 10) << 4) | (n % 10)
+    }
+
     #[test]
     fn test_core_new() {
         let core = Core::new();
@@ -737,6 +1826,618 @@ mod tests {
         assert_eq!(core.pc, 0x0000);
     }
 
+    #[test]
+    fn test_display() {
+        let mut cpu = new_processor();
+        cpu.core.a = 0x12;
+        cpu.cycles = 1000;
+        assert_eq!(cpu.to_string(), "A:12 X:00 Y:00 P:20 SP:FD");
+    }
+
+    #[test]
+    fn test_reset() {
+        let mut cpu = new_processor();
+        cpu.memory.write(RESET_VECTOR, 0x34);
+        cpu.memory.write(RESET_VECTOR + 1, 0x12);
+
+        cpu.reset();
+        assert_eq!(cpu.core.pc, 0x1234);
+        assert_eq!(cpu.core.sp, 0xfa);
+        assert!(cpu.core.f.i);
+        assert_eq!(cpu.cycles, 7);
+    }
+
+    #[test]
+    fn test_nmi() {
+        let mut cpu = new_processor();
+        cpu.memory.write(NMI_VECTOR, 0x00);
+        cpu.memory.write(NMI_VECTOR + 1, 0x30);
+        cpu.memory.write(0x3000, 0xea);
+        cpu.core.pc = 0x0200;
+        cpu.core.f.c = true;
+
+        cpu.set_nmi(true);
+        cpu.emulate_instruction().unwrap();
+        assert_eq!(cpu.core.pc, 0x3000);
+        assert_eq!(cpu.core.sp, 0xfa);
+        assert!(cpu.core.f.i);
+        assert_eq!(cpu.cycles, 7);
+        // B flag clear, bit 5 set
+        assert_eq!(cpu.memory.read(0x1fb), 0x21);
+        assert_eq!(cpu.memory.read_word(0x1fc), 0x0200);
+
+        // Holding the line does not trigger another NMI
+        cpu.set_nmi(true);
+        cpu.emulate_instruction().unwrap();
+        assert_eq!(cpu.core.pc, 0x3001);
+
+        cpu.set_nmi(false);
+        cpu.set_nmi(true);
+        assert!(cpu.nmi_pending());
+        cpu.emulate_instruction().unwrap();
+        assert_eq!(cpu.core.pc, 0x3000);
+    }
+
+    #[test]
+    fn test_irq() {
+        let mut cpu = new_processor();
+        cpu.memory.write(IRQ_VECTOR, 0x00);
+        cpu.memory.write(IRQ_VECTOR + 1, 0x40);
+        cpu.memory.write(0x0200, 0xea);
+        cpu.core.pc = 0x0200;
+        cpu.core.f.i = true;
+
+        cpu.set_irq(true);
+        cpu.emulate_instruction().unwrap();
+        assert_eq!(cpu.core.pc, 0x0201);
+
+        cpu.core.f.i = false;
+        cpu.emulate_instruction().unwrap();
+        assert_eq!(cpu.core.pc, 0x4000);
+        assert!(cpu.core.f.i);
+        assert_eq!(cpu.memory.read(0x1fb), 0x20);
+        assert_eq!(cpu.memory.read_word(0x1fc), 0x0201);
+    }
+
+    #[test]
+    fn test_brk_sets_b_flag() {
+        let mut cpu = new_processor();
+        cpu.memory.write(IRQ_VECTOR, 0x00);
+        cpu.memory.write(IRQ_VECTOR + 1, 0x40);
+        cpu.core.pc = 0x0200;
+
+        cpu.emulate_instruction().unwrap();
+        assert_eq!(cpu.core.pc, 0x4000);
+        assert_eq!(cpu.memory.read(0x1fb), 0x30);
+        assert_eq!(cpu.memory.read_word(0x1fc), 0x0202);
+        assert_eq!(cpu.cycles, 7);
+    }
+
+    #[test]
+    fn test_wrap_at_end_of_memory() {
+        // Instructions at the top of memory carry on from $0000
+        let run = |addr: u16, program: &[u8]| {
+            let mut cpu = new_processor();
+            for (i, byte) in program.iter().enumerate() {
+                cpu.memory.write(addr.wrapping_add(i as u16), *byte);
+            }
+            cpu.memory.write(0x1234, 0x77);
+            cpu.core.pc = addr;
+            cpu.emulate_instruction().unwrap();
+            cpu
+        };
+
+        // NOP
+        assert_eq!(run(0xffff, &[0xea]).core.pc, 0x0000);
+
+        // LDA #$42
+        let cpu = run(0xffff, &[0xa9, 0x42]);
+        assert_eq!((cpu.core.a, cpu.core.pc), (0x42, 0x0001));
+
+        // LDA $1234
+        let cpu = run(0xfffe, &[0xad, 0x34, 0x12]);
+        assert_eq!((cpu.core.a, cpu.core.pc), (0x77, 0x0001));
+
+        // JSR $3000 pushes $0000, the address of its last byte
+        let cpu = run(0xfffe, &[0x20, 0x00, 0x30]);
+        assert_eq!(cpu.core.pc, 0x3000);
+        assert_eq!(cpu.memory.read_word(0x1fc), 0x0000);
+
+        // BRK, whose opcode and padding byte are also the vector at $FFFE
+        let cpu = run(0xfffe, &[0x00, 0x00]);
+        assert_eq!(cpu.core.pc, 0x0000);
+        assert_eq!(cpu.memory.read_word(0x1fc), 0x0000);
+    }
+
+    #[test]
+    fn test_adc_decimal() {
+        let mut cpu = new_nmos_processor();
+        let addr: u16 = 0x1000;
+        cpu.core.f.d = true;
+
+        // Every pair of valid BCD operands gives the decimal sum and carry
+        for a in 0..100 {
+            for b in 0..100 {
+                for c in [false, true] {
+                    cpu.core.a = to_bcd(a);
+                    cpu.core.f.c = c;
+                    cpu.memory.write(addr, to_bcd(b));
+                    cpu.adc(addr);
+
+                    let sum = a + b + c as u8;
+                    assert_eq!(cpu.core.a, to_bcd(sum % 100));
+                    assert_eq!(cpu.core.f.c, sum >= 100);
+                }
+            }
+        }
+
+        // What an NMOS 6502 leaves in A, C, Z, N and V, including operands
+        // that aren't valid BCD. Z follows the binary sum, N and V don't.
+        let cases = [
+            (0x99, 0x01, false, 0x00, true, false, true, false),
+            (0x79, 0x00, true, 0x80, false, false, true, true),
+            (0x93, 0x82, false, 0x75, true, false, false, true),
+            (0x50, 0x50, false, 0x00, true, false, true, true),
+            (0x90, 0x90, false, 0x80, true, false, false, true),
+            (0x0f, 0x0f, false, 0x14, false, false, false, false),
+            (0x0f, 0x01, false, 0x16, false, false, false, false),
+            (0x1c, 0x00, true, 0x23, false, false, false, false),
+            (0x9a, 0x9a, false, 0x9a, true, false, false, true),
+            (0xff, 0xff, true, 0x55, true, false, true, false),
+        ];
+        for (a, b, c, result, carry, zero, negative, overflow) in cases {
+            cpu.core.a = a;
+            cpu.core.f.c = c;
+            cpu.memory.write(addr, b);
+            cpu.adc(addr);
+            assert_eq!(
+                (
+                    cpu.core.a,
+                    cpu.core.f.c,
+                    cpu.core.f.z,
+                    cpu.core.f.n,
+                    cpu.core.f.v
+                ),
+                (result, carry, zero, negative, overflow),
+                "${:02X} + ${:02X} + {}",
+                a,
+                b,
+                c as u8
+            );
+        }
+    }
+
+    #[test]
+    fn test_sbc_decimal() {
+        let mut cpu = new_nmos_processor();
+        let addr: u16 = 0x1000;
+        cpu.core.f.d = true;
+
+        for a in 0..100 {
+            for b in 0..100 {
+                for c in [false, true] {
+                    cpu.core.a = to_bcd(a);
+                    cpu.core.f.c = c;
+                    cpu.memory.write(addr, to_bcd(b));
+                    cpu.sbc(addr);
+
+                    let diff = a as i16 - b as i16 - (!c) as i16;
+                    assert_eq!(cpu.core.a, to_bcd(diff.rem_euclid(100) as u8));
+                    assert_eq!(cpu.core.f.c, diff >= 0);
+                }
+            }
+        }
+
+        // The same for SBC, where all of the flags follow the binary result
+        let cases = [
+            (0x00, 0x01, true, 0x99, false, false, true, false),
+            (0x00, 0x00, false, 0x99, false, false, true, false),
+            (0x01, 0x01, true, 0x00, true, true, false, false),
+            (0x80, 0x01, true, 0x79, true, false, false, true),
+            (0x9a, 0x01, true, 0x99, true, false, true, false),
+            (0x0a, 0x00, true, 0x0a, true, false, false, false),
+            (0x20, 0x0f, true, 0x1b, true, false, false, false),
+        ];
+        for (a, b, c, result, carry, zero, negative, overflow) in cases {
+            cpu.core.a = a;
+            cpu.core.f.c = c;
+            cpu.memory.write(addr, b);
+            cpu.sbc(addr);
+            assert_eq!(
+                (
+                    cpu.core.a,
+                    cpu.core.f.c,
+                    cpu.core.f.z,
+                    cpu.core.f.n,
+                    cpu.core.f.v
+                ),
+                (result, carry, zero, negative, overflow),
+                "${:02X} - ${:02X} - {}",
+                a,
+                b,
+                !c as u8
+            );
+        }
+    }
+
+    #[test]
+    fn test_decimal_flag_ignored_on_2a03() {
+        let mut cpu = new_processor();
+        let addr: u16 = 0x1000;
+        cpu.core.f.d = true;
+
+        cpu.core.a = 0x09;
+        cpu.memory.write(addr, 0x01);
+        cpu.adc(addr);
+        assert_eq!(cpu.core.a, 0x0a);
+
+        cpu.core.f.c = true;
+        cpu.memory.write(addr, 0x0b);
+        cpu.sbc(addr);
+        assert_eq!(cpu.core.a, 0xff);
+    }
+
+    #[test]
+    fn test_rra_isc_decimal() {
+        let mut cpu = new_nmos_processor();
+        let addr: u16 = 0x1000;
+        cpu.core.f.d = true;
+
+        // ROR $12 -> $09, then $09 + $01 + C(0)
+        cpu.core.a = 0x01;
+        cpu.memory.write(addr, 0x12);
+        cpu.rra(addr);
+        assert_eq!(cpu.memory.read(addr), 0x09);
+        assert_eq!(cpu.core.a, 0x10);
+
+        // INC $09 -> $0a, then $20 - $0a with no borrow
+        cpu.core.a = 0x20;
+        cpu.core.f.c = true;
+        cpu.memory.write(addr, 0x09);
+        cpu.isc(addr);
+        assert_eq!(cpu.memory.read(addr), 0x0a);
+        assert_eq!(cpu.core.a, 0x10);
+    }
+
+    #[test]
+    fn test_65c02_undefined_opcodes() {
+        let nops: [(u8, u16, usize); 8] = [
+            (0x03, 1, 1),
+            (0xfb, 1, 1),
+            (0x02, 2, 2),
+            (0xe2, 2, 2),
+            (0x44, 2, 3),
+            (0xd4, 2, 4),
+            (0x5c, 3, 8),
+            (0xfc, 3, 4),
+        ];
+
+        for (opcode, length, cycles) in nops {
+            let mut cpu = new_cmos_processor();
+            load(&mut cpu, 0x0200, &[opcode, 0xff, 0xff]);
+            let expected = alter_by!(cpu.core, pc => 0x0200 + length);
+
+            cpu.emulate_instruction().unwrap();
+            assert_eq!(cpu.core, expected, "opcode 0x{:02x}", opcode);
+            assert_eq!(cpu.cycles, cycles, "opcode 0x{:02x}", opcode);
+        }
+    }
+
+    #[test]
+    fn test_jmp_indirect_page_wrap() {
+        for (mut cpu, target) in [
+            (new_nmos_processor(), 0x1234),
+            (new_cmos_processor(), 0x5634),
+        ] {
+            cpu.memory.write(0x02ff, 0x34);
+            cpu.memory.write(0x0200, 0x12);
+            cpu.memory.write(0x0300, 0x56);
+            load(&mut cpu, 0x1000, &[0x6c, 0xff, 0x02]);
+
+            cpu.emulate_instruction().unwrap();
+            assert_eq!(cpu.core.pc, target);
+        }
+    }
+
+    #[test]
+    fn test_65c02_addressing_modes() {
+        let mut cpu = new_cmos_processor();
+        cpu.memory.write(0x0010, 0x00);
+        cpu.memory.write(0x0011, 0x30);
+        cpu.memory.write(0x3000, 0x42);
+        load(&mut cpu, 0x0200, &[0xb2, 0x10]);
+
+        cpu.emulate_instruction().unwrap();
+        assert_eq!(cpu.core.a, 0x42);
+        assert_eq!(cpu.cycles, 5);
+
+        cpu.core.x = 0x04;
+        cpu.memory.write(0x2004, 0x78);
+        cpu.memory.write(0x2005, 0x56);
+        load(&mut cpu, 0x0200, &[0x7c, 0x00, 0x20]);
+
+        cpu.emulate_instruction().unwrap();
+        assert_eq!(cpu.core.pc, 0x5678);
+    }
+
+    #[test]
+    fn test_stz_trb_tsb() {
+        let mut cpu = new_cmos_processor();
+        let addr: u16 = 0x1000;
+
+        cpu.memory.write(addr, 0xff);
+        cpu.stz(addr);
+        assert_eq!(cpu.memory.read(addr), 0x00);
+
+        cpu.core.a = 0x0f;
+        cpu.memory.write(addr, 0x3c);
+        cpu.trb(addr);
+        assert_eq!(cpu.memory.read(addr), 0x30);
+        assert!(!cpu.core.f.z);
+
+        cpu.tsb(addr);
+        assert_eq!(cpu.memory.read(addr), 0x3f);
+        assert!(cpu.core.f.z);
+    }
+
+    #[test]
+    fn test_65c02_stack_and_accumulator() {
+        let mut cpu = new_cmos_processor();
+
+        cpu.core.x = 0x12;
+        cpu.core.y = 0x80;
+        cpu.phx();
+        cpu.phy();
+        cpu.plx();
+        cpu.ply();
+        assert_eq!(cpu.core.x, 0x80);
+        assert_eq!(cpu.core.y, 0x12);
+        assert!(!cpu.core.f.n);
+
+        cpu.core.a = 0xff;
+        cpu.inca();
+        assert_eq!(cpu.core.a, 0x00);
+        assert!(cpu.core.f.z);
+        cpu.deca();
+        assert_eq!(cpu.core.a, 0xff);
+        assert!(cpu.core.f.n);
+
+        cpu.core.a = 0x01;
+        cpu.memory.write(0x1000, 0xc0);
+        cpu.bit_imm(0x1000);
+        assert!(cpu.core.f.z);
+        assert!(cpu.core.f.n);
+    }
+
+    #[test]
+    fn test_bit_branches() {
+        let mut cpu = new_cmos_processor();
+        cpu.memory.write(0x0010, 0x04);
+        load(&mut cpu, 0x0200, &[0xa7, 0x10, 0xaf, 0x10, 0x10]);
+
+        // SMB2 leaves the bit set
+        cpu.emulate_instruction().unwrap();
+        assert_eq!(cpu.memory.read(0x0010), 0x04);
+        assert_eq!(cpu.core.pc, 0x0202);
+
+        // BBS2 taken
+        cpu.emulate_instruction().unwrap();
+        assert_eq!(cpu.core.pc, 0x0215);
+        assert_eq!(cpu.cycles, 5 + 6);
+
+        cpu.rmb2(0x0010);
+        assert_eq!(cpu.memory.read(0x0010), 0x00);
+        cpu.core.pc = 0x0202;
+        cpu.emulate_instruction().unwrap();
+        assert_eq!(cpu.core.pc, 0x0205);
+
+        load(&mut cpu, 0x0200, &[0x80, 0xfe]);
+        cpu.emulate_instruction().unwrap();
+        assert_eq!(cpu.core.pc, 0x0200);
+    }
+
+    #[test]
+    fn test_wai_stp() {
+        let mut cpu = new_cmos_processor();
+        cpu.memory.write(IRQ_VECTOR, 0x00);
+        cpu.memory.write(IRQ_VECTOR + 1, 0x40);
+        load(&mut cpu, 0x0200, &[0xcb, 0xea, 0xdb]);
+        cpu.core.f.i = true;
+
+        cpu.emulate_instruction().unwrap();
+        assert!(cpu.is_waiting());
+        cpu.emulate_instruction().unwrap();
+        assert_eq!(cpu.core.pc, 0x0201);
+
+        // A masked IRQ resumes execution without being serviced
+        cpu.set_irq(true);
+        cpu.emulate_instruction().unwrap();
+        assert!(!cpu.is_waiting());
+        assert_eq!(cpu.core.pc, 0x0202);
+
+        cpu.emulate_instruction().unwrap();
+        assert!(cpu.is_halted());
+        // A stopped processor gets nowhere, a cycle at a time
+        let cycles = cpu.cycles;
+        cpu.emulate_instruction().unwrap();
+        assert_eq!(cpu.core.pc, 0x0203);
+        assert_eq!(cpu.cycles, cycles + 1);
+        let mut ticks = 0;
+        let step = cpu.step_cycles(|_| ticks += 1).unwrap();
+        assert_eq!((step.kind, step.cycles, ticks), (StepKind::Halted, 1, 1));
+    }
+
+    #[test]
+    fn test_65c02_decimal_mode() {
+        let mut cpu = new_cmos_processor();
+        let addr: u16 = 0x1000;
+        cpu.core.f.d = true;
+
+        cpu.core.a = 0x99;
+        cpu.memory.write(addr, 0x01);
+        cpu.adc(addr);
+        assert_eq!(cpu.core.a, 0x00);
+        assert!(cpu.core.f.c && cpu.core.f.z && !cpu.core.f.n);
+        assert_eq!(cpu.cycles, 1);
+
+        cpu.core.a = 0x00;
+        cpu.core.f.c = true;
+        cpu.sbc(addr);
+        assert_eq!(cpu.core.a, 0x99);
+        assert!(!cpu.core.f.c && !cpu.core.f.z && cpu.core.f.n);
+        assert_eq!(cpu.cycles, 2);
+
+        cpu.memory.write(IRQ_VECTOR, 0x00);
+        cpu.memory.write(IRQ_VECTOR + 1, 0x40);
+        cpu.brk();
+        assert!(!cpu.core.f.d);
+    }
+
+    #[test]
+    fn test_every_nmos_opcode_decodes() {
+        for opcode in 0..=0xff {
+            let mut cpu = new_nmos_processor();
+            load(&mut cpu, 0x0200, &[opcode, 0x10, 0x20]);
+            cpu.emulate_instruction().unwrap();
+            assert!(cpu.cycles >= 2, "opcode 0x{:02x}", opcode);
+        }
+    }
+
+    #[test]
+    fn test_anc_alr() {
+        let mut cpu = new_processor();
+        let addr: u16 = 0x1000;
+
+        cpu.core.a = 0xf0;
+        cpu.memory.write(addr, 0x8f);
+        cpu.anc(addr);
+        assert_eq!(cpu.core.a, 0x80);
+        assert!(cpu.core.f.n && cpu.core.f.c);
+
+        cpu.core.a = 0xff;
+        cpu.memory.write(addr, 0x03);
+        cpu.alr(addr);
+        assert_eq!(cpu.core.a, 0x01);
+        assert!(cpu.core.f.c && !cpu.core.f.n);
+    }
+
+    #[test]
+    fn test_arr() {
+        let mut cpu = new_processor();
+        let addr: u16 = 0x1000;
+
+        cpu.core.a = 0xff;
+        cpu.core.f.c = true;
+        cpu.memory.write(addr, 0xc0);
+        cpu.arr(addr);
+        assert_eq!(cpu.core.a, 0xe0);
+        assert!(cpu.core.f.c && !cpu.core.f.v && cpu.core.f.n);
+
+        cpu.core.a = 0xff;
+        cpu.core.f.c = false;
+        cpu.memory.write(addr, 0x40);
+        cpu.arr(addr);
+        assert_eq!(cpu.core.a, 0x20);
+        assert!(!cpu.core.f.c && cpu.core.f.v);
+
+        let mut cpu = new_nmos_processor();
+        cpu.core.f.d = true;
+        cpu.core.a = 0xff;
+        cpu.core.f.c = false;
+        cpu.memory.write(addr, 0x66);
+        cpu.arr(addr);
+        assert_eq!(cpu.core.a, 0x99);
+        assert!(cpu.core.f.c && !cpu.core.f.n);
+    }
+
+    #[test]
+    fn test_unstable_magic_constant() {
+        let mut cpu = new_processor();
+        let addr: u16 = 0x1000;
+
+        cpu.core.a = 0x00;
+        cpu.core.x = 0xff;
+        cpu.memory.write(addr, 0xff);
+        cpu.xaa(addr);
+        assert_eq!(cpu.core.a, 0xee);
+
+        cpu.magic_constant = 0xff;
+        cpu.core.a = 0x00;
+        cpu.memory.write(addr, 0x5a);
+        cpu.lxa(addr);
+        assert_eq!(cpu.core.a, 0x5a);
+        assert_eq!(cpu.core.x, 0x5a);
+    }
+
+    #[test]
+    fn test_axs_las() {
+        let mut cpu = new_processor();
+        let addr: u16 = 0x1000;
+
+        cpu.core.a = 0x0f;
+        cpu.core.x = 0x3c;
+        cpu.memory.write(addr, 0x0d);
+        cpu.axs(addr);
+        assert_eq!(cpu.core.x, 0xff);
+        assert!(!cpu.core.f.c && cpu.core.f.n);
+
+        cpu.core.sp = 0xf3;
+        cpu.memory.write(addr, 0x3f);
+        cpu.las(addr);
+        assert_eq!(cpu.core.a, 0x33);
+        assert_eq!(cpu.core.x, 0x33);
+        assert_eq!(cpu.core.sp, 0x33);
+    }
+
+    #[test]
+    fn test_unstable_stores() {
+        let mut cpu = new_processor();
+        cpu.core.a = 0xff;
+        cpu.core.x = 0xff;
+        cpu.core.y = 0x10;
+
+        // No page crossing: the value is ANDed with $12 + 1
+        load(&mut cpu, 0x0200, &[0x9f, 0x00, 0x12]);
+        cpu.emulate_instruction().unwrap();
+        assert_eq!(cpu.memory.read(0x1210), 0x13);
+
+        // Page crossing: the value also becomes the high byte
+        load(&mut cpu, 0x0200, &[0x9e, 0xf8, 0x02]);
+        cpu.emulate_instruction().unwrap();
+        assert_eq!(cpu.memory.read(0x0308), 0x03);
+
+        cpu.core.a = 0x0f;
+        load(&mut cpu, 0x0200, &[0x9b, 0x00, 0x12]);
+        cpu.emulate_instruction().unwrap();
+        assert_eq!(cpu.core.sp, 0x0f);
+        assert_eq!(cpu.memory.read(0x1210), 0x03);
+
+        cpu.core.x = 0x01;
+        load(&mut cpu, 0x0200, &[0x9c, 0x00, 0x30]);
+        cpu.emulate_instruction().unwrap();
+        assert_eq!(cpu.memory.read(0x3001), 0x10);
+    }
+
+    #[test]
+    fn test_jam() {
+        let mut cpu = new_processor();
+        cpu.memory.write(RESET_VECTOR, 0x00);
+        cpu.memory.write(RESET_VECTOR + 1, 0x03);
+        load(&mut cpu, 0x0200, &[0x80, 0xff, 0x02]);
+
+        cpu.emulate_instruction().unwrap();
+        assert_eq!(cpu.core.pc, 0x0202);
+
+        cpu.emulate_instruction().unwrap();
+        assert!(cpu.is_halted());
+        cpu.emulate_instruction().unwrap();
+        assert_eq!(cpu.core.pc, 0x0202);
+
+        cpu.reset();
+        assert!(!cpu.is_halted());
+        assert_eq!(cpu.core.pc, 0x0300);
+    }
+
     #[test]
     fn test_clc() {
         let mut cpu = new_processor();
@@ -1016,4 +2717,476 @@ mod tests {
         cpu.tya();
         assert_eq!(expected, cpu.core);
     }
+
+    #[test]
+    fn test_bus_accesses_match_cycles() {
+        for variant in [Variant::Nmos6502, Variant::Cmos65C02] {
+            for opcode in 0..=0xffu8 {
+                // Once with no page crossings and branches not taken, once
+                // with every indexed access crossing a page and every branch
+                // taken across one
+                for (fill, index, flags) in [(0x10, 0x00, 0x00), (0xf0, 0xff, 0xff)] {
+                    let mut cpu =
+                        Processor::with_variant(RandomAccessMemory::new(0x10000), variant);
+                    for addr in 0..=0xffffu16 {
+                        cpu.memory.write(addr, fill);
+                    }
+                    load(&mut cpu, 0x0200, &[opcode]);
+                    cpu.core.x = index;
+                    cpu.core.y = index;
+                    cpu.core.f.set_byte(flags);
+                    cpu.core.f.d = false;
+
+                    cpu.emulate_instruction().unwrap();
+                    assert_eq!(
+                        cpu.bus_accesses().len(),
+                        cpu.cycles,
+                        "{:?} opcode {:02x}",
+                        variant,
+                        opcode
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_rmw_dummy_accesses() {
+        let mut cpu = new_nmos_processor();
+        load(&mut cpu, 0x0200, &[0xe6, 0x10]);
+        cpu.memory.write(0x10, 0x05);
+
+        cpu.emulate_instruction().unwrap();
+        assert_eq!(
+            cpu.bus_accesses(),
+            &[
+                BusAccess {
+                    addr: 0x0200,
+                    value: 0xe6,
+                    kind: AccessKind::OpcodeFetch
+                },
+                BusAccess {
+                    addr: 0x0201,
+                    value: 0x10,
+                    kind: AccessKind::OperandFetch
+                },
+                BusAccess {
+                    addr: 0x0010,
+                    value: 0x05,
+                    kind: AccessKind::Read
+                },
+                BusAccess {
+                    addr: 0x0010,
+                    value: 0x05,
+                    kind: AccessKind::DummyWrite
+                },
+                BusAccess {
+                    addr: 0x0010,
+                    value: 0x06,
+                    kind: AccessKind::Write
+                },
+            ]
+        );
+
+        let mut cpu = new_cmos_processor();
+        load(&mut cpu, 0x0200, &[0xe6, 0x10]);
+        cpu.memory.write(0x10, 0x05);
+
+        cpu.emulate_instruction().unwrap();
+        assert_eq!(
+            cpu.bus_accesses()[3],
+            BusAccess {
+                addr: 0x0010,
+                value: 0x05,
+                kind: AccessKind::DummyRead
+            }
+        );
+    }
+
+    #[test]
+    fn test_indexed_dummy_reads() {
+        // LDA $12F0,X crossing into $1310 reads $1210 first
+        let mut cpu = new_nmos_processor();
+        load(&mut cpu, 0x0200, &[0xbd, 0xf0, 0x12]);
+        cpu.core.x = 0x20;
+        cpu.memory.write(0x1310, 0x42);
+
+        cpu.emulate_instruction().unwrap();
+        assert_eq!(cpu.core.a, 0x42);
+        assert_eq!(cpu.cycles, 5);
+        let accesses = cpu.bus_accesses();
+        assert_eq!(accesses[3].addr, 0x1210);
+        assert_eq!(accesses[3].kind, AccessKind::DummyRead);
+        assert_eq!(accesses[4].addr, 0x1310);
+
+        // STA $1200,X always spends the fix up cycle
+        let mut cpu = new_nmos_processor();
+        load(&mut cpu, 0x0200, &[0x9d, 0x00, 0x12]);
+        cpu.core.x = 0x01;
+
+        cpu.emulate_instruction().unwrap();
+        assert_eq!(cpu.cycles, 5);
+        assert_eq!(cpu.bus_accesses()[3].kind, AccessKind::DummyRead);
+        assert_eq!(cpu.bus_accesses()[3].addr, 0x1201);
+
+        // Implied instructions read the byte after the opcode
+        let mut cpu = new_nmos_processor();
+        load(&mut cpu, 0x0200, &[0xe8]);
+
+        cpu.emulate_instruction().unwrap();
+        assert_eq!(cpu.bus_accesses()[1].addr, 0x0201);
+        assert_eq!(cpu.bus_accesses()[1].kind, AccessKind::DummyRead);
+    }
+
+    #[test]
+    fn test_tick() {
+        // LDX #$03; INC $10; DEX; BNE -5; STA $0300,X
+        let program = [0xa2, 0x03, 0xe6, 0x10, 0xca, 0xd0, 0xfb, 0x9d, 0x00, 0x03];
+        let mut stepped = new_nmos_processor();
+        let mut ticked = new_nmos_processor();
+        load(&mut stepped, 0x0200, &program);
+        load(&mut ticked, 0x0200, &program);
+        stepped.core.a = 0x99;
+        ticked.core.a = 0x99;
+
+        while stepped.core.pc != 0x020a {
+            stepped.emulate_instruction().unwrap();
+
+            let start = ticked.cycles;
+            while !ticked.tick().unwrap() {
+                assert!(ticked.mid_instruction());
+            }
+            assert!(!ticked.mid_instruction());
+            assert_eq!(ticked.cycles, stepped.cycles);
+            assert_eq!(ticked.core, stepped.core);
+            assert_eq!(ticked.bus_accesses(), stepped.bus_accesses());
+            assert_eq!(ticked.cycles - start, ticked.bus_accesses().len());
+        }
+        assert_eq!(ticked.memory.read(0x10), 3);
+
+        // Each access happens on its own cycle, so the write of a store is
+        // only seen on the last one
+        load(&mut ticked, 0x0200, &[0x8d, 0x00, 0x04]);
+        for _ in 0..3 {
+            assert!(!ticked.tick().unwrap());
+            assert_eq!(ticked.memory.read(0x0400), 0x00);
+        }
+        assert!(ticked.tick().unwrap());
+        assert_eq!(ticked.memory.read(0x0400), 0x99);
+
+        // emulate_instruction finishes an instruction that tick started
+        load(&mut ticked, 0x0200, &[0xee, 0x00, 0x04]);
+        ticked.tick().unwrap();
+        ticked.emulate_instruction().unwrap();
+        assert_eq!(ticked.memory.read(0x0400), 0x9a);
+        assert_eq!(ticked.core.pc, 0x0203);
+    }
+
+    // RAM with a register at $4000 that counts the reads and writes to it
+    struct Device {
+        ram: RandomAccessMemory,
+        register_reads: usize,
+        register_writes: usize,
+    }
+
+    impl Memory for Device {
+        fn read(&self, addr: u16) -> u8 {
+            self.ram.read(addr)
+        }
+
+        fn read_bus(&mut self, addr: u16) -> u8 {
+            if addr == 0x4000 {
+                self.register_reads += 1;
+            }
+            self.ram.read(addr)
+        }
+
+        fn peek(&self, addr: u16) -> Option<u8> {
+            (addr != 0x4000).then(|| self.ram.read(addr))
+        }
+
+        fn write(&mut self, addr: u16, data: u8) {
+            match addr {
+                0x4000 => self.register_writes += 1,
+                _ => self.ram.write(addr, data),
+            }
+        }
+
+        fn length(&self) -> usize {
+            0x10000
+        }
+    }
+
+    #[test]
+    fn test_step_back_over_device() {
+        // STA $10; STA $4000; STA $11
+        let mut cpu = Processor::with_memory(Device {
+            ram: RandomAccessMemory::new(0x10000),
+            register_reads: 0,
+            register_writes: 0,
+        });
+        let program = [0x85, 0x10, 0x8d, 0x00, 0x40, 0x85, 0x11];
+        for (i, byte) in program.into_iter().enumerate() {
+            cpu.memory.write(0x0200 + i as u16, byte);
+        }
+        cpu.core.pc = 0x0200;
+        cpu.core.a = 0x42;
+        cpu.enable_history(10);
+        for _ in 0..3 {
+            cpu.step().unwrap();
+        }
+
+        assert!(cpu.step_back());
+        assert_eq!((cpu.core.pc, cpu.memory.read(0x11)), (0x0205, 0));
+        // The register write is left alone, along with everything before it
+        assert!(!cpu.step_back());
+        assert_eq!(cpu.core.pc, 0x0205);
+        assert_eq!(cpu.history_len(), 2);
+        assert_eq!(cpu.memory.register_writes, 1);
+        assert_eq!(cpu.memory.read(0x10), 0x42);
+    }
+
+    #[test]
+    fn test_device_reads() {
+        // LDA $4000; STA $4000,X, whose dummy read lands on the register too
+        let mut cpu = Processor::with_memory(Device {
+            ram: RandomAccessMemory::new(0x10000),
+            register_reads: 0,
+            register_writes: 0,
+        });
+        let program = [0xad, 0x00, 0x40, 0x9d, 0x00, 0x40];
+        for (i, byte) in program.into_iter().enumerate() {
+            cpu.memory.write(0x0200 + i as u16, byte);
+        }
+        cpu.core.pc = 0x0200;
+        cpu.step().unwrap();
+        assert_eq!(cpu.memory.register_reads, 1);
+
+        // tick() replays the reads it has already made rather than making
+        // them again
+        while !cpu.tick().unwrap() {}
+        assert_eq!(cpu.memory.register_reads, 2);
+        assert_eq!(cpu.memory.register_writes, 1);
+        assert_eq!(cpu.memory.read(0x4000), 0);
+        assert_eq!(cpu.memory.register_reads, 2);
+    }
+
+    #[test]
+    fn test_step_cycles() {
+        // INC $10, seeing memory change on the last cycle
+        let mut cpu = new_nmos_processor();
+        load(&mut cpu, 0x0200, &[0xe6, 0x10]);
+        cpu.memory.write(0x10, 0x7f);
+        let mut seen = Vec::new();
+        let step = cpu
+            .step_cycles(|cpu| seen.push(cpu.memory.read(0x10)))
+            .unwrap();
+        assert_eq!(seen, [0x7f, 0x7f, 0x7f, 0x7f, 0x80]);
+        assert_eq!(
+            (step.mnemonic, step.value, step.cycles),
+            ("INC", Some(0x80), 5)
+        );
+        assert_eq!(cpu.cycles, 5);
+        assert!(!cpu.mid_instruction());
+    }
+
+    #[test]
+    fn test_step() {
+        // LDA ($80),Y crossing a page
+        let mut cpu = new_nmos_processor();
+        load(&mut cpu, 0x0200, &[0xb1, 0x80]);
+        cpu.memory.write(0x80, 0xf0);
+        cpu.memory.write(0x81, 0x02);
+        cpu.memory.write(0x0310, 0x42);
+        cpu.core.y = 0x20;
+
+        let step = cpu.step().unwrap();
+        assert_eq!(step.kind, StepKind::Instruction);
+        assert_eq!(step.addr, 0x0200);
+        assert_eq!(step.opcode_bytes(), &[0xb1, 0x80]);
+        assert_eq!(step.mnemonic, "LDA");
+        assert_eq!(step.mode, AddressingMode::IndirectIndexed);
+        assert_eq!(step.effective_addr, Some(0x0310));
+        assert_eq!(step.value, Some(0x42));
+        assert_eq!(step.cycles, 6);
+        assert!(step.page_crossed);
+        assert!(!step.branch_taken);
+
+        // INC $10 reports the value written
+        load(&mut cpu, 0x0200, &[0xe6, 0x10]);
+        cpu.memory.write(0x10, 0x7f);
+
+        let step = cpu.step().unwrap();
+        assert_eq!(step.mnemonic, "INC");
+        assert_eq!(step.mode, AddressingMode::ZeroPage);
+        assert_eq!(step.value, Some(0x80));
+        assert_eq!(step.cycles, 5);
+
+        // BNE taken backwards
+        load(&mut cpu, 0x0200, &[0xd0, 0xfc]);
+        cpu.core.f.z = false;
+
+        let step = cpu.step().unwrap();
+        assert_eq!(step.mode, AddressingMode::Relative);
+        assert_eq!(step.effective_addr, Some(0x01fe));
+        assert!(step.branch_taken);
+        assert!(step.page_crossed);
+        assert_eq!(step.cycles, 4);
+
+        // ROL A
+        load(&mut cpu, 0x0200, &[0x2a]);
+
+        let step = cpu.step().unwrap();
+        assert_eq!(step.mnemonic, "ROL");
+        assert_eq!(step.mode, AddressingMode::Accumulator);
+        assert_eq!(step.effective_addr, None);
+        assert_eq!(step.value, None);
+
+        // An IRQ is reported in place of an instruction
+        cpu.core.f.i = false;
+        cpu.set_irq(true);
+        let step = cpu.step().unwrap();
+        assert_eq!(step.kind, StepKind::Interrupt { vector: IRQ_VECTOR });
+        assert_eq!(step.addr, 0x0201);
+        assert_eq!(step.cycles, 7);
+    }
+
+    #[test]
+    fn test_save_state() {
+        // INC $10; JMP $0200
+        let program = [0xe6, 0x10, 0x4c, 0x00, 0x02];
+        let mut cpu = Processor::with_variant(RandomAccessMemory::new(0x10000), Variant::Nmos6502);
+        load(&mut cpu, 0x0200, &program);
+        cpu.step().unwrap();
+        cpu.step().unwrap();
+        cpu.core.f.c = true;
+        // Stop part way through INC, after it has read the old value
+        for _ in 0..3 {
+            assert!(!cpu.tick().unwrap());
+        }
+        let snapshot = cpu.save_state();
+
+        let mut expected = Vec::new();
+        while expected.len() < 20 {
+            cpu.tick().unwrap();
+            expected.push((cpu.core, cpu.cycles, cpu.memory.read(0x10)));
+        }
+
+        let mut restored =
+            Processor::with_variant(RandomAccessMemory::new(0x10000), Variant::Cmos65C02);
+        restored.load_state(&snapshot).unwrap();
+        assert_eq!(restored.variant, Variant::Nmos6502);
+        assert!(restored.mid_instruction());
+        for state in expected {
+            restored.tick().unwrap();
+            assert_eq!(
+                (restored.core, restored.cycles, restored.memory.read(0x10)),
+                state
+            );
+        }
+
+        // Loading into a machine with a different amount of memory fails
+        // without touching it
+        let mut small = Processor::with_memory(RandomAccessMemory::new(0x1000));
+        small.core.a = 0x12;
+        assert_eq!(
+            small.load_state(&snapshot),
+            Err(Error::InvalidSnapshot {
+                reason: "memory size doesn't match"
+            })
+        );
+        assert_eq!(small.core.a, 0x12);
+        assert_eq!(small.variant, Variant::Ricoh2A03);
+        assert!(!small.mid_instruction());
+
+        // Neither older nor newer layouts are loaded
+        let version = crate::snapshot::SNAPSHOT_VERSION;
+        for other in [version - 1, version + 1] {
+            let mut other_version = snapshot.clone();
+            other_version[4..6].copy_from_slice(&other.to_le_bytes());
+            let length = other_version.len() - 4;
+            let checksum = crate::snapshot::crc32(&other_version[..length]);
+            other_version[length..].copy_from_slice(&checksum.to_le_bytes());
+            assert_eq!(
+                restored.load_state(&other_version),
+                Err(Error::InvalidSnapshot {
+                    reason: "unsupported version"
+                })
+            );
+        }
+    }
+
+    #[test]
+    fn test_step_back() {
+        // LDX #$00; loop: TXA; STA $10,X; INX; CPX #$04; BNE loop; JSR $0300
+        let program = [
+            0xa2, 0x00, 0x8a, 0x95, 0x10, 0xe8, 0xe0, 0x04, 0xd0, 0xf8, 0x20, 0x00, 0x03,
+        ];
+        let mut cpu = new_nmos_processor();
+        load(&mut cpu, 0x0200, &program);
+        cpu.memory.write(0x12, 0x55);
+        assert!(!cpu.step_back());
+
+        cpu.enable_history(100);
+        let mut states = Vec::new();
+        while cpu.core.pc != 0x0300 {
+            states.push((
+                cpu.core,
+                cpu.cycles,
+                cpu.memory.read(0x12),
+                cpu.memory.read(0x1fc),
+            ));
+            cpu.step().unwrap();
+        }
+        assert_eq!(cpu.history_len(), states.len());
+
+        // Half of INC $12, run with tick(), is undone as well
+        cpu.memory.write(0x0300, 0xe6);
+        cpu.memory.write(0x0301, 0x12);
+        let before = (
+            cpu.core,
+            cpu.cycles,
+            cpu.memory.read(0x12),
+            cpu.memory.read(0x1fc),
+        );
+        for _ in 0..4 {
+            cpu.tick().unwrap();
+        }
+        assert!(cpu.step_back());
+        assert!(!cpu.mid_instruction());
+        assert_eq!(
+            (
+                cpu.core,
+                cpu.cycles,
+                cpu.memory.read(0x12),
+                cpu.memory.read(0x1fc)
+            ),
+            before
+        );
+
+        while let Some(state) = states.pop() {
+            assert!(cpu.step_back());
+            assert_eq!(
+                (
+                    cpu.core,
+                    cpu.cycles,
+                    cpu.memory.read(0x12),
+                    cpu.memory.read(0x1fc)
+                ),
+                state
+            );
+        }
+        assert!(!cpu.step_back());
+
+        // Only the most recent instructions are kept
+        cpu.enable_history(2);
+        for _ in 0..5 {
+            cpu.step().unwrap();
+        }
+        assert_eq!(cpu.history_len(), 2);
+        assert!(cpu.step_back());
+        assert!(cpu.step_back());
+        assert!(!cpu.step_back());
+        assert_eq!(cpu.core.pc, 0x0205);
+    }
 }
@@ -0,0 +1,295 @@
+use std::collections::BTreeMap;
+use std::fmt::Display;
+
+// What ran at one address
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AddressProfile {
+    pub instructions: u64,
+    pub cycles: u64,
+}
+
+// Cycles spent in a subroutine, counting what it calls (inclusive) or only
+// its own instructions (exclusive)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RoutineProfile {
+    pub calls: u64,
+    pub inclusive: u64,
+    pub exclusive: u64,
+}
+
+// How an instruction moved between subroutines
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Flow {
+    // JSR, BRK or an interrupt, landing at the new PC
+    Call,
+    // RTS or RTI
+    Return,
+    Other,
+}
+
+// A subroutine being run and the stack pointer just after it was called
+#[derive(Clone, Copy, Debug)]
+struct Frame {
+    routine: u16,
+    sp: u8,
+}
+
+// Counts instructions and cycles for every address, and for every
+// subroutine by following calls and returns. The code running when
+// profiling starts is treated as a subroutine at the address it started at.
+#[derive(Clone, Debug)]
+pub struct Profile {
+    addresses: Vec<AddressProfile>,
+    routines: BTreeMap<u16, RoutineProfile>,
+    // Exclusive cycles for each chain of calls, outermost first
+    stacks: BTreeMap<Vec<u16>, u64>,
+    frames: Vec<Frame>,
+    // The PC and cycle count from when the current instruction started
+    started: Option<(u16, usize)>,
+    pub total_cycles: u64,
+}
+
+impl Default for Profile {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Profile {
+    pub fn new() -> Self {
+        Self {
+            addresses: vec![AddressProfile::default(); 0x10000],
+            routines: BTreeMap::new(),
+            stacks: BTreeMap::new(),
+            frames: Vec::new(),
+            started: None,
+            total_cycles: 0,
+        }
+    }
+
+    pub fn address(&self, addr: u16) -> AddressProfile {
+        self.addresses[addr as usize]
+    }
+
+    pub fn routine(&self, addr: u16) -> Option<RoutineProfile> {
+        self.routines.get(&addr).copied()
+    }
+
+    pub fn routines(&self) -> impl Iterator<Item = (u16, RoutineProfile)> + '_ {
+        self.routines
+            .iter()
+            .map(|(addr, routine)| (*addr, *routine))
+    }
+
+    // Call chains like "C000;C123;C456 1234", as read by flamegraph.pl and
+    // similar tools
+    pub fn collapsed_stacks(&self) -> String {
+        let mut output = String::new();
+        for (stack, cycles) in &self.stacks {
+            let names: Vec<_> = stack.iter().map(|addr| format!("{:04X}", addr)).collect();
+            output += &format!("{} {}\n", names.join(";"), cycles);
+        }
+        output
+    }
+
+    pub(crate) fn begin(&mut self, pc: u16, cycles: usize, sp: u8) {
+        if self.frames.is_empty() {
+            self.frames.push(Frame { routine: pc, sp });
+            self.routines.entry(pc).or_default().calls += 1;
+        }
+        self.started = Some((pc, cycles));
+    }
+
+    // Charges the instruction that began at the last begin() to its address
+    // and to the subroutines it ran in, then follows any call or return
+    pub(crate) fn end(&mut self, instruction: bool, flow: Flow, pc: u16, cycles: usize, sp: u8) {
+        let Some((addr, start)) = self.started.take() else {
+            return;
+        };
+        let spent = cycles.saturating_sub(start) as u64;
+        self.total_cycles += spent;
+        let counts = &mut self.addresses[addr as usize];
+        counts.instructions += instruction as u64;
+        counts.cycles += spent;
+
+        let stack: Vec<_> = self.frames.iter().map(|frame| frame.routine).collect();
+        *self.stacks.entry(stack.clone()).or_default() += spent;
+        for (i, routine) in stack.iter().enumerate() {
+            let counts = self.routines.entry(*routine).or_default();
+            // A recursive routine only counts once
+            if !stack[..i].contains(routine) {
+                counts.inclusive += spent;
+            }
+            if i == stack.len() - 1 {
+                counts.exclusive += spent;
+            }
+        }
+
+        match flow {
+            Flow::Call => {
+                self.frames.push(Frame { routine: pc, sp });
+                self.routines.entry(pc).or_default().calls += 1;
+            }
+            // Drop every call the stack has been unwound past, so code that
+            // pulls its return address and returns from further up stays in
+            // step. The outermost frame is never left.
+            Flow::Return => {
+                while self.frames.len() > 1 && self.frames[self.frames.len() - 1].sp < sp {
+                    self.frames.pop();
+                }
+            }
+            Flow::Other => {}
+        }
+    }
+
+    // Forgets the call stack, for when the processor is reset
+    pub(crate) fn restart(&mut self) {
+        self.frames.clear();
+        self.started = None;
+    }
+}
+
+// Subroutines by inclusive cycles, then the busiest addresses
+impl Display for Profile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let percent = |cycles: u64| cycles as f64 * 100.0 / self.total_cycles.max(1) as f64;
+
+        let mut routines: Vec<_> = self.routines().collect();
+        routines.sort_by_key(|(addr, routine)| (std::cmp::Reverse(routine.inclusive), *addr));
+        writeln!(f, "routine      calls       inclusive            exclusive")?;
+        for (addr, routine) in routines {
+            writeln!(
+                f,
+                "${:04X} {:>11} {:>12} {:5.1}% {:>12} {:5.1}%",
+                addr,
+                routine.calls,
+                routine.inclusive,
+                percent(routine.inclusive),
+                routine.exclusive,
+                percent(routine.exclusive)
+            )?;
+        }
+
+        let mut addresses: Vec<_> = (0..=0xffff)
+            .map(|addr| (addr, self.address(addr)))
+            .filter(|(_, counts)| counts.cycles > 0)
+            .collect();
+        addresses.sort_by_key(|(addr, counts)| (std::cmp::Reverse(counts.cycles), *addr));
+        writeln!(f)?;
+        writeln!(f, "address  instructions       cycles")?;
+        for (addr, counts) in addresses {
+            writeln!(
+                f,
+                "${:04X} {:>15} {:>12} {:5.1}%",
+                addr,
+                counts.instructions,
+                counts.cycles,
+                percent(counts.cycles)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Memory, Processor, RandomAccessMemory};
+
+    fn processor(code: &[(u16, &[u8])]) -> Processor<RandomAccessMemory> {
+        let mut cpu = Processor::with_memory(RandomAccessMemory::new(0x10000));
+        for (addr, bytes) in code {
+            for (i, byte) in bytes.iter().enumerate() {
+                cpu.memory.write(addr + i as u16, *byte);
+            }
+        }
+        cpu.core.pc = 0x0200;
+        cpu.enable_profiling();
+        cpu
+    }
+
+    #[test]
+    fn test_profile() {
+        let mut cpu = processor(&[
+            // JSR $0210; JSR $0210; JMP $0206
+            (
+                0x0200,
+                &[0x20, 0x10, 0x02, 0x20, 0x10, 0x02, 0x4c, 0x06, 0x02],
+            ),
+            // INX; JSR $0220; RTS
+            (0x0210, &[0xe8, 0x20, 0x20, 0x02, 0x60]),
+            // INY; RTS
+            (0x0220, &[0xc8, 0x60]),
+        ]);
+        for _ in 0..7 {
+            cpu.step().unwrap();
+        }
+        // tick() counts the same way
+        let mut finished = 0;
+        while finished < 7 {
+            finished += cpu.tick().unwrap() as usize;
+        }
+
+        let profile = cpu.disable_profiling().unwrap();
+        assert_eq!(profile.total_cycles, 62);
+        assert_eq!(
+            profile.address(0x0211),
+            AddressProfile {
+                instructions: 2,
+                cycles: 12
+            }
+        );
+        assert_eq!(
+            profile.address(0x0206),
+            AddressProfile {
+                instructions: 2,
+                cycles: 6
+            }
+        );
+        let routine = |calls, inclusive, exclusive| {
+            Some(RoutineProfile {
+                calls,
+                inclusive,
+                exclusive,
+            })
+        };
+        assert_eq!(profile.routine(0x0200), routine(1, 62, 18));
+        assert_eq!(profile.routine(0x0210), routine(2, 44, 28));
+        assert_eq!(profile.routine(0x0220), routine(2, 16, 16));
+        assert_eq!(
+            profile.collapsed_stacks(),
+            "0200 18\n0200;0210 28\n0200;0210;0220 16\n"
+        );
+
+        let report = profile.to_string();
+        let lines: Vec<_> = report.lines().collect();
+        assert_eq!(
+            lines[1],
+            "$0200           1           62 100.0%           18  29.0%"
+        );
+        assert_eq!(lines[6], "$0211               2           12  19.4%");
+        assert!(cpu.profile().is_none());
+    }
+
+    #[test]
+    fn test_unwinding() {
+        let mut cpu = processor(&[
+            // JSR $0210; NOP
+            (0x0200, &[0x20, 0x10, 0x02, 0xea]),
+            // JSR $0220; NOP
+            (0x0210, &[0x20, 0x20, 0x02, 0xea]),
+            // PLA; PLA; RTS, returning straight to $0203
+            (0x0220, &[0x68, 0x68, 0x60]),
+        ]);
+        for _ in 0..6 {
+            cpu.step().unwrap();
+        }
+        assert_eq!(cpu.core.pc, 0x0204);
+
+        // The NOP at $0203 is back in the outermost routine
+        let profile = cpu.profile().unwrap();
+        assert_eq!(profile.routine(0x0200).unwrap().exclusive, 8);
+        assert_eq!(profile.routine(0x0210).unwrap().exclusive, 6);
+        assert_eq!(profile.routine(0x0220).unwrap().exclusive, 14);
+    }
+}
@@ -0,0 +1,116 @@
+use crate::error::Error;
+use crate::memory::Memory;
+use crate::processor::{Core, Processor};
+
+// Why one of the run methods returned
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StopReason {
+    // At least the requested number of cycles has run. The last instruction
+    // is always finished, so a few more may have.
+    BudgetExhausted,
+    // The PC reached the requested address, or the condition became true.
+    // The instruction at pc hasn't run yet.
+    Breakpoint { pc: u16 },
+    // The instruction at pc couldn't be decoded; nothing was executed
+    InvalidOpcode { pc: u16, opcode: u8 },
+    // A JAM/KIL instruction has stopped the processor until it is reset
+    Halted,
+}
+
+impl<T: Memory> Processor<T> {
+    // Runs whole instructions until at least `budget` cycles have passed
+    pub fn run_cycles(&mut self, budget: usize) -> StopReason {
+        self.run_until(budget, |_, _| false)
+    }
+
+    // Runs until the next instruction is at addr. At least one instruction
+    // runs first, so this carries on from a breakpoint it stopped at.
+    pub fn run_until_pc(&mut self, addr: u16, budget: usize) -> StopReason {
+        self.run_until(budget, |core, _| core.pc == addr)
+    }
+
+    // Runs until the condition is true after an instruction, or the budget
+    // runs out. The condition isn't checked before the first instruction,
+    // as a debugger continuing from a breakpoint would want. Time spent
+    // waiting for an interrupt counts towards the budget.
+    pub fn run_until<F>(&mut self, budget: usize, mut condition: F) -> StopReason
+    where
+        F: FnMut(&Core, &T) -> bool,
+    {
+        let start = self.cycles;
+        loop {
+            if self.is_halted() {
+                return StopReason::Halted;
+            }
+            if self.cycles - start >= budget {
+                return StopReason::BudgetExhausted;
+            }
+
+            match self.step() {
+                Ok(_) => {}
+                Err(Error::InvalidOpcode { pc, opcode }) => {
+                    return StopReason::InvalidOpcode { pc, opcode }
+                }
+                Err(err) => unreachable!("instructions only fail to decode: {}", err),
+            }
+            if condition(&self.core, &self.memory) {
+                return StopReason::Breakpoint { pc: self.core.pc };
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::RandomAccessMemory;
+    use crate::variant::Variant;
+
+    fn processor(program: &[u8]) -> Processor<RandomAccessMemory> {
+        let mut cpu = Processor::with_variant(RandomAccessMemory::new(0x10000), Variant::Nmos6502);
+        for (i, byte) in program.iter().enumerate() {
+            cpu.memory.write(0x0200 + i as u16, *byte);
+        }
+        cpu.core.pc = 0x0200;
+        cpu
+    }
+
+    #[test]
+    fn test_run() {
+        // INX; INC $10; JMP $0200
+        let mut cpu = processor(&[0xe8, 0xe6, 0x10, 0x4c, 0x00, 0x02]);
+
+        assert_eq!(cpu.run_cycles(11), StopReason::BudgetExhausted);
+        assert_eq!(cpu.cycles, 12);
+        assert_eq!(cpu.core.x, 2);
+        assert_eq!(cpu.run_cycles(0), StopReason::BudgetExhausted);
+        assert_eq!(cpu.cycles, 12);
+
+        assert_eq!(
+            cpu.run_until_pc(0x0203, 100),
+            StopReason::Breakpoint { pc: 0x0203 }
+        );
+        assert_eq!(cpu.memory.read(0x10), 2);
+        // Carrying on from the breakpoint goes round the loop again
+        assert_eq!(
+            cpu.run_until_pc(0x0203, 100),
+            StopReason::Breakpoint { pc: 0x0203 }
+        );
+        assert_eq!((cpu.core.x, cpu.memory.read(0x10)), (3, 3));
+        assert_eq!(cpu.run_until_pc(0x0400, 100), StopReason::BudgetExhausted);
+
+        assert_eq!(
+            cpu.run_until(1000, |core, memory| core.x == 20 && memory.read(0x10) == 20),
+            StopReason::Breakpoint { pc: 0x0203 }
+        );
+    }
+
+    #[test]
+    fn test_run_halted() {
+        // NOP; JAM
+        let mut cpu = processor(&[0xea, 0x02]);
+        assert_eq!(cpu.run_cycles(100), StopReason::Halted);
+        assert_eq!(cpu.cycles, 2 + 2);
+        assert_eq!(cpu.run_cycles(100), StopReason::Halted);
+    }
+}
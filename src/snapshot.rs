@@ -0,0 +1,278 @@
+use crate::error::Error;
+use crate::flags::Flags;
+use crate::memory::{Memory, MirroredMemory, RandomAccessMemory, ReadOnlyMemory};
+
+// Snapshots start with the magic number, the format version and what kind of
+// machine they hold, and end with a CRC-32 of everything before it
+const MAGIC: &[u8; 4] = b"R65S";
+// Bumped whenever the layout changes. 2 added the PPU's position and the
+// MMC1, MMC3 and discrete-logic mapper state, 3 NROM's CHR RAM, 4 the PPU's
+// registers.
+pub const SNAPSHOT_VERSION: u16 = 4;
+const HEADER_LENGTH: usize = 7;
+const CHECKSUM_LENGTH: usize = 4;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum SnapshotKind {
+    Processor = 0,
+    Nes = 1,
+}
+
+fn invalid(reason: &'static str) -> Error {
+    Error::InvalidSnapshot { reason }
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffffffffu32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xedb88320 & mask);
+        }
+    }
+    !crc
+}
+
+// Something whose state can be written to and read back from a snapshot.
+// Only state that changes while the machine runs is saved; ROM contents and
+// sizes come from whatever the snapshot is loaded into.
+pub trait SaveState {
+    fn save(&self, writer: &mut StateWriter);
+    fn load(&mut self, reader: &mut StateReader) -> Result<(), Error>;
+}
+
+#[derive(Debug, Default)]
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.u8(value as u8);
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    // Written with its length so that loading can check it
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        self.u32(bytes.len() as u32);
+        self.data.extend_from_slice(bytes);
+    }
+}
+
+pub struct StateReader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> StateReader<'a> {
+    fn take(&mut self, length: usize) -> Result<&'a [u8], Error> {
+        let bytes = self
+            .data
+            .get(self.offset..self.offset + length)
+            .ok_or(invalid("snapshot is truncated"))?;
+        self.offset += length;
+        Ok(bytes)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, Error> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn bool(&mut self) -> Result<bool, Error> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(invalid("bad boolean")),
+        }
+    }
+
+    pub fn u16(&mut self) -> Result<u16, Error> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, Error> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, Error> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    pub fn bytes(&mut self) -> Result<&'a [u8], Error> {
+        let length = self.u32()? as usize;
+        self.take(length)
+    }
+
+    // Reads bytes written by StateWriter::bytes into a buffer that must be
+    // the same size
+    pub fn bytes_into(&mut self, buffer: &mut [u8]) -> Result<(), Error> {
+        let bytes = self.bytes()?;
+        if bytes.len() != buffer.len() {
+            return Err(invalid("memory size doesn't match"));
+        }
+        buffer.copy_from_slice(bytes);
+        Ok(())
+    }
+}
+
+pub(crate) fn write_snapshot(kind: SnapshotKind, state: &impl SaveState) -> Vec<u8> {
+    let mut writer = StateWriter::default();
+    writer.data.extend_from_slice(MAGIC);
+    writer.u16(SNAPSHOT_VERSION);
+    writer.u8(kind as u8);
+    state.save(&mut writer);
+
+    let checksum = crc32(&writer.data);
+    writer.u32(checksum);
+    writer.data
+}
+
+// Checks the header and checksum before anything is loaded. If the body turns
+// out not to fit, the state from before the load is put back.
+pub(crate) fn read_snapshot<S: SaveState>(
+    kind: SnapshotKind,
+    state: &mut S,
+    data: &[u8],
+) -> Result<(), Error> {
+    if data.len() < HEADER_LENGTH + CHECKSUM_LENGTH || &data[..4] != MAGIC {
+        return Err(invalid("not a snapshot"));
+    }
+    let (body, checksum) = data.split_at(data.len() - CHECKSUM_LENGTH);
+    if crc32(body) != u32::from_le_bytes(checksum.try_into().unwrap()) {
+        return Err(invalid("checksum doesn't match"));
+    }
+
+    let mut reader = StateReader {
+        data: body,
+        offset: 4,
+    };
+    if reader.u16()? != SNAPSHOT_VERSION {
+        return Err(invalid("unsupported version"));
+    }
+    if reader.u8()? != kind as u8 {
+        return Err(invalid("snapshot is for a different machine"));
+    }
+
+    let mut backup = StateWriter::default();
+    state.save(&mut backup);
+
+    let result = state.load(&mut reader).and_then(|_| {
+        if reader.offset == body.len() {
+            Ok(())
+        } else {
+            Err(invalid("unexpected data at the end"))
+        }
+    });
+    if result.is_err() {
+        let mut restore = StateReader {
+            data: &backup.data,
+            offset: 0,
+        };
+        state
+            .load(&mut restore)
+            .expect("a state we just saved can be loaded");
+    }
+    result
+}
+
+impl SaveState for Flags {
+    fn save(&self, writer: &mut StateWriter) {
+        writer.u8(self.get_byte());
+    }
+
+    fn load(&mut self, reader: &mut StateReader) -> Result<(), Error> {
+        self.set_byte(reader.u8()?);
+        Ok(())
+    }
+}
+
+impl SaveState for RandomAccessMemory {
+    fn save(&self, writer: &mut StateWriter) {
+        writer.bytes(&self.contents);
+    }
+
+    fn load(&mut self, reader: &mut StateReader) -> Result<(), Error> {
+        reader.bytes_into(&mut self.contents)
+    }
+}
+
+impl SaveState for ReadOnlyMemory {
+    fn save(&self, _writer: &mut StateWriter) {}
+
+    fn load(&mut self, _reader: &mut StateReader) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+impl<T: Memory + SaveState> SaveState for MirroredMemory<T> {
+    fn save(&self, writer: &mut StateWriter) {
+        self.underlying.save(writer);
+    }
+
+    fn load(&mut self, reader: &mut StateReader) -> Result<(), Error> {
+        self.underlying.load(reader)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b"123456789"), 0xcbf43926);
+        assert_eq!(crc32(b""), 0);
+    }
+
+    #[test]
+    fn test_memory_round_trip() {
+        let mut ram = RandomAccessMemory::new(0x10);
+        ram.write(0x04, 0x42);
+        let snapshot = write_snapshot(SnapshotKind::Processor, &ram);
+
+        let mut loaded = RandomAccessMemory::new(0x10);
+        read_snapshot(SnapshotKind::Processor, &mut loaded, &snapshot).unwrap();
+        assert_eq!(loaded.contents, ram.contents);
+
+        // A different size is rejected and leaves the memory alone
+        let mut small = RandomAccessMemory::new(0x08);
+        small.write(0x00, 0x99);
+        assert_eq!(
+            read_snapshot(SnapshotKind::Processor, &mut small, &snapshot),
+            Err(invalid("memory size doesn't match"))
+        );
+        assert_eq!(small.read(0x00), 0x99);
+
+        assert_eq!(
+            read_snapshot(SnapshotKind::Nes, &mut loaded, &snapshot),
+            Err(invalid("snapshot is for a different machine"))
+        );
+
+        let mut corrupt = snapshot.clone();
+        corrupt[10] ^= 0xff;
+        assert_eq!(
+            read_snapshot(SnapshotKind::Processor, &mut loaded, &corrupt),
+            Err(invalid("checksum doesn't match"))
+        );
+        assert_eq!(
+            read_snapshot(SnapshotKind::Processor, &mut loaded, b"R65"),
+            Err(invalid("not a snapshot"))
+        );
+    }
+}
@@ -0,0 +1,106 @@
+use std::fmt::Display;
+
+use crate::opcode::AddressingMode;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StepKind {
+    Instruction,
+    // An NMI or IRQ was serviced in place of an instruction
+    Interrupt { vector: u16 },
+    // WAI is waiting for an interrupt
+    Waiting,
+    // JAM or STP has stopped the processor
+    Halted,
+}
+
+// Everything that happened during one call to Processor::step. Only
+// instructions fill in the fields after kind and addr.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Step {
+    pub kind: StepKind,
+    pub addr: u16,
+    pub bytes: [u8; 3],
+    pub length: u16,
+    pub mnemonic: &'static str,
+    pub mode: AddressingMode,
+    // Branch targets for relative instructions, otherwise the address the
+    // operand resolved to
+    pub effective_addr: Option<u16>,
+    // The value read from or written to the effective address; the written
+    // value for read-modify-write instructions
+    pub value: Option<u8>,
+    pub cycles: usize,
+    pub page_crossed: bool,
+    pub branch_taken: bool,
+}
+
+impl Step {
+    pub(crate) fn new(kind: StepKind, addr: u16, cycles: usize) -> Self {
+        Self {
+            kind,
+            addr,
+            bytes: [0; 3],
+            length: 0,
+            mnemonic: "",
+            mode: AddressingMode::Implied,
+            effective_addr: None,
+            value: None,
+            cycles,
+            page_crossed: false,
+            branch_taken: false,
+        }
+    }
+
+    pub fn opcode_bytes(&self) -> &[u8] {
+        &self.bytes[..self.length as usize]
+    }
+
+    // The operand in assembler syntax, with branch targets worked out
+    pub fn operand(&self) -> String {
+        let byte = self.bytes[1];
+        let word = u16::from_le_bytes([self.bytes[1], self.bytes[2]]);
+        let target = |offset: u8, length: u16| {
+            self.addr
+                .wrapping_add(length)
+                .wrapping_add(offset as i8 as u16)
+        };
+        match self.mode {
+            AddressingMode::Implied => String::new(),
+            AddressingMode::Accumulator => "A".to_string(),
+            AddressingMode::Immediate => format!("#${:02X}", byte),
+            AddressingMode::ZeroPage => format!("${:02X}", byte),
+            AddressingMode::ZeroPageX => format!("${:02X},X", byte),
+            AddressingMode::ZeroPageY => format!("${:02X},Y", byte),
+            AddressingMode::Relative => format!("${:04X}", target(byte, 2)),
+            AddressingMode::Absolute => format!("${:04X}", word),
+            AddressingMode::AbsoluteX => format!("${:04X},X", word),
+            AddressingMode::AbsoluteY => format!("${:04X},Y", word),
+            AddressingMode::Indirect => format!("(${:04X})", word),
+            AddressingMode::IndexedIndirect => format!("(${:02X},X)", byte),
+            AddressingMode::IndirectIndexed => format!("(${:02X}),Y", byte),
+            AddressingMode::ZeroPageIndirect => format!("(${:02X})", byte),
+            AddressingMode::ZeroPageRelative => {
+                format!("${:02X},${:04X}", byte, target(self.bytes[2], 3))
+            }
+            AddressingMode::AbsoluteIndexedIndirect => format!("(${:04X},X)", word),
+        }
+    }
+}
+
+impl Display for Step {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.kind {
+            StepKind::Instruction => {
+                let operand = self.operand();
+                if operand.is_empty() {
+                    write!(f, "{}", self.mnemonic)
+                } else {
+                    write!(f, "{} {}", self.mnemonic, operand)
+                }
+            }
+            StepKind::Interrupt { vector } => write!(f, "interrupt via ${:04X}", vector),
+            StepKind::Waiting => write!(f, "waiting"),
+            StepKind::Halted => write!(f, "halted"),
+        }
+    }
+}
@@ -0,0 +1,498 @@
+use std::io::{self, Write};
+use std::ops::RangeInclusive;
+
+use crate::debugger::Expression;
+use crate::error::Error;
+use crate::flags::Flags;
+use crate::memory::Memory;
+use crate::opcode::{AddressingMode, OpcodeInfo};
+use crate::processor::Processor;
+use crate::step::{Step, StepKind};
+
+// The instruction at PC, decoded from memory without running it, with the
+// addresses its operand resolves to given the current registers
+pub(crate) struct Upcoming {
+    pub info: &'static OpcodeInfo,
+    pub step: Step,
+    // Where an indirect mode's pointer is stored, and the pointer itself
+    pub pointer: Option<(u16, u16)>,
+    pub effective_addr: Option<u16>,
+}
+
+impl Upcoming {
+    pub fn new<T: Memory>(cpu: &Processor<T>) -> Self {
+        let pc = cpu.core.pc;
+        let info = &cpu.variant.opcodes()[cpu.memory.read(pc) as usize];
+        let mut step = Step::new(StepKind::Instruction, pc, 0);
+        step.length = info.length;
+        step.mnemonic = info.mnemonic;
+        step.mode = info.mode;
+        for i in 0..info.length {
+            step.bytes[i as usize] = cpu.memory.read(pc.wrapping_add(i));
+        }
+
+        let byte = step.bytes[1];
+        let word = u16::from_le_bytes([step.bytes[1], step.bytes[2]]);
+        let (x, y) = (cpu.core.x, cpu.core.y);
+        // Pointers in zero page wrap around within it
+        let zero_page_word = |addr: u8| {
+            u16::from_le_bytes([
+                cpu.memory.read(addr as u16),
+                cpu.memory.read(addr.wrapping_add(1) as u16),
+            ])
+        };
+
+        let (pointer, effective_addr) = match info.mode {
+            AddressingMode::ZeroPage => (None, Some(byte as u16)),
+            AddressingMode::ZeroPageX => (None, Some(byte.wrapping_add(x) as u16)),
+            AddressingMode::ZeroPageY => (None, Some(byte.wrapping_add(y) as u16)),
+            AddressingMode::Absolute => (None, Some(word)),
+            AddressingMode::AbsoluteX => (None, Some(word.wrapping_add(x as u16))),
+            AddressingMode::AbsoluteY => (None, Some(word.wrapping_add(y as u16))),
+            AddressingMode::Indirect => {
+                // The NMOS parts don't carry into the high byte
+                let high = if cpu.variant.is_cmos() {
+                    word.wrapping_add(1)
+                } else {
+                    (word & 0xff00) | (word.wrapping_add(1) & 0x00ff)
+                };
+                let target = u16::from_le_bytes([cpu.memory.read(word), cpu.memory.read(high)]);
+                (Some((word, target)), None)
+            }
+            AddressingMode::IndexedIndirect => {
+                let location = byte.wrapping_add(x);
+                let pointer = zero_page_word(location);
+                (Some((location as u16, pointer)), Some(pointer))
+            }
+            AddressingMode::IndirectIndexed => {
+                let pointer = zero_page_word(byte);
+                (
+                    Some((byte as u16, pointer)),
+                    Some(pointer.wrapping_add(y as u16)),
+                )
+            }
+            AddressingMode::ZeroPageIndirect => {
+                let pointer = zero_page_word(byte);
+                (Some((byte as u16, pointer)), Some(pointer))
+            }
+            AddressingMode::ZeroPageRelative => (None, Some(byte as u16)),
+            AddressingMode::AbsoluteIndexedIndirect => {
+                let location = word.wrapping_add(x as u16);
+                let target = cpu.memory.read_word(location);
+                (Some((location, target)), None)
+            }
+            _ => (None, None),
+        };
+
+        Self {
+            info,
+            step,
+            pointer,
+            effective_addr,
+        }
+    }
+
+    // JMP and JSR use their operand as the destination rather than reading it
+    pub fn is_jump(&self) -> bool {
+        matches!(self.info.mnemonic, "JMP" | "JSR")
+    }
+}
+
+// Where the PPU is in its frame. Scanline 261 is the pre-render line.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PpuPosition {
+    pub scanline: u16,
+    pub dot: u16,
+    pub frame: u64,
+}
+
+impl PpuPosition {
+    // Mesen numbers the pre-render line -1
+    fn signed_scanline(&self) -> i32 {
+        if self.scanline == 261 {
+            -1
+        } else {
+            self.scanline as i32
+        }
+    }
+}
+
+// The layouts used by other emulators' trace loggers
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TraceFormat {
+    // C000  4C F5 C5  JMP $C5F5        A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7
+    Nestest,
+    // C000  $4C $F5 $C5    JMP $C5F5   A:00 X:00 Y:00 P:24 SP:FD CYC:21  SL:0   FC:0 CPU Cycle:7
+    Mesen,
+    // f0      c7          A:00 X:00 Y:00 S:FD P:nvUbdIzc  $C000:4C F5 C5  JMP $C5F5
+    Fceux,
+}
+
+// Nintendulator and FCEUX don't read the PPU and APU registers when they log,
+// as reading them has side effects on a real NES, and show them as FF
+fn logged_value<T: Memory>(cpu: &Processor<T>, format: TraceFormat, addr: u16) -> u8 {
+    match (format, addr) {
+        (TraceFormat::Nestest | TraceFormat::Fceux, 0x2000..=0x401f) => 0xff,
+        _ => cpu.memory.read(addr),
+    }
+}
+
+// The instruction at PC with the address and value its operand refers to,
+// from before the instruction runs
+pub(crate) fn disassembly<T: Memory>(cpu: &Processor<T>, format: TraceFormat) -> String {
+    let upcoming = Upcoming::new(cpu);
+    let step = &upcoming.step;
+    let mnemonic = match (format, step.mnemonic) {
+        // nestest.log uses a different name for one of the unofficial opcodes
+        (TraceFormat::Nestest, "ISC") => "ISB",
+        (_, mnemonic) => mnemonic,
+    };
+    let operand = step.operand();
+    if operand.is_empty() {
+        return mnemonic.to_string();
+    }
+
+    let value = |addr| logged_value(cpu, format, addr);
+    let mode = step.mode;
+    let direct = matches!(mode, AddressingMode::ZeroPage | AddressingMode::Absolute);
+    let annotation = match (format, upcoming.pointer, upcoming.effective_addr) {
+        (_, _, Some(_)) if upcoming.is_jump() || mode == AddressingMode::ZeroPageRelative => {
+            String::new()
+        }
+        (TraceFormat::Nestest, pointer, Some(addr)) => match (mode, pointer) {
+            (AddressingMode::ZeroPageX | AddressingMode::ZeroPageY, _) => {
+                format!(" @ {:02X} = {:02X}", addr, value(addr))
+            }
+            (AddressingMode::AbsoluteX | AddressingMode::AbsoluteY, _) => {
+                format!(" @ {:04X} = {:02X}", addr, value(addr))
+            }
+            (AddressingMode::IndexedIndirect, Some((location, _))) => {
+                format!(" @ {:02X} = {:04X} = {:02X}", location, addr, value(addr))
+            }
+            (_, Some((_, pointer))) => {
+                format!(" = {:04X} @ {:04X} = {:02X}", pointer, addr, value(addr))
+            }
+            _ => format!(" = {:02X}", value(addr)),
+        },
+        (TraceFormat::Nestest, Some((_, target)), None) => format!(" = {:04X}", target),
+        (TraceFormat::Mesen, _, Some(addr)) if direct => format!(" = ${:02X}", value(addr)),
+        (TraceFormat::Mesen, _, Some(addr)) => {
+            format!(" [${:04X}] = ${:02X}", addr, value(addr))
+        }
+        (TraceFormat::Mesen, Some((_, target)), None) => format!(" [${:04X}]", target),
+        (TraceFormat::Fceux, _, Some(addr)) if direct => format!(" = #${:02X}", value(addr)),
+        (TraceFormat::Fceux, _, Some(addr)) => {
+            format!(" @ ${:04X} = #${:02X}", addr, value(addr))
+        }
+        (TraceFormat::Fceux, Some((_, target)), None) => format!(" = ${:04X}", target),
+        _ => String::new(),
+    };
+    format!("{} {}{}", mnemonic, operand, annotation)
+}
+
+// Flags as letters, upper case when set. Bit 5 always reads as set and the B
+// flag only exists on the stack.
+fn flag_letters(flags: &Flags) -> String {
+    let byte = flags.get_byte();
+    "nvubdizc"
+        .chars()
+        .enumerate()
+        .map(|(i, letter)| {
+            if byte & (0x80 >> i) != 0 {
+                letter.to_ascii_uppercase()
+            } else {
+                letter
+            }
+        })
+        .collect()
+}
+
+// Writes a line for each instruction before it runs. Instructions can be
+// limited to PC ranges, and logging can be switched on and off by
+// conditions written like debugger expressions.
+pub struct TraceLogger<W: Write> {
+    output: W,
+    pub format: TraceFormat,
+    ranges: Vec<RangeInclusive<u16>>,
+    start: Option<Expression>,
+    stop: Option<Expression>,
+    active: bool,
+}
+
+impl<W: Write> TraceLogger<W> {
+    pub fn new(output: W, format: TraceFormat) -> Self {
+        Self {
+            output,
+            format,
+            ranges: Vec::new(),
+            start: None,
+            stop: None,
+            active: true,
+        }
+    }
+
+    // Once there is a range, only instructions inside one are logged
+    pub fn add_range(&mut self, range: RangeInclusive<u16>) {
+        self.ranges.push(range);
+    }
+
+    // Nothing is logged until the condition is true before an instruction
+    pub fn start_when(&mut self, condition: &str) -> Result<(), Error> {
+        self.start = Some(Expression::parse(condition)?);
+        self.active = false;
+        Ok(())
+    }
+
+    // Logging stops before the first instruction where the condition is
+    // true, until the start condition is true again
+    pub fn stop_when(&mut self, condition: &str) -> Result<(), Error> {
+        self.stop = Some(Expression::parse(condition)?);
+        Ok(())
+    }
+
+    pub fn into_inner(self) -> W {
+        self.output
+    }
+
+    // The line for the instruction at PC. Formats that show the PPU leave
+    // that part out when there isn't one.
+    pub fn line<T: Memory>(&self, cpu: &Processor<T>, ppu: Option<PpuPosition>) -> String {
+        let core = &cpu.core;
+        let upcoming = Upcoming::new(cpu);
+        let bytes = upcoming.step.opcode_bytes();
+        let hex = |prefix: &str| {
+            bytes
+                .iter()
+                .map(|byte| format!("{}{:02X}", prefix, byte))
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        let registers = format!("A:{:02X} X:{:02X} Y:{:02X}", core.a, core.x, core.y);
+        let disassembly = disassembly(cpu, self.format);
+
+        match self.format {
+            TraceFormat::Nestest => {
+                let marker = if upcoming.info.official { ' ' } else { '*' };
+                let ppu = ppu.map_or(String::new(), |ppu| {
+                    format!(" PPU:{:3},{:3}", ppu.scanline, ppu.dot)
+                });
+                format!(
+                    "{:04X}  {:<9}{}{:<32}{} P:{:02X} SP:{:02X}{} CYC:{}",
+                    core.pc,
+                    hex(""),
+                    marker,
+                    disassembly,
+                    registers,
+                    core.f.get_byte(),
+                    core.sp,
+                    ppu,
+                    cpu.cycles
+                )
+            }
+            TraceFormat::Mesen => {
+                let ppu = ppu.map_or(String::new(), |ppu| {
+                    format!(
+                        " CYC:{:<3} SL:{:<3} FC:{}",
+                        ppu.dot,
+                        ppu.signed_scanline(),
+                        ppu.frame
+                    )
+                });
+                format!(
+                    "{:04X}  {:<15}{:<27} {} P:{:02X} SP:{:02X}{} CPU Cycle:{}",
+                    core.pc,
+                    hex("$"),
+                    disassembly,
+                    registers,
+                    core.f.get_byte(),
+                    core.sp,
+                    ppu,
+                    cpu.cycles
+                )
+            }
+            TraceFormat::Fceux => {
+                let frame = ppu.map_or(String::new(), |ppu| format!("f{:<6} ", ppu.frame));
+                format!(
+                    "{}c{:<11} {} S:{:02X} P:{}  ${:04X}:{:<9} {}",
+                    frame,
+                    cpu.cycles,
+                    registers,
+                    core.sp,
+                    flag_letters(&core.f),
+                    core.pc,
+                    hex(""),
+                    disassembly
+                )
+            }
+        }
+    }
+
+    // Logs the instruction at PC if it passes the filters. Call this before
+    // each instruction runs.
+    pub fn log<T: Memory>(
+        &mut self,
+        cpu: &Processor<T>,
+        ppu: Option<PpuPosition>,
+    ) -> io::Result<()> {
+        if !self.active && self.start.as_ref().is_some_and(|start| start.is_true(cpu)) {
+            self.active = true;
+        }
+        if self.active && self.stop.as_ref().is_some_and(|stop| stop.is_true(cpu)) {
+            self.active = false;
+        }
+        if !self.active {
+            return Ok(());
+        }
+
+        let pc = cpu.core.pc;
+        if !self.ranges.is_empty() && !self.ranges.iter().any(|range| range.contains(&pc)) {
+            return Ok(());
+        }
+        writeln!(self.output, "{}", self.line(cpu, ppu))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::RandomAccessMemory;
+
+    fn processor(program: &[u8]) -> Processor<RandomAccessMemory> {
+        let mut cpu = Processor::with_memory(RandomAccessMemory::new(0x10000));
+        for (i, byte) in program.iter().enumerate() {
+            cpu.memory.write(0xc000 + i as u16, *byte);
+        }
+        cpu.core.pc = 0xc000;
+        cpu
+    }
+
+    #[test]
+    fn test_formats() {
+        let mut cpu = processor(&[0x4c, 0xf5, 0xc5]);
+        cpu.core.f.i = true;
+        cpu.cycles = 7;
+        let ppu = Some(PpuPosition {
+            scanline: 0,
+            dot: 21,
+            frame: 0,
+        });
+        let line = |format| TraceLogger::new(Vec::new(), format).line(&cpu, ppu);
+        assert_eq!(
+            line(TraceFormat::Nestest),
+            "C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7"
+        );
+        assert_eq!(
+            line(TraceFormat::Mesen),
+            "C000  $4C $F5 $C5    JMP $C5F5                   A:00 X:00 Y:00 P:24 SP:FD CYC:21  SL:0   FC:0 CPU Cycle:7"
+        );
+        assert_eq!(
+            line(TraceFormat::Fceux),
+            "f0      c7           A:00 X:00 Y:00 S:FD P:nvUbdIzc  $C000:4C F5 C5  JMP $C5F5"
+        );
+        assert!(TraceLogger::new(Vec::new(), TraceFormat::Nestest)
+            .line(&cpu, None)
+            .ends_with("SP:FD CYC:7"));
+
+        let cpu = processor(&[0x04, 0xa9]);
+        assert!(TraceLogger::new(Vec::new(), TraceFormat::Nestest)
+            .line(&cpu, None)
+            .starts_with("C000  04 A9    *NOP $A9 = 00  "));
+    }
+
+    #[test]
+    fn test_disassembly() {
+        let cases: [(&[u8], &str, &str, &str); 12] = [
+            (
+                &[0x86, 0x00],
+                "STX $00 = 11",
+                "STX $00 = $11",
+                "STX $00 = #$11",
+            ),
+            (
+                &[0xb5, 0xff],
+                "LDA $FF,X @ 01 = 05",
+                "LDA $FF,X [$0001] = $05",
+                "LDA $FF,X @ $0001 = #$05",
+            ),
+            (
+                &[0xad, 0x00, 0x03],
+                "LDA $0300 = 33",
+                "LDA $0300 = $33",
+                "LDA $0300 = #$33",
+            ),
+            (
+                &[0xbd, 0xff, 0x02],
+                "LDA $02FF,X @ 0301 = 44",
+                "LDA $02FF,X [$0301] = $44",
+                "LDA $02FF,X @ $0301 = #$44",
+            ),
+            (
+                &[0x6c, 0xff, 0x02],
+                "JMP ($02FF) = 5500",
+                "JMP ($02FF) [$5500]",
+                "JMP ($02FF) = $5500",
+            ),
+            (
+                &[0xa1, 0xff],
+                "LDA ($FF,X) @ 01 = 0005 = 00",
+                "LDA ($FF,X) [$0005] = $00",
+                "LDA ($FF,X) @ $0005 = #$00",
+            ),
+            (
+                &[0xb1, 0x00],
+                "LDA ($00),Y = 0511 @ 0513 = 00",
+                "LDA ($00),Y [$0513] = $00",
+                "LDA ($00),Y @ $0513 = #$00",
+            ),
+            (
+                &[0x8d, 0x02, 0x20],
+                "STA $2002 = FF",
+                "STA $2002 = $00",
+                "STA $2002 = #$FF",
+            ),
+            (&[0x4a], "LSR A", "LSR A", "LSR A"),
+            (&[0xe8], "INX", "INX", "INX"),
+            (&[0xd0, 0xfe], "BNE $C000", "BNE $C000", "BNE $C000"),
+            (
+                &[0xe7, 0x00],
+                "ISB $00 = 11",
+                "ISC $00 = $11",
+                "ISC $00 = #$11",
+            ),
+        ];
+        for (program, nestest, mesen, fceux) in cases {
+            let mut cpu = processor(program);
+            cpu.core.x = 2;
+            cpu.core.y = 2;
+            cpu.memory.write(0x00, 0x11);
+            cpu.memory.write(0x01, 0x05);
+            cpu.memory.write(0x0300, 0x33);
+            cpu.memory.write(0x0301, 0x44);
+            cpu.memory.write(0x0200, 0x55);
+            assert_eq!(disassembly(&cpu, TraceFormat::Nestest), nestest);
+            assert_eq!(disassembly(&cpu, TraceFormat::Mesen), mesen);
+            assert_eq!(disassembly(&cpu, TraceFormat::Fceux), fceux);
+        }
+    }
+
+    #[test]
+    fn test_filters() {
+        // INX; JSR $C010; JMP $C000; ...; $C010: RTS
+        let mut cpu = processor(&[0xe8, 0x20, 0x10, 0xc0, 0x4c, 0x00, 0xc0]);
+        cpu.memory.write(0xc010, 0x60);
+        let mut logger = TraceLogger::new(Vec::new(), TraceFormat::Nestest);
+        logger.add_range(0xc010..=0xc0ff);
+        logger.add_range(0xc000..=0xc000);
+        logger.start_when("X == 2").unwrap();
+        logger.stop_when("X == 4").unwrap();
+        for _ in 0..20 {
+            logger.log(&cpu, None).unwrap();
+            cpu.step().unwrap();
+        }
+
+        let output = String::from_utf8(logger.into_inner()).unwrap();
+        let pcs: Vec<_> = output.lines().map(|line| &line[..4]).collect();
+        // X becomes 2 after the second INX and 4 after the fourth
+        assert_eq!(pcs, ["C010", "C000", "C010", "C000"]);
+    }
+}
@@ -0,0 +1,521 @@
+use std::fmt::Display;
+
+// The parts of a trace line that can be compared between emulators
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TraceField {
+    Pc,
+    Bytes,
+    A,
+    X,
+    Y,
+    P,
+    Sp,
+    Cycles,
+    Scanline,
+    Dot,
+    Frame,
+}
+
+impl TraceField {
+    const ALL: [TraceField; 11] = [
+        TraceField::Pc,
+        TraceField::Bytes,
+        TraceField::A,
+        TraceField::X,
+        TraceField::Y,
+        TraceField::P,
+        TraceField::Sp,
+        TraceField::Cycles,
+        TraceField::Scanline,
+        TraceField::Dot,
+        TraceField::Frame,
+    ];
+
+    // The CPU state, which two logs must agree on to be lined up
+    const CPU: [TraceField; 6] = [
+        TraceField::Pc,
+        TraceField::A,
+        TraceField::X,
+        TraceField::Y,
+        TraceField::P,
+        TraceField::Sp,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            TraceField::Pc => "pc",
+            TraceField::Bytes => "bytes",
+            TraceField::A => "a",
+            TraceField::X => "x",
+            TraceField::Y => "y",
+            TraceField::P => "p",
+            TraceField::Sp => "sp",
+            TraceField::Cycles => "cycles",
+            TraceField::Scanline => "scanline",
+            TraceField::Dot => "dot",
+            TraceField::Frame => "frame",
+        }
+    }
+}
+
+// One instruction from a nestest, Mesen or FCEUX style log, with whatever
+// fields that format has
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TraceRecord {
+    // Counting from 1
+    pub line_number: usize,
+    pub line: String,
+    pub pc: u16,
+    pub bytes: Vec<u8>,
+    pub a: Option<u8>,
+    pub x: Option<u8>,
+    pub y: Option<u8>,
+    pub p: Option<u8>,
+    pub sp: Option<u8>,
+    pub cycles: Option<u64>,
+    // The pre-render line is -1, whichever way the log numbers it
+    pub scanline: Option<i32>,
+    pub dot: Option<u16>,
+    pub frame: Option<u64>,
+}
+
+fn hex_byte(text: &str) -> Option<u8> {
+    match text.len() {
+        2 => u8::from_str_radix(text, 16).ok(),
+        _ => None,
+    }
+}
+
+fn hex_word(text: &str) -> Option<u16> {
+    match text.len() {
+        4 => u16::from_str_radix(text, 16).ok(),
+        _ => None,
+    }
+}
+
+// Either two hex digits or flag letters, upper case for set, like nvUbdIzc
+fn status(text: &str) -> Option<u8> {
+    if text.len() != 8 {
+        return hex_byte(text);
+    }
+    text.chars()
+        .zip("nvubdizc".chars())
+        .try_fold(0, |byte, (letter, flag)| {
+            match (letter == flag, letter == flag.to_ascii_uppercase()) {
+                (true, _) => Some(byte << 1),
+                (_, true) => Some(byte << 1 | 1),
+                _ => None,
+            }
+        })
+}
+
+impl TraceRecord {
+    // Lines without a PC and registers, such as headers and interrupt
+    // markers, aren't instructions and give None
+    pub fn parse(line_number: usize, line: &str) -> Option<Self> {
+        let mut record = TraceRecord {
+            line_number,
+            line: line.trim_end().to_string(),
+            ..Default::default()
+        };
+
+        // Make every field a single KEY:VALUE token
+        let mut text = line.replace("CPU Cycle:", "CPUCycle:");
+        if let Some(start) = text.find("PPU:") {
+            let end = text[start..]
+                .find("CYC:")
+                .map_or(text.len(), |end| start + end);
+            let ppu: String = text[start..end].split_whitespace().collect();
+            text = format!("{} {} {}", &text[..start], ppu, &text[end..]);
+        }
+
+        let mut pc = None;
+        let mut cyc = None;
+        let mut cpu_cycles = None;
+        let mut tokens = text.split_whitespace().peekable();
+        // FCEUX starts with the frame and cycle counts
+        if let Some(frame) = tokens.peek().and_then(|token| token.strip_prefix('f')) {
+            record.frame = Some(frame.parse().ok()?);
+            tokens.next();
+        }
+        if let Some(cycles) = tokens.peek().and_then(|token| token.strip_prefix('c')) {
+            cpu_cycles = Some(cycles.parse().ok()?);
+            tokens.next();
+        }
+
+        while let Some(token) = tokens.next() {
+            if pc.is_none() {
+                // nestest and Mesen start with the PC, FCEUX has $PC:bytes
+                // after the registers
+                let start = match token
+                    .strip_prefix('$')
+                    .and_then(|token| token.split_once(':'))
+                {
+                    Some((addr, byte)) => hex_word(addr).zip(hex_byte(byte).map(Some)),
+                    None => hex_word(token).map(|addr| (addr, None)),
+                };
+                if let Some((addr, byte)) = start {
+                    pc = Some(addr);
+                    record.bytes.extend(byte);
+                    while record.bytes.len() < 3 {
+                        let byte = tokens
+                            .peek()
+                            .and_then(|token| hex_byte(token.trim_start_matches('$')));
+                        let Some(byte) = byte else { break };
+                        record.bytes.push(byte);
+                        tokens.next();
+                    }
+                    continue;
+                }
+            }
+
+            let Some((key, value)) = token.split_once(':') else {
+                continue;
+            };
+            match key {
+                "A" => record.a = hex_byte(value),
+                "X" => record.x = hex_byte(value),
+                "Y" => record.y = hex_byte(value),
+                "P" => record.p = status(value),
+                "S" | "SP" => record.sp = hex_byte(value),
+                "PPU" => {
+                    let (scanline, dot) = value.split_once(',')?;
+                    record.scanline = scanline.parse().ok();
+                    record.dot = dot.parse().ok();
+                }
+                "SL" | "V" => record.scanline = value.parse().ok(),
+                "H" => record.dot = value.parse().ok(),
+                "FC" | "Fr" => record.frame = value.parse().ok(),
+                "CPUCycle" | "Cycle" => cpu_cycles = value.parse().ok(),
+                "CYC" => cyc = value.parse().ok(),
+                _ => {}
+            }
+        }
+
+        // CYC is the CPU cycle count in nestest.log, but the dot in Mesen's
+        // logs, which count CPU cycles separately
+        match cpu_cycles {
+            Some(cycles) => {
+                record.cycles = Some(cycles);
+                if record.dot.is_none() {
+                    record.dot = cyc.map(|dot| dot as u16);
+                }
+            }
+            None => record.cycles = cyc,
+        }
+        if record.scanline == Some(261) {
+            record.scanline = Some(-1);
+        }
+
+        record.pc = pc?;
+        record.a?;
+        Some(record)
+    }
+
+    // The field as text, or None if this log doesn't have it
+    pub fn field(&self, field: TraceField) -> Option<String> {
+        let byte = |value: Option<u8>| value.map(|value| format!("{:02X}", value));
+        let number = |value: Option<u64>| value.map(|value| value.to_string());
+        match field {
+            TraceField::Pc => Some(format!("{:04X}", self.pc)),
+            TraceField::Bytes => match self.bytes.is_empty() {
+                true => None,
+                false => Some(
+                    self.bytes
+                        .iter()
+                        .map(|byte| format!("{:02X}", byte))
+                        .collect::<Vec<_>>()
+                        .join(" "),
+                ),
+            },
+            TraceField::A => byte(self.a),
+            TraceField::X => byte(self.x),
+            TraceField::Y => byte(self.y),
+            TraceField::P => byte(self.p),
+            TraceField::Sp => byte(self.sp),
+            TraceField::Cycles => number(self.cycles),
+            TraceField::Scanline => self.scanline.map(|scanline| scanline.to_string()),
+            TraceField::Dot => number(self.dot.map(u64::from)),
+            TraceField::Frame => number(self.frame),
+        }
+    }
+}
+
+// Every instruction in a log, skipping the lines that aren't instructions
+pub fn parse_trace(log: &str) -> Vec<TraceRecord> {
+    log.lines()
+        .enumerate()
+        .filter_map(|(i, line)| TraceRecord::parse(i + 1, line))
+        .collect()
+}
+
+// Where two logs stop agreeing
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Divergence {
+    pub fields: Vec<TraceField>,
+    pub left: TraceRecord,
+    pub right: TraceRecord,
+    // The matching pairs just before
+    pub context: Vec<(TraceRecord, TraceRecord)>,
+}
+
+impl Display for Divergence {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let names: Vec<_> = self.fields.iter().map(TraceField::name).collect();
+        writeln!(
+            f,
+            "left line {} and right line {} differ in {}",
+            self.left.line_number,
+            self.right.line_number,
+            names.join(", ")
+        )?;
+        let width = self
+            .context
+            .iter()
+            .map(|(left, _)| left)
+            .chain([&self.left])
+            .map(|record| record.line.len())
+            .max()
+            .unwrap_or(0);
+        for (left, right) in &self.context {
+            writeln!(f, "  {:width$} | {}", left.line, right.line)?;
+        }
+        writeln!(f, "> {:width$} | {}", self.left.line, self.right.line)?;
+        for field in &self.fields {
+            writeln!(
+                f,
+                "  {}: {} vs {}",
+                field.name(),
+                self.left.field(*field).unwrap_or_default(),
+                self.right.field(*field).unwrap_or_default()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TraceComparison {
+    // How many instructions were compared, including the one that differed
+    pub compared: usize,
+    pub divergence: Option<Divergence>,
+}
+
+// Compares two logs instruction by instruction. Fields only one of the logs
+// has are skipped.
+#[derive(Clone, Debug)]
+pub struct TraceDiff {
+    pub ignore: Vec<TraceField>,
+    // P is compared after ANDing with this, to ignore bits such as bit 5,
+    // which some emulators always show set
+    pub p_mask: u8,
+    pub context: usize,
+    // How far into either log to look for where the other one starts
+    pub search: usize,
+}
+
+impl Default for TraceDiff {
+    fn default() -> Self {
+        Self {
+            ignore: Vec::new(),
+            p_mask: 0xff,
+            context: 5,
+            search: 1000,
+        }
+    }
+}
+
+impl TraceDiff {
+    // Takes a field name, "ppu" for the scanline and dot, or "p0" to "p7"
+    // for a bit of P. Gives false if the name isn't known.
+    pub fn ignore(&mut self, name: &str) -> bool {
+        if name == "ppu" {
+            self.ignore.extend([TraceField::Scanline, TraceField::Dot]);
+            return true;
+        }
+        if let Some(bit) = name
+            .strip_prefix('p')
+            .and_then(|bit| bit.parse::<u8>().ok())
+        {
+            if bit < 8 {
+                self.p_mask &= !(1 << bit);
+                return true;
+            }
+        }
+        match TraceField::ALL.iter().find(|field| field.name() == name) {
+            Some(field) => {
+                self.ignore.push(*field);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn differences(
+        &self,
+        left: &TraceRecord,
+        right: &TraceRecord,
+        fields: &[TraceField],
+    ) -> Vec<TraceField> {
+        let value = |record: &TraceRecord, field: TraceField| match field {
+            TraceField::P => record.p.map(|p| format!("{:02X}", p & self.p_mask)),
+            _ => record.field(field),
+        };
+        fields
+            .iter()
+            .filter(|field| !self.ignore.contains(field))
+            .filter(
+                |field| match (value(left, **field), value(right, **field)) {
+                    (Some(left), Some(right)) => left != right,
+                    _ => false,
+                },
+            )
+            .copied()
+            .collect()
+    }
+
+    // The first pair of instructions with the same CPU state, trying the
+    // start of each log against the first few of the other
+    fn align(&self, left: &[TraceRecord], right: &[TraceRecord]) -> (usize, usize) {
+        let matches = |i: usize, j: usize| match (left.get(i), right.get(j)) {
+            (Some(left), Some(right)) => self.differences(left, right, &TraceField::CPU).is_empty(),
+            _ => false,
+        };
+        (0..self.search)
+            .find_map(|k| match (matches(0, k), matches(k, 0)) {
+                (true, _) => Some((0, k)),
+                (_, true) => Some((k, 0)),
+                _ => None,
+            })
+            .unwrap_or((0, 0))
+    }
+
+    pub fn compare(&self, left: &str, right: &str) -> TraceComparison {
+        let left = parse_trace(left);
+        let right = parse_trace(right);
+        let (start_left, start_right) = self.align(&left, &right);
+
+        let mut context = Vec::new();
+        let pairs = left[start_left..].iter().zip(&right[start_right..]);
+        for (compared, (left, right)) in pairs.enumerate() {
+            let fields = self.differences(left, right, &TraceField::ALL);
+            if !fields.is_empty() {
+                return TraceComparison {
+                    compared: compared + 1,
+                    divergence: Some(Divergence {
+                        fields,
+                        left: left.clone(),
+                        right: right.clone(),
+                        context,
+                    }),
+                };
+            }
+            if context.len() == self.context {
+                context.remove(0);
+            }
+            if self.context > 0 {
+                context.push((left.clone(), right.clone()));
+            }
+        }
+        TraceComparison {
+            compared: (left.len() - start_left).min(right.len() - start_right),
+            divergence: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NESTEST: &str = "\
+C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7
+C5F5  A2 00     LDX #$00                        A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 30 CYC:10
+C5F7  86 00     STX $00 = 00                    A:00 X:00 Y:00 P:26 SP:FD PPU:  0, 36 CYC:12
+C5F9  86 10     STX $10 = 00                    A:00 X:00 Y:00 P:26 SP:FD PPU:261,340 CYC:15
+";
+
+    #[test]
+    fn test_parse() {
+        let records = parse_trace(NESTEST);
+        assert_eq!(records.len(), 4);
+        assert_eq!(records[1].pc, 0xc5f5);
+        assert_eq!(records[1].bytes, [0xa2, 0x00]);
+        assert_eq!(records[2].p, Some(0x26));
+        assert_eq!(
+            (records[3].cycles, records[3].scanline, records[3].dot),
+            (Some(15), Some(-1), Some(340))
+        );
+
+        let mesen = TraceRecord::parse(
+            1,
+            "C5F7  $86 $00          STX $00 = $00               A:00 X:00 Y:00 P:26 SP:FD CYC:36  SL:0   FC:2 CPU Cycle:12",
+        )
+        .unwrap();
+        assert_eq!(mesen.bytes, [0x86, 0x00]);
+        assert_eq!(
+            (mesen.cycles, mesen.scanline, mesen.dot, mesen.frame),
+            (Some(12), Some(0), Some(36), Some(2))
+        );
+
+        let fceux = TraceRecord::parse(
+            1,
+            "f2      c12          A:00 X:00 Y:00 S:FD P:nvUbdIZc  $C5F7:86 00     STX $0000 = #$00",
+        )
+        .unwrap();
+        assert_eq!(
+            (fceux.pc, fceux.p, fceux.sp),
+            (0xc5f7, Some(0x26), Some(0xfd))
+        );
+        assert_eq!(fceux.bytes, [0x86, 0x00]);
+        assert_eq!(
+            (fceux.cycles, fceux.frame, fceux.dot),
+            (Some(12), Some(2), None)
+        );
+        assert_eq!(
+            fceux.field(TraceField::Bytes),
+            mesen.field(TraceField::Bytes)
+        );
+
+        assert_eq!(TraceRecord::parse(1, "FCEUX log started"), None);
+        assert_eq!(TraceRecord::parse(1, ""), None);
+    }
+
+    #[test]
+    fn test_compare() {
+        // FCEUX shows the flags as letters and leaves out the PPU, and this
+        // log starts an instruction later
+        let fceux = "\
+Log Start
+f1      c10          A:00 X:00 Y:00 S:FD P:nvUbdIzc  $C5F5:A2 00     LDX #$00
+f1      c12          A:00 X:00 Y:00 S:FD P:nvUbdIZc  $C5F7:86 00     STX $0000 = #$00
+f1      c16          A:00 X:00 Y:00 S:FD P:nvubdIZc  $C5F9:86 10     STX $0010 = #$00
+";
+        let diff = TraceDiff::default();
+        let comparison = diff.compare(NESTEST, fceux);
+        assert_eq!(comparison.compared, 3);
+        let divergence = comparison.divergence.unwrap();
+        assert_eq!(divergence.fields, [TraceField::P, TraceField::Cycles]);
+        assert_eq!(divergence.left.line_number, 4);
+        assert_eq!(divergence.right.line_number, 4);
+        assert_eq!(divergence.context.len(), 2);
+
+        let report = divergence.to_string();
+        let lines: Vec<_> = report.lines().collect();
+        assert_eq!(lines[0], "left line 4 and right line 4 differ in p, cycles");
+        assert!(lines[3].starts_with("> C5F9  86 10"));
+        assert_eq!(lines[4], "  p: 26 vs 06");
+
+        let mut diff = TraceDiff::default();
+        assert!(diff.ignore("cycles"));
+        assert!(diff.ignore("p5"));
+        assert!(!diff.ignore("q"));
+        assert_eq!(
+            diff.compare(NESTEST, fceux),
+            TraceComparison {
+                compared: 3,
+                divergence: None
+            }
+        );
+    }
+}
@@ -0,0 +1,30 @@
+use crate::opcode::{OpcodeInfo, CMOS_OPCODES, OPCODES};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Variant {
+    // The NES CPU, which is an NMOS 6502 with the decimal mode circuitry
+    // disconnected
+    #[default]
+    Ricoh2A03,
+    Nmos6502,
+    // The WDC 65C02, including the Rockwell bit manipulation instructions
+    Cmos65C02,
+}
+
+impl Variant {
+    pub fn has_decimal_mode(&self) -> bool {
+        !matches!(self, Variant::Ricoh2A03)
+    }
+
+    pub fn is_cmos(&self) -> bool {
+        matches!(self, Variant::Cmos65C02)
+    }
+
+    pub fn opcodes(&self) -> &'static [OpcodeInfo; 256] {
+        if self.is_cmos() {
+            &CMOS_OPCODES
+        } else {
+            &OPCODES
+        }
+    }
+}